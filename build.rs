@@ -1,6 +1,9 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::configure()
-        .build_server(false)
+        // the server is only for the mock daemon in the tests
+        .build_server(true)
+        .server_mod_attribute(".", "#[cfg(test)]")
+        .generate_default_stubs(true)
         .compile(&["proto/management_interface.proto"], &["proto"])?;
     Ok(())
-}
\ No newline at end of file
+}
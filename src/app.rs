@@ -0,0 +1,1984 @@
+//! The tray state and the actions that can be taken from the tray
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use ksni::MenuItem;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, watch, Notify};
+use tokio_util::task::TaskTracker;
+use tracing::{debug, warn};
+
+use crate::capabilities::DaemonCapabilities;
+use crate::clipboard;
+use crate::commands::Command;
+use crate::config::{Config, IconTheme, SortLocations};
+use crate::favorites::{Favorite, Favorites};
+use crate::format::{
+    connection_details, endpoint_lines, error_details, exit_addresses, fill_template,
+    format_duration, format_time_remaining, geoip_place, state_label, state_summary,
+    unknown_placeholders,
+};
+use crate::icons::{self, BundledIcon};
+use crate::latency::{self, Latencies};
+use crate::last_location;
+use crate::launch::{self, Launchers};
+use crate::menu::{self, LocationsCache};
+use crate::model::{
+    AccessMethod, BridgeState, DeviceState, DnsBlocker, ErrorCause, ErrorState, GeographicLocation,
+    LocationConstraint, ObfuscationMode, Ownership, QuantumResistant, Relay, RelayInfo, RelayList,
+    RelaySettings, RelayType, Settings, TunnelState, TunnelType, VersionInfo,
+};
+use crate::names::{compare_names, CountryNames};
+use crate::notify::{Action, Notifier};
+use crate::prompt;
+use crate::proto;
+use crate::usage::{self, SessionUsage};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppState {
+    DaemonUnavailable,
+    Inactive,
+    /// The relay info is missing from some events, mostly early connecting ones
+    Connected(Option<RelayInfo>),
+    Connecting(Option<RelayInfo>),
+    Disconnecting,
+    Disconnected,
+    /// Disconnected with lockdown mode blocking all traffic
+    Blocked,
+    /// `None` if the daemon didn't say what went wrong or we can't make sense of it
+    Error(Option<ErrorState>),
+}
+
+impl From<TunnelState> for AppState {
+    fn from(tunnel_state: TunnelState) -> Self {
+        match tunnel_state {
+            TunnelState::Connecting(relay_info) => AppState::Connecting(relay_info),
+            TunnelState::Connected(relay_info) => AppState::Connected(relay_info),
+            TunnelState::Disconnecting => AppState::Disconnecting,
+            TunnelState::Disconnected { locked_down: true } => AppState::Blocked,
+            TunnelState::Disconnected { locked_down: false } => AppState::Disconnected,
+            TunnelState::Error(error_state) => AppState::Error(error_state),
+        }
+    }
+}
+
+impl AppState {
+    /// A short name for scripts, such as `connected` or `daemon-unavailable`
+    pub fn name(&self) -> &'static str {
+        match self {
+            AppState::DaemonUnavailable => "daemon-unavailable",
+            AppState::Inactive => "inactive",
+            AppState::Connected(_) => "connected",
+            AppState::Connecting(_) => "connecting",
+            AppState::Disconnecting => "disconnecting",
+            AppState::Disconnected => "disconnected",
+            AppState::Blocked => "blocked",
+            AppState::Error(_) => "error",
+        }
+    }
+
+    /// Whether the tunnel is up, or on its way up, in the state with this [`name`](Self::name)
+    pub fn tunnel_is_up(name: &str) -> bool {
+        name == AppState::Connected(None).name() || name == AppState::Connecting(None).name()
+    }
+
+    /// Whether `next` would show the same as this state. Later events of a connection can
+    /// leave out the relay info, which isn't a change since the tray keeps the earlier info.
+    pub fn shows_same_as(&self, next: &AppState) -> bool {
+        match (self, next) {
+            (AppState::Connected(Some(_)), AppState::Connected(None))
+            | (AppState::Connecting(Some(_)), AppState::Connecting(None)) => true,
+            _ => self == next,
+        }
+    }
+
+    /// The relay info if there is a tunnel and the daemon told us about it
+    pub fn relay_info(&self) -> Option<&RelayInfo> {
+        match self {
+            AppState::Connected(relay_info) | AppState::Connecting(relay_info) => {
+                relay_info.as_ref()
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether traffic is blocked on purpose rather than because something broke: lockdown
+    /// mode while disconnected, or the daemon keeping traffic blocked while the device is
+    /// offline
+    pub fn is_deliberately_blocked(&self) -> bool {
+        match self {
+            AppState::Blocked => true,
+            AppState::Error(Some(ErrorState { cause, blocking_error })) => {
+                blocking_error.is_none() && *cause == ErrorCause::IsOffline
+            }
+            _ => false,
+        }
+    }
+
+    /// Summary and body of the desktop notification for entering this state, transient
+    /// states don't get one
+    pub fn notification(&self) -> Option<(String, String)> {
+        match self {
+            AppState::Connected(relay_info) => {
+                let summary = match relay_info.as_ref().and_then(RelayInfo::hostname) {
+                    Some(hostname) => format!("Connected to {}", hostname),
+                    None => "Connected".into(),
+                };
+                Some((summary, String::new()))
+            }
+            AppState::Disconnected => Some(("Disconnected".into(), String::new())),
+            AppState::Blocked => {
+                Some(("Disconnected".into(), "Lockdown mode is blocking all traffic".into()))
+            }
+            AppState::Error(error_state) => {
+                let body = error_state
+                    .as_ref()
+                    .map(|error_state| error_details(error_state).join("\n"))
+                    .unwrap_or_default();
+                Some(("Mullvad VPN error".into(), body))
+            }
+            AppState::DaemonUnavailable
+            | AppState::Inactive
+            | AppState::Connecting(_)
+            | AppState::Disconnecting => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum AccountExpiry {
+    Unknown,
+    NoAccount,
+    At(SystemTime),
+}
+
+impl AccountExpiry {
+    pub fn time_remaining(&self) -> Option<Duration> {
+        match self {
+            // an expired account has no time remaining
+            AccountExpiry::At(expiry) => Some(
+                expiry.duration_since(SystemTime::now()).unwrap_or_default(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Connecting fails with an expired account, and the daemon keeps retrying
+    pub fn is_expired(&self) -> bool {
+        self.time_remaining() == Some(Duration::ZERO)
+    }
+
+    pub fn label(&self) -> String {
+        match self.time_remaining() {
+            Some(Duration::ZERO) => "Account has expired".into(),
+            Some(remaining) => format!("Account expires in {}", format_time_remaining(remaining)),
+            None => match self {
+                AccountExpiry::NoAccount => "No account configured".into(),
+                _ => "Account expiry unknown".into(),
+            },
+        }
+    }
+}
+
+/// Multihop connections go through an entry relay before the exit relay, and OpenVPN
+/// connections can go through a bridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocationKind {
+    Exit,
+    Entry,
+    Bridge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocationsStatus {
+    Loading,
+    Loaded,
+    /// Fetching the relay list failed, the menu offers to try again
+    Failed,
+}
+
+#[derive(Debug)]
+pub struct MulltrayApp {
+    pub locations: RelayList,
+    pub locations_status: LocationsStatus,
+    /// What the [`CommandRunner`](crate::commands::CommandRunner) last showed, changes go
+    /// through it with [`Command`]s
+    pub settings: Settings,
+    pub app_state: AppState,
+    pub tokio_handle: tokio::runtime::Handle,
+    pub quit: Arc<Notify>,
+    pub notifier: Notifier,
+    pub notifications_enabled: bool,
+    pub tray_updates: mpsc::UnboundedSender<TrayUpdate>,
+    pub commands: mpsc::UnboundedSender<Command>,
+    pub tasks: TaskTracker,
+    pub config: Config,
+    /// What the daemon can do, nothing until it has been reached
+    pub capabilities: DaemonCapabilities,
+    /// Whether the daemon has split tunneling, going by its settings until a call says not
+    pub split_tunnel_supported: bool,
+    /// The method the daemon reaches the Mullvad API with, `None` until it's known
+    pub api_access_method: Option<AccessMethod>,
+    pub launchers: Launchers,
+    /// Empty unless country names are translated
+    pub country_names: CountryNames,
+    pub show_inactive_relays: bool,
+    pub favorites: Favorites,
+    /// The last exit location chosen in mulltray, `None` if letting the daemon pick was
+    /// chosen last
+    pub last_location: Option<Favorite>,
+    pub account_expiry: AccountExpiry,
+    /// `None` until it has been fetched from the daemon
+    pub device_state: Option<DeviceState>,
+    /// When the WireGuard key of this device was created, `None` if not logged in
+    pub wireguard_key_created: Option<SystemTime>,
+    pub measure_latency: bool,
+    pub latencies: Latencies,
+    /// For [`latency::measure_latencies`], empty while measuring is turned off
+    pub relays_to_measure: watch::Sender<Vec<(String, Ipv4Addr)>>,
+    /// Shown in the title for a moment after connecting or disconnecting fails
+    pub command_failure: Option<String>,
+    /// When each relay was last connected to, by hostname
+    pub last_used: HashMap<String, Instant>,
+    /// Bumped whenever `locations` changes
+    pub locations_version: u64,
+    /// Bumped whenever `latencies` or `last_used` change
+    pub metrics_version: u64,
+    pub locations_cache: LocationsCache,
+    /// `None` until the daemon has checked for updates
+    pub version_info: Option<VersionInfo>,
+    /// When the tunnel last went up, `None` while not connected. Brief reconnects such as
+    /// the one after rotating the WireGuard key don't reset it.
+    pub connected_since: Option<Instant>,
+    /// Whether the tray has asked the daemon to disconnect since the tunnel was last up
+    pub disconnect_requested: bool,
+    /// Whether the tunnel last went down without the tray asking for it
+    pub disconnected_unexpectedly: bool,
+    /// For [`tick_while_connected`]
+    pub connection: watch::Sender<Connection>,
+    /// The name of the current state for the D-Bus interface
+    pub state_names: watch::Sender<&'static str>,
+    /// Scrolling on the icon that hasn't been acted on yet, and when it last happened
+    pub scrolled: Option<(i32, Instant)>,
+    /// `None` until the tunnel interface has been read after connecting
+    pub session_usage: Option<SessionUsage>,
+    /// Whether "Connect to fastest" is measuring latencies, shown in the title
+    pub measuring_fastest: bool,
+    /// Whether to connect once the daemon has been reached, cleared after the first time
+    pub connect_on_start: bool,
+    /// `None` until it has been fetched from the daemon
+    pub daemon_version: Option<String>,
+    /// The management socket of the daemon that is in use
+    pub socket_path: PathBuf,
+    /// The latest error state or failed command, for the diagnostics
+    pub last_error: Option<String>,
+}
+
+/// A relay that "Connect to fastest" can pick
+#[derive(Debug, Clone)]
+struct Candidate {
+    country: String,
+    city: String,
+    hostname: String,
+    addr: Ipv4Addr,
+}
+
+/// Buttons on the notification for an error
+const ERROR_ACTIONS: &[Action] = &[("reconnect", "Reconnect"), ("disconnect", "Disconnect")];
+/// Button on the notification for a disconnect that wasn't asked for from the tray
+const DISCONNECTED_ACTIONS: &[Action] = &[("connect", "Reconnect")];
+/// How long "Connect to fastest" measures before giving up
+const FASTEST_TIMEOUT: Duration = Duration::from_secs(5);
+// how far to scroll on the icon to connect or disconnect, some hosts send 1 for each step of
+// the mouse wheel and others 120, while touchpads send lots of small steps
+const SCROLL_THRESHOLD: i32 = 2;
+// scrolling that is spread out over a longer time than this doesn't add up
+const SCROLL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Whether connecting or disconnecting from the icon would change anything. Nothing is done
+/// while the tunnel is on its way up or down so that quick clicks and scrolls don't queue up
+/// contradictory commands.
+fn quick_connect_changes(app_state: &AppState, connect: bool) -> bool {
+    !matches!(
+        (app_state, connect),
+        (AppState::Connecting(_) | AppState::Disconnecting, _)
+            | (AppState::Connected(_), true)
+            | (AppState::Disconnected | AppState::Blocked, false)
+    )
+}
+
+/// Lets tasks spawned from tray callbacks modify the tray once they're done
+pub type TrayUpdate = Box<dyn FnOnce(&mut MulltrayApp) + Send>;
+
+impl MulltrayApp {
+    /// The exit or entry location constraint, `None` if the relay settings aren't known
+    pub fn location_constraint(
+        &self,
+        kind: LocationKind,
+    ) -> Option<&Option<LocationConstraint>> {
+        match kind {
+            LocationKind::Exit => Some(&self.relay_settings()?.location),
+            LocationKind::Entry => Some(&self.relay_settings()?.entry_location),
+            LocationKind::Bridge => Some(&self.settings.bridge_location),
+        }
+    }
+
+    /// `None` unless the relay settings are the Normal kind that mulltray supports
+    pub fn relay_settings(&self) -> Option<&RelaySettings> {
+        self.settings.relay_settings.as_ref()
+    }
+
+    pub fn set_locations(&mut self, mut locations: RelayList) {
+        self.locations_status = LocationsStatus::Loaded;
+        // an empty relay list is a hiccup on the daemon side, stale relays are more useful than none
+        if locations.countries.is_empty() {
+            return;
+        }
+        for country in &mut locations.countries {
+            if let Some(name) = self.country_names.get(&country.code) {
+                country.name = name.to_string();
+            }
+            country.cities.sort_by(|a, b| compare_names(&a.name, &b.name));
+        }
+        locations.countries.sort_by(|a, b| compare_names(&a.name, &b.name));
+        self.locations = locations;
+        self.locations_version += 1;
+        self.publish_relays_to_measure();
+        self.restore_location_if_reset();
+    }
+
+    /// Fetches the relay list in the background
+    pub fn load_locations(&mut self) {
+        // the minimal menu has no use for the relays
+        if self.config.minimal_menu {
+            return;
+        }
+        self.locations_status = LocationsStatus::Loading;
+        self.send(Command::LoadLocations);
+    }
+
+    /// Runs a task in the background, it gets a moment to finish when quitting
+    pub fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        self.tasks.spawn_on(task, &self.tokio_handle);
+    }
+
+    /// Runs a task that waits for the user to answer a dialog. Quitting doesn't wait for it
+    /// like it does for [`Self::spawn`], the dialog is closed along with the task instead.
+    fn spawn_dialog(&self, task: impl Future<Output = ()> + Send + 'static) {
+        self.tokio_handle.spawn(task);
+    }
+
+    /// Has the [`CommandRunner`](crate::commands::CommandRunner) do something in the daemon
+    fn send(&self, command: Command) {
+        let _ = self.commands.send(command);
+    }
+
+    pub fn connect(&self) {
+        self.send(Command::Connect);
+    }
+
+    pub fn disconnect(&mut self) {
+        self.disconnect_requested = true;
+        self.send(Command::Disconnect);
+    }
+
+    pub fn reconnect(&self) {
+        self.send(Command::Reconnect);
+    }
+
+    /// Whether toggling the connection disconnects. An error also counts as connected since
+    /// the daemon is trying to connect.
+    pub fn toggle_disconnects(&self) -> bool {
+        matches!(self.app_state, AppState::Connected(_) | AppState::Error(_))
+    }
+
+    pub fn toggle_connection(&mut self) {
+        self.quick_connect(!self.toggle_disconnects());
+    }
+
+    /// Connects or disconnects from the icon, if `quick_connect_changes` anything
+    fn quick_connect(&mut self, connect: bool) {
+        match (quick_connect_changes(&self.app_state, connect), connect) {
+            (false, _) => {}
+            (true, true) => self.connect(),
+            (true, false) => self.disconnect(),
+        }
+    }
+
+    /// Connects if the tunnel is down the first time the daemon is reached. Later
+    /// reconnects to the daemon leave the tunnel alone, the user may have disconnected since.
+    pub fn connect_on_start(&mut self) {
+        if !std::mem::take(&mut self.connect_on_start) {
+            return;
+        }
+        // the daemon doesn't say whether it was disconnected on purpose before we started
+        if matches!(self.app_state, AppState::Disconnected | AppState::Blocked) {
+            debug!("Connecting on start");
+            self.connect();
+        }
+    }
+
+    pub fn set_app_state(&mut self, app_state: AppState) {
+        // later events of a connection can come without the relay info earlier ones had
+        let app_state = match (app_state, &self.app_state) {
+            (
+                AppState::Connected(None),
+                AppState::Connecting(Some(relay_info)) | AppState::Connected(Some(relay_info)),
+            ) => AppState::Connected(Some(relay_info.clone())),
+            (AppState::Connecting(None), AppState::Connecting(Some(relay_info))) => {
+                AppState::Connecting(Some(relay_info.clone()))
+            }
+            (app_state, _) => app_state,
+        };
+        match &app_state {
+            AppState::Connected(relay_info) => {
+                if let Some(hostname) = relay_info.as_ref().and_then(RelayInfo::hostname) {
+                    self.last_used.insert(hostname.clone(), Instant::now());
+                    self.metrics_version += 1;
+                }
+                if self.connected_since.is_none() {
+                    self.connected_since = Some(Instant::now());
+                    self.session_usage = None;
+                }
+                self.disconnect_requested = false;
+                self.disconnected_unexpectedly = false;
+            }
+            AppState::Connecting(_) => {}
+            AppState::Error(_) if !app_state.is_deliberately_blocked() => {
+                self.last_error = Some(state_summary(&app_state));
+                self.connected_since = None;
+            }
+            _ => {
+                if self.connected_since.take().is_some() {
+                    // the daemon doesn't say who asked, only that the tray didn't
+                    self.disconnected_unexpectedly = !self.disconnect_requested;
+                }
+            }
+        }
+        debug!(?app_state, "Tray state changed");
+        let name = app_state.name();
+        self.state_names.send_if_modified(|current| std::mem::replace(current, name) != name);
+        self.app_state = app_state;
+        self.publish_connection();
+    }
+
+    fn publish_connection(&self) {
+        let connection = match self.app_state {
+            AppState::Connected(_) => Connection::Up { usage_interface: self.usage_interface() },
+            _ => Connection::Down,
+        };
+        self.connection.send_if_modified(|current| {
+            std::mem::replace(current, connection.clone()) != connection
+        });
+    }
+
+    fn publish_relays_to_measure(&self) {
+        let relays = latency::relays_to_measure(self);
+        self.relays_to_measure.send_if_modified(|current| {
+            std::mem::replace(current, relays.clone()) != relays
+        });
+    }
+
+    /// The tunnel interface to read the data usage from, `None` if usage isn't shown
+    fn usage_interface(&self) -> Option<String> {
+        let AppState::Connected(Some(relay_info)) = &self.app_state else {
+            return None;
+        };
+        let endpoint = relay_info.endpoint.as_ref().filter(|_| self.config.show_usage)?;
+        endpoint.tunnel_interface.clone()
+    }
+
+    /// Connecting fails without a device so this is also true while the device is unknown
+    pub fn is_logged_in(&self) -> bool {
+        self.device_state
+            .as_ref()
+            .is_none_or(|device_state| matches!(device_state, DeviceState::LoggedIn(_)))
+    }
+
+    pub fn set_device_state(&mut self, device_state: DeviceState) {
+        if !matches!(device_state, DeviceState::LoggedIn(_)) {
+            self.account_expiry = AccountExpiry::NoAccount;
+        }
+        let was_revoked = self.is_revoked();
+        self.device_state = Some(device_state);
+        if !was_revoked && self.is_revoked() {
+            self.notify_revoked();
+        }
+    }
+
+    fn is_revoked(&self) -> bool {
+        matches!(self.device_state, Some(DeviceState::Revoked))
+    }
+
+    fn notify_revoked(&self) {
+        self.notify(
+            "Device removed".into(),
+            "This device was removed from the Mullvad account, log in again to connect".into(),
+        );
+    }
+
+    /// What to tell the user about updates, `None` if the installed version is fine
+    pub fn update_label(&self) -> Option<String> {
+        let version_info = self.version_info.as_ref()?;
+        match &version_info.suggested_upgrade {
+            Some(version) => Some(format!("Mullvad {version} available")),
+            None if !version_info.supported => Some("Mullvad version no longer supported".into()),
+            None => None,
+        }
+    }
+
+    /// Notifies once whenever a different update becomes available
+    pub fn set_version_info(&mut self, version_info: VersionInfo) {
+        let old_label = self.update_label();
+        self.version_info = Some(version_info);
+        if let Some(label) = self.update_label().filter(|label| Some(label) != old_label.as_ref()) {
+            if self.config.update_notifications {
+                self.notify(label, "Update the Mullvad app to keep using it safely".into());
+            }
+        }
+    }
+
+    /// Marks the device as revoked if it was removed from the account from another device,
+    /// `device_ids` being the devices the account has left
+    pub fn device_removed(&mut self, account_number: &str, device_ids: &[String]) {
+        let Some(DeviceState::LoggedIn(Some(account_and_device))) = &self.device_state else {
+            return;
+        };
+        if account_and_device.account_number == account_number
+            && !device_ids.contains(&account_and_device.device_id)
+        {
+            self.device_state = Some(DeviceState::Revoked);
+            self.notify_revoked();
+        }
+    }
+
+    pub fn log_in(&self) {
+        let commands = self.commands.clone();
+        let notifier = self.notifier.clone();
+        let account_number_from_file = match &self.config.account_number_file {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(contents) => Some(contents),
+                Err(e) => {
+                    warn!("Could not read {}: {}", path.display(), e);
+                    notifier.notify("Could not log in".into(), e.to_string());
+                    return;
+                }
+            },
+            None => None,
+        };
+        self.spawn_dialog(async move {
+            let account_number = match account_number_from_file {
+                Some(account_number) => Some(account_number),
+                None => {
+                    match prompt::ask("Log in to Mullvad", "Account number:").await {
+                        Ok(account_number) => account_number,
+                        Err(e) => {
+                            warn!("Could not ask for the account number: {}", e);
+                            notifier.notify("Could not log in".into(), e.to_string());
+                            return;
+                        }
+                    }
+                }
+            };
+            // cancelled
+            let Some(account_number) = account_number else {
+                return;
+            };
+            let account_number: String = account_number.split_whitespace().collect();
+            let _ = commands.send(Command::LogIn(account_number));
+        });
+    }
+
+    /// Lets the user pick an exit location by typing, from the locations the menu lists
+    pub fn search_location(&self) {
+        let mut choices = vec![];
+        for country in menu::cached_locations(self, LocationKind::Exit).iter() {
+            let favorite = |city: Option<&str>, hostname: Option<&str>| Favorite {
+                country: country.code.clone(),
+                city: city.map(Into::into),
+                hostname: hostname.map(Into::into),
+            };
+            choices.push((country.name.clone(), favorite(None, None)));
+            for city in &country.cities {
+                let line = format!("{} {}", country.name, city.name);
+                choices.push((line.clone(), favorite(Some(&city.code), None)));
+                for relay in &city.relays {
+                    let location = favorite(Some(&city.code), Some(&relay.hostname));
+                    choices.push((format!("{} {}", line, relay.hostname), location));
+                }
+            }
+        }
+        let command = self.config.chooser.clone();
+        let notifier = self.notifier.clone();
+        let tray_updates = self.tray_updates.clone();
+        self.spawn_dialog(async move {
+            let lines: Vec<String> = choices.iter().map(|(line, _)| line.clone()).collect();
+            let chosen = match prompt::choose(command.as_deref(), &lines).await {
+                Ok(Some(chosen)) => chosen,
+                // cancelled
+                Ok(None) => return,
+                Err(e) => {
+                    warn!("Could not search for a location: {}", e);
+                    notifier.notify("Could not search for a location".into(), e.to_string());
+                    return;
+                }
+            };
+            // some choosers let the user type something that isn't one of the choices
+            let Some((_, location)) = choices.into_iter().find(|(line, _)| *line == chosen) else {
+                debug!("{:?} is not a location", chosen);
+                return;
+            };
+            let _ = tray_updates.send(Box::new(move |tray: &mut MulltrayApp| {
+                let Favorite { country, city, hostname } = location;
+                tray.set_location(LocationKind::Exit, country, city, hostname);
+            }));
+        });
+    }
+
+    /// Asks for a voucher code, or takes it from the clipboard if there's no way to ask
+    pub fn redeem_voucher(&self) {
+        let commands = self.commands.clone();
+        let notifier = self.notifier.clone();
+        self.spawn_dialog(async move {
+            let voucher = match prompt::ask("Redeem voucher", "Voucher code:").await {
+                Err(prompt::Error::NoDialogTool) => {
+                    match tokio::task::spawn_blocking(clipboard::paste).await {
+                        Ok(pasted) => pasted.map(Some).map_err(|e| e.to_string()),
+                        Err(_) => return,
+                    }
+                }
+                result => result.map_err(|e| e.to_string()),
+            };
+            let voucher = match voucher {
+                Ok(Some(voucher)) => voucher.trim().to_string(),
+                // cancelled
+                Ok(None) => return,
+                Err(e) => {
+                    warn!("Could not get the voucher code: {}", e);
+                    notifier.notify("Could not redeem voucher".into(), e);
+                    return;
+                }
+            };
+            let _ = commands.send(Command::RedeemVoucher(voucher));
+        });
+    }
+
+    pub fn log_out(&self) {
+        self.send(Command::LogOut);
+    }
+
+    /// Replaces the WireGuard key, the daemon reconnects with the new key if connected
+    pub fn rotate_wireguard_key(&self) {
+        self.send(Command::RotateWireguardKey);
+    }
+
+    pub fn copy_to_clipboard(&self, text: String) {
+        let notifier = self.notifier.clone();
+        self.tokio_handle.spawn_blocking(move || {
+            if let Err(e) = clipboard::copy(&text) {
+                warn!("Could not copy to clipboard: {}", e);
+                notifier.notify("Could not copy to clipboard".into(), e.to_string());
+            }
+        });
+    }
+
+    pub fn notify(&self, summary: String, body: String) {
+        if self.notifications_enabled {
+            self.notifier.notify(summary, body);
+        }
+    }
+
+    /// Notifies about the tunnel state. Errors get buttons that do the same as the
+    /// "Reconnect" and "Disconnect" menu items, and a tunnel that went down without being
+    /// disconnected from the tray gets one for connecting again.
+    pub fn notify_state(&self, summary: String, body: String) {
+        if !self.notifications_enabled {
+            return;
+        }
+        let actions = match self.app_state {
+            AppState::Error(_) => ERROR_ACTIONS,
+            AppState::Disconnected | AppState::Blocked if self.disconnected_unexpectedly => {
+                DISCONNECTED_ACTIONS
+            }
+            _ => {
+                self.notifier.notify(summary, body);
+                return;
+            }
+        };
+        let tray_updates = self.tray_updates.clone();
+        self.notifier.notify_with_actions(summary, body, actions, move |action| {
+            let _ = tray_updates.send(Box::new(move |tray: &mut MulltrayApp| match action {
+                "connect" => tray.connect(),
+                "reconnect" => tray.reconnect(),
+                _ => tray.disconnect(),
+            }));
+        });
+    }
+
+    pub fn set_sort_locations(&mut self, sort_locations: SortLocations) {
+        self.config.sort_locations = sort_locations;
+        if let Err(e) = Config::save_option("sort_locations", sort_locations.as_str()) {
+            warn!("Could not save the sort order: {}", e);
+            self.notifier.notify("Could not save the sort order".into(), e.to_string());
+        }
+    }
+
+    /// Reads the config file again and applies what can be changed while running. An invalid
+    /// file leaves the config as it was.
+    pub fn reload_config(&mut self) {
+        let config = match Config::load() {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("Could not reload the config: {}", e);
+                let body = format!("{e}\nThe previous configuration is still used");
+                self.notifier.notify("Could not reload the mulltray config".into(), body);
+                return;
+            }
+        };
+        check_title_format(&config);
+        if config.notifications != self.config.notifications {
+            self.notifications_enabled = config.notifications;
+        }
+        if config.measure_latency != self.config.measure_latency {
+            self.measure_latency = config.measure_latency;
+            self.publish_relays_to_measure();
+        }
+        // these are only used at startup
+        let mut needs_restart = vec![];
+        if config.socket_path != self.config.socket_path {
+            needs_restart.push("socket_path");
+        }
+        if config.translate_country_names != self.config.translate_country_names {
+            needs_restart.push("translate_country_names");
+        }
+        let load_locations = self.config.minimal_menu && !config.minimal_menu;
+        self.config = config;
+        debug!("Reloaded the config");
+        // show_usage may have changed
+        self.publish_connection();
+        if load_locations && self.locations_status != LocationsStatus::Loaded {
+            self.load_locations();
+        }
+        if !needs_restart.is_empty() {
+            self.notifier.notify(
+                "Restart mulltray to apply the config".into(),
+                format!("Changing {} needs a restart", needs_restart.join(" and ")),
+            );
+        }
+    }
+
+    pub fn toggle_apply_location_immediately(&mut self) {
+        let enabled = !self.config.apply_location_immediately;
+        self.config.apply_location_immediately = enabled;
+        if let Err(e) = Config::save_option("apply_location_immediately", enabled) {
+            warn!("Could not save the setting: {}", e);
+            self.notifier.notify("Could not save the setting".into(), e.to_string());
+        }
+    }
+
+    pub fn toggle_measure_latency(&mut self) {
+        self.measure_latency = !self.measure_latency;
+        self.publish_relays_to_measure();
+    }
+
+    pub fn open_mullvad_app(&self) {
+        if let Some((program, args)) = &self.launchers.mullvad_app {
+            self.launch("Could not open the Mullvad app", program, args);
+        }
+    }
+
+    pub fn open_account_page(&self) {
+        let failure = "Could not open the account page";
+        self.launch(failure, "xdg-open", &[launch::ACCOUNT_PAGE_URL]);
+    }
+
+    fn launch(&self, failure: &str, program: &str, args: &[&str]) {
+        if let Err(e) = launch::spawn_detached(program, args) {
+            warn!("{} with {}: {}", failure, program, e);
+            self.notifier.notify(failure.into(), e.to_string());
+        }
+    }
+
+    pub fn quit(&self) {
+        self.quit.notify_one();
+    }
+
+    pub fn set_location(
+        &mut self,
+        kind: LocationKind,
+        country: String,
+        city: Option<String>,
+        hostname: Option<String>,
+    ) {
+        if kind == LocationKind::Exit {
+            self.remember_location(Some(Favorite {
+                country: country.clone(),
+                city: city.clone(),
+                hostname: hostname.clone(),
+            }));
+        }
+        let location = GeographicLocation { country, city, hostname };
+        self.set_location_constraint(kind, Some(LocationConstraint::Location(location)));
+    }
+
+    /// Lets the daemon pick any relay
+    pub fn clear_location(&mut self, kind: LocationKind) {
+        if kind == LocationKind::Exit {
+            self.remember_location(None);
+        }
+        self.set_location_constraint(kind, None);
+    }
+
+    /// Lets the daemon pick any relay in one of the custom lists
+    pub fn set_custom_list(&mut self, kind: LocationKind, id: String) {
+        if !self.capabilities.custom_lists {
+            return;
+        }
+        self.set_location_constraint(kind, Some(LocationConstraint::CustomList(id)));
+    }
+
+    /// The selected exit location, or the relay we're connected to if any relay can be picked
+    pub fn current_location(&self) -> Option<GeographicLocation> {
+        match self.location_constraint(LocationKind::Exit)? {
+            Some(LocationConstraint::Location(geo)) => Some(geo.clone()),
+            Some(LocationConstraint::CustomList(_)) => None,
+            None => {
+                let Favorite { country, city, hostname } = self.current_relay()?;
+                Some(GeographicLocation { country, city, hostname })
+            }
+        }
+    }
+
+    pub fn add_current_location_to_custom_list(&mut self, id: String) {
+        if !self.capabilities.custom_lists {
+            return;
+        }
+        let Some(location) = self.current_location() else {
+            return;
+        };
+        self.send(Command::AddToCustomList { id, location });
+    }
+
+    /// Asks for the name of a new custom list and creates it
+    pub fn create_custom_list(&self) {
+        if !self.capabilities.custom_lists {
+            return;
+        }
+        let commands = self.commands.clone();
+        let notifier = self.notifier.clone();
+        self.spawn_dialog(async move {
+            let name = match prompt::ask("Create custom list", "Name of the new list:").await {
+                Ok(Some(name)) => name,
+                // cancelled
+                Ok(None) => return,
+                Err(e) => {
+                    warn!("Could not ask for the list name: {}", e);
+                    notifier.notify("Could not create custom list".into(), e.to_string());
+                    return;
+                }
+            };
+            let _ = commands.send(Command::CreateCustomList(name));
+        });
+    }
+
+    fn remember_location(&mut self, location: Option<Favorite>) {
+        if self.last_location != location {
+            last_location::save(location.as_ref());
+            self.last_location = location;
+        }
+    }
+
+    /// The remembered exit location, `None` if it's gone from the relay list or already set
+    pub fn restorable_location(&self) -> Option<&Favorite> {
+        let location = self.last_location.as_ref()?;
+        self.favorite_label(location)?;
+        let is_current = match self.location_constraint(LocationKind::Exit)? {
+            Some(LocationConstraint::Location(geo)) => {
+                geo.country == location.country
+                    && geo.city == location.city
+                    && geo.hostname == location.hostname
+            }
+            _ => false,
+        };
+        Some(location).filter(|_| !is_current)
+    }
+
+    pub fn restore_last_location(&mut self) {
+        if let Some(location) = self.restorable_location().cloned() {
+            let Favorite { country, city, hostname } = location;
+            self.set_location(LocationKind::Exit, country, city, hostname);
+        }
+    }
+
+    /// Sets the remembered exit location again if the daemon lets any relay be picked, as it
+    /// does after its settings have been reset. Only done if the config asks for it.
+    pub fn restore_location_if_reset(&mut self) {
+        let is_automatic = self.location_constraint(LocationKind::Exit) == Some(&None);
+        if !self.config.restore_location || !is_automatic {
+            return;
+        }
+        let Some(Favorite { country, city, hostname }) = self.restorable_location().cloned() else {
+            return;
+        };
+        debug!("The daemon has no exit location, restoring {}", country);
+        let location = GeographicLocation { country, city, hostname };
+        // the user didn't ask for anything so this doesn't connect
+        self.send(Command::SetLocation {
+            kind: LocationKind::Exit,
+            location: Some(LocationConstraint::Location(location)),
+            connect: false,
+        });
+    }
+
+    /// The active relays that can be used with the current settings
+    fn fastest_candidates(&self) -> Vec<Candidate> {
+        let mut candidates = vec![];
+        for country in &self.locations.countries {
+            for city in &country.cities {
+                let relays = city.relays.iter().filter(|relay| {
+                    relay.active && self.relay_is_listed(LocationKind::Exit, relay)
+                });
+                for relay in relays {
+                    let Some(addr) = relay.ipv4_addr_in else {
+                        continue;
+                    };
+                    candidates.push(Candidate {
+                        country: country.code.clone(),
+                        city: city.code.clone(),
+                        hostname: relay.hostname.clone(),
+                        addr,
+                    });
+                }
+            }
+        }
+        candidates
+    }
+
+    fn connect_to_candidate(&mut self, candidate: Candidate) {
+        let Candidate { country, city, hostname, .. } = candidate;
+        let city = Some(city);
+        self.connect_to_location(Some(Favorite { country, city, hostname: Some(hostname) }));
+    }
+
+    /// Sets the exit location and connects once the daemon has it, `None` letting the daemon
+    /// pick. This connects only once whether or not the location is applied immediately.
+    fn connect_to_location(&mut self, location: Option<Favorite>) {
+        self.remember_location(location.clone());
+        let location = location.map(|Favorite { country, city, hostname }| {
+            LocationConstraint::Location(GeographicLocation { country, city, hostname })
+        });
+        self.send(Command::SetLocation { kind: LocationKind::Exit, location, connect: true });
+    }
+
+    /// Connects to the relay with the lowest latency. Without recent measurements one relay
+    /// in each city is measured first, starting from the ones that used to be the fastest.
+    pub fn connect_to_fastest(&mut self) {
+        let candidates = self.fastest_candidates();
+        let fastest_measured = candidates
+            .iter()
+            .filter_map(|candidate| Some((self.latencies.get(&candidate.hostname)?, candidate)))
+            .min_by_key(|(latency, _)| *latency)
+            .map(|(_, candidate)| candidate.clone());
+        if let Some(candidate) = fastest_measured {
+            self.connect_to_candidate(candidate);
+            return;
+        }
+        let sample = fastest_sample(candidates, &self.latencies);
+        self.measuring_fastest = true;
+        let tray_updates = self.tray_updates.clone();
+        self.spawn(async move {
+            let relays = sample
+                .iter()
+                .map(|candidate| (candidate.hostname.clone(), candidate.addr))
+                .collect();
+            let results = latency::probe_all(relays, FASTEST_TIMEOUT).await;
+            let fastest = results
+                .iter()
+                .min_by_key(|(_, latency)| *latency)
+                .and_then(|(hostname, _)| sample.iter().find(|c| c.hostname == *hostname))
+                .cloned();
+            let _ = tray_updates.send(Box::new(move |tray: &mut MulltrayApp| {
+                tray.measuring_fastest = false;
+                for (hostname, latency) in results {
+                    tray.latencies.record(hostname, Some(latency));
+                }
+                tray.metrics_version += 1;
+                match fastest {
+                    Some(candidate) => tray.connect_to_candidate(candidate),
+                    None => {
+                        warn!("None of the relays answered, letting the daemon choose");
+                        tray.notifier.notify(
+                            "Could not find the fastest relay".into(),
+                            "Connecting to a relay chosen by the daemon".into(),
+                        );
+                        tray.connect_to_location(None);
+                    }
+                }
+            }));
+        });
+    }
+
+    /// Takes the settings from a daemon event. They go through the runner, which knows
+    /// whether they're up to date with the changes it's making.
+    pub fn receive_settings(&self, settings: proto::Settings) {
+        self.send(Command::DaemonSettings(Box::new(settings)));
+    }
+
+    /// Makes the daemon reach the Mullvad API with `method` from now on
+    pub fn set_api_access_method(&mut self, method: AccessMethod) {
+        if !self.capabilities.api_access_methods {
+            return;
+        }
+        let id = method.id.clone();
+        self.api_access_method = Some(method);
+        self.send(Command::SetApiAccessMethod(id));
+    }
+
+    pub fn set_block_when_disconnected(&self, enabled: bool) {
+        self.send(Command::SetBlockWhenDisconnected(enabled));
+    }
+
+    pub fn set_auto_connect(&self, enabled: bool) {
+        self.send(Command::SetAutoConnect(enabled));
+    }
+
+    pub fn set_allow_lan(&self, enabled: bool) {
+        self.send(Command::SetAllowLan(enabled));
+    }
+
+    pub fn set_enable_ipv6(&self, enabled: bool) {
+        self.send(Command::SetEnableIpv6(enabled));
+    }
+
+    pub fn set_split_tunnel_enabled(&self, enabled: bool) {
+        self.send(Command::SetSplitTunnelEnabled(enabled));
+    }
+
+    /// Asks for an application to exclude from the tunnel
+    pub fn add_split_tunnel_app(&self) {
+        let commands = self.commands.clone();
+        let notifier = self.notifier.clone();
+        self.spawn_dialog(async move {
+            let path = match prompt::choose_file("Exclude application from the VPN").await {
+                Ok(Some(path)) => path,
+                // cancelled
+                Ok(None) => return,
+                Err(e) => {
+                    warn!("Could not choose an application: {}", e);
+                    notifier.notify("Could not add application".into(), e.to_string());
+                    return;
+                }
+            };
+            let _ = commands.send(Command::AddSplitTunnelApp(path));
+        });
+    }
+
+    pub fn remove_split_tunnel_app(&self, path: String) {
+        self.send(Command::RemoveSplitTunnelApp(path));
+    }
+
+    pub fn set_dns_blocker(&self, blocker: DnsBlocker, enabled: bool) {
+        self.send(Command::SetDnsBlocker(blocker, enabled));
+    }
+
+    pub fn set_custom_dns_enabled(&self, enabled: bool) {
+        // used if there are no custom DNS servers yet
+        let servers = self.config.dns_servers
+            .first()
+            .map(|server| server.addresses.iter().map(|ip| ip.to_string()).collect())
+            .unwrap_or_default();
+        self.send(Command::SetCustomDnsEnabled { enabled, servers });
+    }
+
+    pub fn set_custom_dns_servers(&self, addresses: Vec<String>) {
+        self.send(Command::SetCustomDnsServers(addresses));
+    }
+
+    pub fn set_obfuscation(&self, mode: ObfuscationMode) {
+        if mode != ObfuscationMode::Shadowsocks || self.capabilities.shadowsocks_obfuscation {
+            self.send(Command::SetObfuscation(mode));
+        }
+    }
+
+    /// `None` lets the daemon pick the port
+    pub fn set_udp2tcp_port(&self, port: Option<u32>) {
+        self.send(Command::SetUdp2TcpPort(port));
+    }
+
+    pub fn set_quantum_resistant_tunnel(&self, state: QuantumResistant) {
+        self.send(Command::SetQuantumResistantTunnel(state));
+    }
+
+    pub fn set_daita(&self, enabled: bool) {
+        if self.capabilities.daita {
+            self.send(Command::SetDaita(enabled));
+        }
+    }
+
+    pub fn set_wireguard_mtu(&self, mtu: Option<u32>) {
+        self.send(Command::SetWireguardMtu(mtu));
+    }
+
+    pub fn set_bridge_state(&self, state: BridgeState) {
+        self.send(Command::SetBridgeState(state));
+    }
+
+    /// The relay we're connected to, as a favorite
+    pub fn current_relay(&self) -> Option<Favorite> {
+        let AppState::Connected(relay_info) = &self.app_state else {
+            return None;
+        };
+        let hostname = relay_info.as_ref()?.hostname()?;
+        for country in &self.locations.countries {
+            for city in &country.cities {
+                if city.relays.iter().any(|relay| relay.hostname == *hostname) {
+                    return Some(Favorite {
+                        country: country.code.clone(),
+                        city: Some(city.code.clone()),
+                        hostname: Some(hostname.clone()),
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    /// Human readable name of the favorite, `None` if it's not in the relay list anymore
+    pub fn favorite_label(&self, favorite: &Favorite) -> Option<String> {
+        let country = self.locations.countries.iter().find(|c| c.code == favorite.country)?;
+        let Some(city_code) = &favorite.city else {
+            return Some(country.name.clone());
+        };
+        let city = country.cities.iter().find(|c| c.code == *city_code)?;
+        match &favorite.hostname {
+            Some(hostname) => city.relays
+                .iter()
+                .find(|relay| relay.hostname == *hostname)
+                .map(|relay| format!("{} ({}, {})", relay.hostname, city.name, country.name)),
+            None => Some(format!("{}, {}", city.name, country.name)),
+        }
+    }
+
+    fn set_location_constraint(
+        &mut self,
+        kind: LocationKind,
+        location: Option<LocationConstraint>,
+    ) {
+        // with the tunnel up it reconnects anyway
+        let connect = self.config.apply_location_immediately && self.is_logged_in();
+        self.send(Command::SetLocation { kind, location, connect });
+    }
+
+    /// Whether the relay can be used with the tunnel protocol constraint. Entry relays are
+    /// only used for WireGuard multihop, and bridges have relays of their own.
+    pub fn relay_matches_tunnel_protocol(&self, kind: LocationKind, relay: &Relay) -> bool {
+        let tunnel_type = match kind {
+            LocationKind::Exit => self.relay_settings().and_then(|relay| relay.tunnel_type),
+            LocationKind::Entry => Some(TunnelType::WireGuard),
+            LocationKind::Bridge => return relay.relay_type == RelayType::Bridge,
+        };
+        relay_has_tunnel_type(relay, tunnel_type)
+    }
+
+    /// Whether the relay shows up in the location menu of `kind`. Countries and cities where
+    /// none do are left out of the menu altogether.
+    pub fn relay_is_listed(&self, kind: LocationKind, relay: &Relay) -> bool {
+        self.relay_matches_tunnel_protocol(kind, relay)
+            // the filters are part of the relay constraints, bridges have constraints of their own
+            && (kind == LocationKind::Bridge || self.relay_matches_filters(relay))
+            && (relay.active || self.show_inactive_relays)
+    }
+
+    /// Whether the relay satisfies the ownership and provider constraints
+    pub fn relay_matches_filters(&self, relay: &Relay) -> bool {
+        let Some(relay_settings) = self.relay_settings() else {
+            return true;
+        };
+        let ownership_matches = match relay_settings.ownership {
+            Ownership::Any => true,
+            Ownership::MullvadOwned => relay.owned,
+            Ownership::Rented => !relay.owned,
+        };
+        let providers = &relay_settings.providers;
+        ownership_matches && (providers.is_empty() || providers.contains(&relay.provider))
+    }
+
+    /// Every provider in the relay list, sorted by name
+    pub fn all_providers(&self) -> Vec<String> {
+        let mut providers: Vec<String> = self.locations.countries
+            .iter()
+            .flat_map(|country| &country.cities)
+            .flat_map(|city| &city.relays)
+            .map(|relay| relay.provider.clone())
+            .collect();
+        providers.sort_unstable();
+        providers.dedup();
+        providers
+    }
+
+    pub fn set_mullvad_owned_only(&self, enabled: bool) {
+        self.send(Command::SetMullvadOwnedOnly(enabled));
+    }
+
+    pub fn set_provider_enabled(&self, provider: String, enabled: bool) {
+        let all_providers = self.all_providers();
+        self.send(Command::SetProviderEnabled { provider, enabled, all_providers });
+    }
+
+    pub fn set_wireguard_port(&self, port: Option<u32>) {
+        self.send(Command::SetWireguardPort(port));
+    }
+
+    pub fn set_tunnel_protocol(&self, tunnel_type: Option<TunnelType>) {
+        self.send(Command::SetTunnelProtocol(tunnel_type));
+        if !self.exit_location_has_usable_relay(tunnel_type) {
+            self.notify(
+                "No relay matches the settings".into(),
+                "The selected location has no relays for this tunnel protocol".into(),
+            );
+        }
+    }
+
+    /// Whether any relay in the selected exit location works with `tunnel_type`, true if no
+    /// particular location is selected
+    fn exit_location_has_usable_relay(&self, tunnel_type: Option<TunnelType>) -> bool {
+        let Some(Some(LocationConstraint::Location(geo))) =
+            self.location_constraint(LocationKind::Exit)
+        else {
+            return true;
+        };
+        self.locations.countries
+            .iter()
+            .filter(|country| country.code == geo.country)
+            .flat_map(|country| &country.cities)
+            .filter(|city| geo.city.as_ref().is_none_or(|code| *code == city.code))
+            .flat_map(|city| &city.relays)
+            .filter(|relay| geo.hostname.as_ref().is_none_or(|host| *host == relay.hostname))
+            .any(|relay| relay_has_tunnel_type(relay, tunnel_type))
+    }
+
+    pub fn set_multihop(&self, enabled: bool) {
+        self.send(Command::SetMultihop(enabled));
+    }
+}
+
+/// Whether the relay can be used with the tunnel protocol, `None` meaning any of them
+fn relay_has_tunnel_type(relay: &Relay, tunnel_type: Option<TunnelType>) -> bool {
+    match tunnel_type {
+        Some(tunnel_type) => relay.tunnel_type() == Some(tunnel_type),
+        None => relay.tunnel_type().is_some(),
+    }
+}
+
+/// One relay in each city for "Connect to fastest" to measure. Cities that used to be the
+/// fastest come first so that they're measured before it runs out of time.
+fn fastest_sample(candidates: Vec<Candidate>, latencies: &Latencies) -> Vec<Candidate> {
+    // no latency sorts after all of them
+    let last_known = |candidate: &Candidate| {
+        let latency = latencies.last_known(&candidate.hostname);
+        (latency.is_none(), latency)
+    };
+    let mut sample: Vec<Candidate> = vec![];
+    for candidate in candidates {
+        let same_city = |other: &&mut Candidate| {
+            other.country == candidate.country && other.city == candidate.city
+        };
+        match sample.iter_mut().find(same_city) {
+            Some(other) if last_known(&candidate) < last_known(other) => *other = candidate,
+            Some(_) => {}
+            None => sample.push(candidate),
+        }
+    }
+    sample.sort_by_key(last_known);
+    sample
+}
+
+/// The placeholders that `title_format` in the config can use
+const TITLE_PLACEHOLDERS: [&str; 6] =
+    ["state", "hostname", "city", "country", "exit_ip", "duration"];
+
+/// Warns about placeholders in `title_format` that we don't know, they're left out of the title
+pub fn check_title_format(config: &Config) {
+    if let Some(template) = &config.title_format {
+        let unknown = unknown_placeholders(template, &TITLE_PLACEHOLDERS);
+        if !unknown.is_empty() {
+            warn!("Unknown placeholders in title_format: {}", unknown.join(", "));
+        }
+    }
+}
+
+fn fill_title_template(
+    template: &str,
+    app_state: &AppState,
+    state: &str,
+    duration: Option<String>,
+) -> String {
+    let relay_info = app_state.relay_info();
+    let location = relay_info.and_then(|relay_info| relay_info.location.as_ref());
+    fill_template(template, |name| match name {
+        "state" => Some(state.into()),
+        "hostname" => relay_info.and_then(RelayInfo::hostname).cloned(),
+        "city" => location.and_then(|location| location.city.clone()),
+        "country" => location.and_then(|location| location.country.clone()),
+        "exit_ip" => location.and_then(|location| {
+            location.ipv4.clone().or_else(|| location.ipv6.clone())
+        }),
+        "duration" => duration.clone(),
+        _ => None,
+    })
+}
+
+impl ksni::Tray for MulltrayApp {
+    fn activate(&mut self, _x: i32, _y: i32) {
+        // the user asked for this one so it's shown even if notifications are turned off
+        let body = self.app_state.relay_info().map(connection_details).unwrap_or_default();
+        self.notifier.notify(self.title(), body);
+    }
+    fn secondary_activate(&mut self, _x: i32, _y: i32) {
+        self.toggle_connection();
+    }
+    fn scroll(&mut self, delta: i32, dir: &str) {
+        if dir != "vertical" || delta == 0 {
+            return;
+        }
+        // scrolling the other way or after a while starts over
+        let scrolled = match self.scrolled {
+            Some((scrolled, at))
+                if at.elapsed() < SCROLL_TIMEOUT && scrolled.signum() == delta.signum() =>
+            {
+                scrolled.saturating_add(delta)
+            }
+            _ => delta,
+        };
+        if scrolled.abs() < SCROLL_THRESHOLD {
+            self.scrolled = Some((scrolled, Instant::now()));
+            return;
+        }
+        self.scrolled = None;
+        // positive is up
+        self.quick_connect((scrolled > 0) != self.config.invert_scroll);
+    }
+    fn title(&self) -> String {
+        if let Some(failure) = &self.command_failure {
+            return format!("mulltray - {failure}");
+        }
+        if self.measuring_fastest {
+            return "mulltray - measuring…".into();
+        }
+        let logged_out = match &self.app_state {
+            AppState::Disconnected | AppState::Blocked if self.is_revoked() => {
+                Some("logged out (device removed)")
+            }
+            AppState::Disconnected | AppState::Blocked if !self.is_logged_in() => {
+                Some("logged out")
+            }
+            _ => None,
+        };
+        let duration = self.connected_since
+            .filter(|_| matches!(self.app_state, AppState::Connected(_)))
+            .map(|since| format_duration(since.elapsed()));
+        if let Some(template) = &self.config.title_format {
+            let state = logged_out.unwrap_or_else(|| state_label(&self.app_state));
+            return fill_title_template(template, &self.app_state, state, duration);
+        }
+        let state = logged_out.map_or_else(|| state_summary(&self.app_state), String::from);
+        match duration {
+            Some(duration) => format!("mulltray - {state} ({duration})"),
+            None => format!("mulltray - {state}"),
+        }
+    }
+    fn tool_tip(&self) -> ksni::ToolTip {
+        let mut lines = vec![];
+        match &self.app_state {
+            AppState::Connected(_) | AppState::Connecting(_) => {
+                let relay_info = self.app_state.relay_info();
+                if let Some(location) = relay_info.and_then(|info| info.location.as_ref()) {
+                    lines.extend(geoip_place(location));
+                    lines.extend(exit_addresses(location));
+                }
+                if let Some(endpoint) = relay_info.and_then(|info| info.endpoint.as_ref()) {
+                    lines.extend(endpoint_lines(endpoint));
+                }
+            }
+            AppState::Disconnected => lines.push("Your traffic is not protected".into()),
+            AppState::Blocked => lines.push("All traffic is blocked until you connect".into()),
+            _ => {}
+        }
+        ksni::ToolTip {
+            title: self.title(),
+            description: lines.join("\n"),
+            ..Default::default()
+        }
+    }
+    fn icon_name(&self) -> String {
+        let icon = match self.app_state {
+            AppState::Disconnected | AppState::Blocked if !self.is_logged_in() => {
+                "network-vpn-offline"
+            }
+            _ if self.app_state.is_deliberately_blocked() => "network-vpn-no-route",
+            AppState::DaemonUnavailable => "network-vpn-offline",
+            AppState::Inactive => "network-vpn-offline",
+            AppState::Error(_) => "network-vpn-error",
+            AppState::Connecting(_) => "network-vpn-acquiring",
+            AppState::Disconnecting => "network-vpn-acquiring",
+            AppState::Disconnected | AppState::Blocked => "network-vpn-disconnected",
+            AppState::Connected(_) => "network-vpn",
+        };
+        match self.config.icon_theme {
+            IconTheme::Symbolic => format!("{icon}-symbolic"),
+            IconTheme::Color => icon.to_string(),
+            // the panel falls back to the pixmap when there's no icon name
+            IconTheme::Bundled => String::new(),
+        }
+    }
+    fn status(&self) -> ksni::Status {
+        let idle = matches!(self.app_state, AppState::Disconnected | AppState::Inactive);
+        if self.config.hide_when_disconnected && idle && self.command_failure.is_none() {
+            ksni::Status::Passive
+        } else {
+            ksni::Status::Active
+        }
+    }
+    fn overlay_icon_name(&self) -> String {
+        // a badge on top of the icon for hosts that can show one
+        match self.app_state {
+            AppState::Error(_) if !self.app_state.is_deliberately_blocked() => {
+                match self.config.icon_theme {
+                    IconTheme::Symbolic => "dialog-warning-symbolic".into(),
+                    IconTheme::Color | IconTheme::Bundled => "dialog-warning".into(),
+                }
+            }
+            _ => String::new(),
+        }
+    }
+    fn icon_pixmap(&self) -> Vec<ksni::Icon> {
+        // also used by panels whose icon theme doesn't have the named icon
+        let icon = match self.app_state {
+            AppState::Disconnected | AppState::Blocked if !self.is_logged_in() => {
+                BundledIcon::Disconnected
+            }
+            _ if self.app_state.is_deliberately_blocked() => BundledIcon::Blocked,
+            AppState::Connected(_) => BundledIcon::Connected,
+            AppState::Connecting(_) | AppState::Disconnecting => BundledIcon::Connecting,
+            AppState::Error(_) => BundledIcon::Error,
+            AppState::Disconnected
+            | AppState::Blocked
+            | AppState::Inactive
+            | AppState::DaemonUnavailable => BundledIcon::Disconnected,
+        };
+        icons::pixmap(icon)
+    }
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        menu::tray_menu(self)
+    }
+}
+
+/// Whether the tunnel is connected, as far as [`tick_while_connected`] needs to know
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Connection {
+    #[default]
+    Down,
+    /// With the tunnel interface to read the data usage from, `None` unless usage is shown
+    Up { usage_interface: Option<String> },
+}
+
+/// The title only gets redrawn on updates so the connection duration needs to be poked.
+/// The data usage is sampled at the same time.
+pub async fn tick_while_connected(
+    mut connection: watch::Receiver<Connection>,
+    tray_handle: &ksni::Handle<MulltrayApp>,
+) {
+    loop {
+        let Connection::Up { usage_interface } = connection.borrow_and_update().clone() else {
+            if connection.changed().await.is_err() {
+                return std::future::pending().await;
+            }
+            continue;
+        };
+        let counters = usage_interface.as_deref().and_then(usage::read_counters);
+        tray_handle.update(|tray: &mut MulltrayApp| {
+            if let Some(counters) = counters {
+                tray.session_usage.get_or_insert_with(Default::default).record(counters);
+            }
+        });
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(30)) => {}
+            Ok(()) = connection.changed() => {}
+        }
+    }
+}
+
+/// Reads the config file again whenever mulltray gets SIGHUP
+pub async fn reload_config_on_sighup(tray_handle: &ksni::Handle<MulltrayApp>) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!("Could not listen for SIGHUP, reload the config from the menu instead: {e}");
+            return std::future::pending().await;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        debug!("Received SIGHUP");
+        tray_handle.update(|tray: &mut MulltrayApp| tray.reload_config());
+    }
+}
+
+pub async fn apply_tray_updates(
+    mut tray_updates: mpsc::UnboundedReceiver<TrayUpdate>,
+    tray_handle: &ksni::Handle<MulltrayApp>,
+) {
+    while let Some(update) = tray_updates.recv().await {
+        tray_handle.update(update);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::app_state;
+    use crate::model::{Endpoint, ErrorCause, Location, TunnelState};
+    use crate::proto::tunnel_state::State;
+    use crate::testing::{
+        connected, find_item, is_visible, label, relay_list, submenu_labels, tunnel_state,
+        MockDaemon, TestTray,
+    };
+    use crate::usage::Counters;
+
+    fn error_state(cause: ErrorCause) -> ErrorState {
+        ErrorState { cause, blocking_error: None }
+    }
+
+    #[test]
+    fn every_tunnel_state_has_an_app_state() {
+        let cases = [
+            (TunnelState::Disconnected { locked_down: false }, AppState::Disconnected),
+            (TunnelState::Disconnected { locked_down: true }, AppState::Blocked),
+            (TunnelState::Connecting(None), AppState::Connecting(None)),
+            (TunnelState::Connected(None), AppState::Connected(None)),
+            (TunnelState::Disconnecting, AppState::Disconnecting),
+            (TunnelState::Error(None), AppState::Error(None)),
+            (
+                TunnelState::Error(Some(error_state(ErrorCause::SetDns))),
+                AppState::Error(Some(error_state(ErrorCause::SetDns))),
+            ),
+        ];
+        for (tunnel_state, expected) in cases {
+            assert_eq!(AppState::from(tunnel_state.clone()), expected, "{tunnel_state:?}");
+        }
+    }
+
+    #[test]
+    fn tunnel_states_from_the_daemon() {
+        let state = |state| app_state(tunnel_state(state));
+        assert_eq!(state(State::Disconnected(Default::default())), AppState::Disconnected);
+        assert_eq!(state(State::Connecting(Default::default())), AppState::Connecting(None));
+        let AppState::Connected(Some(relay_info)) = state(connected("se-got-wg-001")) else {
+            panic!("not connected with relay info");
+        };
+        assert_eq!(relay_info.hostname().map(String::as_str), Some("se-got-wg-001"));
+        assert_eq!(state(State::Disconnecting(Default::default())), AppState::Disconnecting);
+        // an error without its details is still an error
+        assert_eq!(state(State::Error(Default::default())), AppState::Error(None));
+        let unknown_cause = proto::ErrorState { cause: 1000, ..Default::default() };
+        let error = proto::tunnel_state::Error { error_state: Some(unknown_cause) };
+        assert_eq!(state(State::Error(error)), AppState::Error(None));
+        // a state from a newer daemon
+        assert_eq!(app_state(proto::TunnelState { state: None }), AppState::Inactive);
+    }
+
+    #[test]
+    fn offline_errors_are_deliberate_blocks() {
+        let error = |cause| AppState::Error(Some(error_state(cause)));
+        assert!(error(ErrorCause::IsOffline).is_deliberately_blocked());
+        assert!(!error(ErrorCause::SetDns).is_deliberately_blocked());
+        assert!(!AppState::Error(None).is_deliberately_blocked());
+    }
+
+    /// The visible top-level labels of the menu
+    fn top_labels(menu: &[MenuItem<MulltrayApp>]) -> Vec<&str> {
+        menu
+            .iter()
+            .filter(|item| is_visible(item) && !matches!(item, MenuItem::Separator))
+            .map(label)
+            .collect()
+    }
+
+    fn menu_with(tray: &TestTray, f: impl FnOnce(&mut MulltrayApp)) -> Vec<MenuItem<MulltrayApp>> {
+        tray.handle.update(|tray: &mut MulltrayApp| {
+            f(tray);
+            ksni::Tray::menu(tray)
+        })
+    }
+
+    #[tokio::test]
+    async fn the_menu_follows_the_tunnel_state() {
+        let tray = TestTray::new(Config::default());
+        let menu = menu_with(&tray, |tray| tray.app_state = AppState::Disconnected);
+        let labels = top_labels(&menu);
+        assert!(labels.contains(&"Connect"), "{labels:?}");
+        assert!(!labels.contains(&"Disconnect"), "{labels:?}");
+
+        let menu = menu_with(&tray, |tray| tray.app_state = AppState::Connected(None));
+        let labels = top_labels(&menu);
+        assert!(labels.contains(&"Disconnect"), "{labels:?}");
+        assert!(labels.contains(&"Reconnect"), "{labels:?}");
+        assert!(!labels.contains(&"Connect"), "{labels:?}");
+
+        let menu = menu_with(&tray, |tray| {
+            tray.app_state = AppState::Error(Some(error_state(ErrorCause::StartTunnel)))
+        });
+        let labels = top_labels(&menu);
+        assert!(labels.contains(&"Failed to start the tunnel"), "{labels:?}");
+        assert!(labels.contains(&"All traffic is blocked"), "{labels:?}");
+        assert!(labels.contains(&"Reconnect"), "{labels:?}");
+    }
+
+    #[tokio::test]
+    async fn the_menu_lists_the_relay_list() {
+        let tray = TestTray::new(Config::default());
+        let menu = menu_with(&tray, |tray| {
+            tray.set_locations(relay_list(&["se-got-wg-001", "de-ber-wg-001"]).into())
+        });
+        let countries = submenu_labels(&menu, &["Choose location"]);
+        let listed = |name: &str| countries.iter().any(|label| label.starts_with(name));
+        assert!(listed("DE") && listed("SE"), "{countries:?}");
+        assert!(find_item(&menu, &["Connect to fastest"]).is_some_and(is_visible));
+    }
+
+    #[tokio::test]
+    async fn logged_out_menus_offer_to_log_in() {
+        let tray = TestTray::new(Config::default());
+        let menu = menu_with(&tray, |tray| tray.set_device_state(DeviceState::LoggedOut));
+        let labels = top_labels(&menu);
+        assert!(labels.contains(&"Not logged in"), "{labels:?}");
+        assert!(labels.contains(&"Log in..."), "{labels:?}");
+        assert!(!labels.contains(&"Log out"), "{labels:?}");
+    }
+
+    #[tokio::test]
+    async fn the_minimal_menu_only_toggles_and_quits() {
+        let tray = TestTray::new(Config { minimal_menu: true, ..Config::default() });
+        let menu = menu_with(&tray, |tray| tray.app_state = AppState::Connected(None));
+        assert_eq!(top_labels(&menu), ["Disconnect", "Quit"]);
+    }
+
+    #[tokio::test]
+    async fn session_usage_starts_over_with_a_new_connection() {
+        let tray = TestTray::new(Config::default());
+        let counters = Counters { rx_bytes: 100, tx_bytes: 10 };
+        let usage = |tray: &MulltrayApp| tray.session_usage.as_ref().map(SessionUsage::total);
+        tray.handle.update(|tray: &mut MulltrayApp| {
+            tray.set_app_state(AppState::Connected(None));
+            tray.session_usage.get_or_insert_with(Default::default).record(counters);
+            // reconnecting is still the same session
+            tray.set_app_state(AppState::Connecting(None));
+            tray.set_app_state(AppState::Connected(None));
+            assert_eq!(usage(tray), Some(counters));
+
+            tray.set_app_state(AppState::Disconnected);
+            tray.set_app_state(AppState::Connecting(None));
+            tray.set_app_state(AppState::Connected(None));
+            assert_eq!(usage(tray), None);
+        });
+    }
+
+    #[tokio::test]
+    async fn only_disconnects_from_elsewhere_are_unexpected() {
+        let tray = TestTray::new(Config::default());
+        let disconnected_unexpectedly = |disconnect_from_tray: bool| {
+            tray.handle.update(|tray: &mut MulltrayApp| {
+                tray.set_app_state(AppState::Connected(None));
+                if disconnect_from_tray {
+                    tray.disconnect();
+                }
+                tray.set_app_state(AppState::Disconnecting);
+                tray.set_app_state(AppState::Disconnected);
+                tray.disconnected_unexpectedly
+            })
+        };
+        assert!(disconnected_unexpectedly(false));
+        assert!(!disconnected_unexpectedly(true));
+        assert!(disconnected_unexpectedly(false));
+
+        // never having been up isn't a disconnect
+        let never_up = tray.handle.update(|tray: &mut MulltrayApp| {
+            tray.set_app_state(AppState::Connected(None));
+            tray.disconnect();
+            tray.set_app_state(AppState::Disconnected);
+            tray.set_app_state(AppState::Connecting(None));
+            tray.set_app_state(AppState::Disconnected);
+            tray.disconnected_unexpectedly
+        });
+        assert!(!never_up);
+    }
+
+    #[tokio::test]
+    async fn background_tasks_are_told_what_they_need() {
+        let tray = TestTray::new(Config { show_usage: true, ..Config::default() });
+        let (connection, relays) = tray.handle.update(|tray: &mut MulltrayApp| {
+            (tray.connection.subscribe(), tray.relays_to_measure.subscribe())
+        });
+        let endpoint = Endpoint {
+            address: "185.213.154.1:51820".into(),
+            tunnel_type: TunnelType::WireGuard,
+            quantum_resistant: false,
+            obfuscation: None,
+            proxy: None,
+            entry_address: None,
+            tunnel_interface: Some("wg0-mullvad".into()),
+        };
+        let relay_info = RelayInfo { endpoint: Some(endpoint), ..relay_info(None, None) };
+        tray.handle.update(|tray: &mut MulltrayApp| {
+            tray.set_app_state(AppState::Connected(Some(relay_info)));
+        });
+        let usage_interface = Some("wg0-mullvad".to_string());
+        assert_eq!(*connection.borrow(), Connection::Up { usage_interface });
+        tray.handle.update(|tray: &mut MulltrayApp| tray.set_app_state(AppState::Disconnected));
+        assert_eq!(*connection.borrow(), Connection::Down);
+
+        let hostnames = || -> Vec<String> {
+            relays.borrow().iter().map(|(hostname, _)| hostname.clone()).collect()
+        };
+        tray.handle.update(|tray: &mut MulltrayApp| {
+            tray.set_locations(relay_list(&["se-got-wg-001", "se-got-wg-002"]).into())
+        });
+        assert!(hostnames().is_empty());
+        tray.handle.update(|tray: &mut MulltrayApp| tray.toggle_measure_latency());
+        assert_eq!(hostnames(), ["se-got-wg-001", "se-got-wg-002"]);
+        tray.handle.update(|tray: &mut MulltrayApp| tray.toggle_measure_latency());
+        assert!(hostnames().is_empty());
+    }
+
+    fn relay_info(hostname: Option<&str>, entry_hostname: Option<&str>) -> RelayInfo {
+        RelayInfo {
+            location: Some(Location {
+                hostname: hostname.map(Into::into),
+                entry_hostname: entry_hostname.map(Into::into),
+                country: Some("Sweden".into()),
+                city: None,
+                ipv4: None,
+                ipv6: None,
+            }),
+            endpoint: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn titles_only_name_the_relays_that_are_known() {
+        use AppState::{Connected, Connecting};
+        let no_location = RelayInfo { location: None, endpoint: None };
+        let exit = Some("se-got-wg-001");
+        let entry = Some("se-sto-wg-002");
+        let cases = [
+            (Connecting(None), "connecting.."),
+            (Connected(None), "connected"),
+            (Connected(Some(no_location.clone())), "connected"),
+            (Connecting(Some(no_location)), "connecting.."),
+            (Connected(Some(relay_info(None, None))), "connected"),
+            (Connected(Some(relay_info(None, entry))), "connected"),
+            (Connected(Some(relay_info(exit, None))), "connected to se-got-wg-001"),
+            (
+                Connected(Some(relay_info(exit, entry))),
+                "connected via se-sto-wg-002 → se-got-wg-001",
+            ),
+            (Connecting(Some(relay_info(exit, None))), "connecting to se-got-wg-001.."),
+            (
+                Connecting(Some(relay_info(exit, entry))),
+                "connecting via se-sto-wg-002 → se-got-wg-001..",
+            ),
+        ];
+        let tray = TestTray::new(Config::default());
+        for (app_state, expected) in cases {
+            tray.handle.update(|tray: &mut MulltrayApp| {
+                tray.app_state = app_state.clone();
+                assert_eq!(ksni::Tray::title(tray), format!("mulltray - {expected}"));
+                ksni::Tray::tool_tip(tray);
+                ksni::Tray::menu(tray);
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn later_states_without_relay_info_keep_the_earlier_one() {
+        use AppState::{Connected, Connecting};
+        let tray = TestTray::new(Config::default());
+        let info = relay_info(Some("se-got-wg-001"), None);
+        let other = relay_info(Some("de-ber-wg-001"), None);
+        let cases = [
+            (Connecting(Some(info.clone())), Connecting(None), Connecting(Some(info.clone()))),
+            (Connecting(Some(info.clone())), Connected(None), Connected(Some(info.clone()))),
+            (Connected(Some(info.clone())), Connected(None), Connected(Some(info.clone()))),
+            (Connected(Some(info.clone())), Connected(Some(other.clone())), Connected(Some(other))),
+            // a new connection doesn't get the relay of the last one
+            (Connected(Some(info.clone())), Connecting(None), Connecting(None)),
+            (AppState::Disconnected, Connected(None), Connected(None)),
+        ];
+        for (before, app_state, expected) in cases {
+            tray.handle.update(|tray: &mut MulltrayApp| {
+                tray.app_state = before.clone();
+                tray.set_app_state(app_state.clone());
+                assert_eq!(tray.app_state, expected, "{before:?} then {app_state:?}");
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn titles_follow_the_title_format() {
+        let location = Location {
+            city: Some("Gothenburg".into()),
+            ipv6: Some("2a03:1b20::1".into()),
+            ..relay_info(Some("se-got-wg-001"), None).location.unwrap()
+        };
+        let connected = AppState::Connected(Some(RelayInfo {
+            location: Some(location),
+            endpoint: None,
+        }));
+        let title_with = |title_format: Option<&str>, app_state: &AppState| {
+            let config = Config { title_format: title_format.map(Into::into), ..Config::default() };
+            let tray = TestTray::new(config);
+            tray.handle.update(|tray: &mut MulltrayApp| {
+                tray.app_state = app_state.clone();
+                ksni::Tray::title(tray)
+            })
+        };
+        // the duration is missing since the connection wasn't seen starting
+        let template = Some("{state} {hostname} {city}, {country} ({exit_ip}) {duration}");
+        assert_eq!(
+            title_with(template, &connected),
+            "connected se-got-wg-001 Gothenburg, Sweden (2a03:1b20::1)"
+        );
+        // missing values are left out rather than shown as "None"
+        let title = title_with(Some("{state} {hostname}"), &AppState::Connected(None));
+        assert_eq!(title, "connected");
+        assert_eq!(title_with(Some("vpn: {state} {bogus}"), &connected), "vpn: connected");
+        assert_eq!(title_with(None, &connected), "mulltray - connected to se-got-wg-001");
+    }
+
+    #[test]
+    fn quick_connecting_only_changes_settled_states() {
+        let error = AppState::Error(Some(error_state(ErrorCause::StartTunnel)));
+        let cases = [
+            (AppState::Disconnected, true, true),
+            (AppState::Disconnected, false, false),
+            (AppState::Blocked, true, true),
+            (AppState::Blocked, false, false),
+            (AppState::Connected(None), true, false),
+            (AppState::Connected(None), false, true),
+            (AppState::Connecting(None), true, false),
+            (AppState::Connecting(None), false, false),
+            (AppState::Disconnecting, true, false),
+            (AppState::Disconnecting, false, false),
+            (error.clone(), true, true),
+            (error, false, true),
+            (AppState::Inactive, true, true),
+            (AppState::DaemonUnavailable, true, true),
+        ];
+        for (app_state, connect, changes) in cases {
+            let changed = quick_connect_changes(&app_state, connect);
+            assert_eq!(changed, changes, "connect: {connect} in {app_state:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn scrolling_has_to_add_up_before_it_connects() {
+        let tray = TestTray::new(Config::default());
+        let daemon = MockDaemon::default();
+        let _server = tray.serve(&daemon);
+        let scrolled = |delta: i32, dir: &'static str| {
+            tray.handle.update(|tray: &mut MulltrayApp| {
+                tray.app_state = AppState::Disconnected;
+                ksni::Tray::scroll(tray, delta, dir);
+                tray.scrolled.map(|(scrolled, _)| scrolled)
+            })
+        };
+        assert_eq!(scrolled(1, "vertical"), Some(1));
+        // scrolling the other way starts over
+        assert_eq!(scrolled(-1, "vertical"), Some(-1));
+        assert_eq!(scrolled(1, "vertical"), Some(1));
+        assert_eq!(scrolled(5, "horizontal"), Some(1));
+        assert_eq!(scrolled(0, "vertical"), Some(1));
+        assert_eq!(daemon.calls(), Vec::<&str>::new());
+        assert_eq!(scrolled(1, "vertical"), None);
+        tray.wait_for("connecting", |_| daemon.calls() == ["connect_tunnel"]).await;
+    }
+
+    #[test]
+    fn accounts_expire_at_their_expiry() {
+        let now = SystemTime::now();
+        let hour = Duration::from_secs(60 * 60);
+        let expired = AccountExpiry::At(now - Duration::from_secs(1));
+        assert!(expired.is_expired());
+        assert_eq!(expired.time_remaining(), Some(Duration::ZERO));
+        assert_eq!(expired.label(), "Account has expired");
+        let expiring = AccountExpiry::At(now + hour + Duration::from_secs(60));
+        assert!(!expiring.is_expired());
+        assert_eq!(expiring.label(), "Account expires in 1 hour");
+        assert!(!AccountExpiry::Unknown.is_expired());
+        assert!(!AccountExpiry::NoAccount.is_expired());
+    }
+
+    #[tokio::test]
+    async fn connect_is_greyed_out_once_the_account_runs_out() {
+        let tray = TestTray::new(Config::default());
+        let connect = |tray: &TestTray| {
+            let menu = menu_with(tray, |tray| tray.app_state = AppState::Disconnected);
+            let labels = ["Connect", "Account expired — add time"];
+            let connect =
+                menu.iter().find(|item| is_visible(item) && labels.contains(&label(item)));
+            let Some(MenuItem::Standard(connect)) = connect else {
+                panic!("no connect item");
+            };
+            (connect.label.clone(), connect.enabled)
+        };
+        // expires well before the next refresh of the expiry
+        let expiry = SystemTime::now() + Duration::from_millis(50);
+        tray.handle.update(|tray: &mut MulltrayApp| {
+            tray.set_device_state(DeviceState::LoggedIn(None));
+            tray.account_expiry = AccountExpiry::At(expiry);
+        });
+        assert_eq!(connect(&tray), ("Connect".into(), true));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(connect(&tray), ("Account expired — add time".into(), false));
+        let menu = menu_with(&tray, |_| {});
+        let Some(MenuItem::Standard(fastest)) = find_item(&menu, &["Connect to fastest"]) else {
+            panic!("no connect to fastest item");
+        };
+        assert!(!fastest.enabled);
+
+        // a voucher was redeemed
+        let expiry = SystemTime::now() + Duration::from_secs(30 * 24 * 60 * 60);
+        tray.handle.update(|tray: &mut MulltrayApp| {
+            tray.account_expiry = AccountExpiry::At(expiry);
+        });
+        assert_eq!(connect(&tray), ("Connect".into(), true));
+    }
+
+    #[test]
+    fn fastest_sample_starts_from_the_cities_that_used_to_be_fastest() {
+        let candidate = |city: &str, hostname: &str| Candidate {
+            country: "se".into(),
+            city: city.into(),
+            hostname: hostname.into(),
+            addr: Ipv4Addr::LOCALHOST,
+        };
+        let candidates = vec![
+            candidate("got", "se-got-wg-001"),
+            candidate("got", "se-got-wg-002"),
+            candidate("mma", "se-mma-wg-001"),
+            candidate("sto", "se-sto-wg-001"),
+            candidate("sto", "se-sto-wg-002"),
+        ];
+        let mut latencies = Latencies::default();
+        latencies.record("se-got-wg-002".into(), Some(Duration::from_millis(30)));
+        latencies.record("se-sto-wg-001".into(), Some(Duration::from_millis(20)));
+
+        let sample = fastest_sample(candidates, &latencies);
+        let hostnames: Vec<_> = sample.iter().map(|c| c.hostname.as_str()).collect();
+        assert_eq!(hostnames, ["se-sto-wg-001", "se-got-wg-002", "se-mma-wg-001"]);
+    }
+}
@@ -0,0 +1,95 @@
+//! Which of the optional features the daemon has, going by its version. Calls that an older
+//! daemon doesn't know are better not made at all than made and answered with an error.
+
+/// The first versions with each feature, as (year, release)
+const DAITA: (u32, u32) = (2024, 8);
+const CUSTOM_LISTS: (u32, u32) = (2024, 1);
+const API_ACCESS_METHODS: (u32, u32) = (2024, 1);
+const SHADOWSOCKS_OBFUSCATION: (u32, u32) = (2024, 4);
+
+/// Features that depend on the version of the daemon. Everything is off for a version that
+/// can't be parsed, since the menu can't offer what the daemon might not do.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DaemonCapabilities {
+    pub daita: bool,
+    pub custom_lists: bool,
+    pub api_access_methods: bool,
+    pub shadowsocks_obfuscation: bool,
+}
+
+/// The year and the release of a version such as `2024.8`, `2024.8-beta1` or
+/// `2025.3-dev-0123ab`
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let release = version.trim().split('-').next()?;
+    let (year, number) = release.split_once('.')?;
+    Some((year.parse().ok()?, number.parse().ok()?))
+}
+
+impl DaemonCapabilities {
+    pub fn for_version(version: &str) -> Self {
+        let Some(version) = parse_version(version) else {
+            return Self::default();
+        };
+        Self {
+            daita: version >= DAITA,
+            custom_lists: version >= CUSTOM_LISTS,
+            api_access_methods: version >= API_ACCESS_METHODS,
+            shadowsocks_obfuscation: version >= SHADOWSOCKS_OBFUSCATION,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_parsed() {
+        let cases = [
+            ("2024.8", Some((2024, 8))),
+            (" 2024.8\n", Some((2024, 8))),
+            ("2024.8-beta1", Some((2024, 8))),
+            ("2025.3-dev-0123ab", Some((2025, 3))),
+            ("2023.6-beta2-dev-4e5f6a", Some((2023, 6))),
+            ("", None),
+            ("2024", None),
+            ("2024.", None),
+            (".8", None),
+            ("2024.8.1", None),
+            ("v2024.8", None),
+            ("2024.x", None),
+            ("-2024.8", None),
+            ("unknown", None),
+        ];
+        for (version, expected) in cases {
+            assert_eq!(parse_version(version), expected, "{version:?}");
+        }
+    }
+
+    #[test]
+    fn features_start_at_their_versions() {
+        let none = DaemonCapabilities::default();
+        let lists = DaemonCapabilities { custom_lists: true, api_access_methods: true, ..none };
+        let shadowsocks = DaemonCapabilities { shadowsocks_obfuscation: true, ..lists };
+        let all = DaemonCapabilities { daita: true, ..shadowsocks };
+        let cases = [
+            ("2023.6", none),
+            ("2023.99", none),
+            ("2024.1-beta1", lists),
+            ("2024.1", lists),
+            ("2024.3", lists),
+            ("2024.4-beta1", shadowsocks),
+            ("2024.4", shadowsocks),
+            ("2024.7", shadowsocks),
+            ("2024.8-beta1", all),
+            ("2024.8", all),
+            ("2024.9-dev-0123ab", all),
+            ("2025.1", all),
+            ("garbage", none),
+            ("", none),
+        ];
+        for (version, expected) in cases {
+            assert_eq!(DaemonCapabilities::for_version(version), expected, "{version:?}");
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Command-line arguments
+
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+/// Where the Mullvad daemon listens on Linux unless told otherwise
+pub const DEFAULT_SOCKET_PATH: &str = "/var/run/mullvad-vpn";
+
+/// System tray icon for controlling the Mullvad VPN daemon
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path to the management socket of the Mullvad daemon [default: /var/run/mullvad-vpn]
+    #[arg(long, env = "MULLVAD_RPC_SOCKET_PATH")]
+    pub socket_path: Option<PathBuf>,
+
+    /// Log filter such as `debug` or `mulltray=trace`, overrides `MULLTRAY_LOG`
+    #[arg(long)]
+    pub log_level: Option<String>,
+
+    /// Log to a file in `$XDG_STATE_HOME/mulltray/` instead of stderr
+    #[arg(long)]
+    pub log_file: bool,
+
+    /// Quit the mulltray that is already running and take its place
+    #[arg(long)]
+    pub replace: bool,
+
+    /// Connect once the daemon is reached if the tunnel is down, like `connect_on_start` in
+    /// the config file
+    #[arg(long)]
+    pub connect_on_start: bool,
+
+    /// Start mulltray on login by adding it to `$XDG_CONFIG_HOME/autostart/`, then exit
+    #[arg(long, conflicts_with = "uninstall_autostart")]
+    pub install_autostart: bool,
+
+    /// Stop starting mulltray on login, then exit
+    #[arg(long)]
+    pub uninstall_autostart: bool,
+
+    /// Print a systemd user unit for running mulltray as a service, then exit. Save it as
+    /// `~/.config/systemd/user/mulltray.service`.
+    #[arg(long)]
+    pub print_systemd_unit: bool,
+
+    /// Let --install-autostart and --uninstall-autostart replace or remove a desktop file that
+    /// mulltray didn't create
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print the state of the tunnel and exit instead of showing the tray icon
+    Status {
+        /// Print JSON for status bars such as waybar instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the state of the tunnel every time it changes, for status bars to follow
+    Watch {
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// One line of text
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
@@ -0,0 +1,111 @@
+//! Copying text to and from the clipboard with the command line tools of the display server.
+//!
+//! Clipboard contents are owned by a running process on both Wayland and X11, so mulltray
+//! can't simply hand the text over and forget about it. wl-copy and xclip fork into the
+//! background and keep serving the text after we're done with them.
+
+use std::fmt;
+use std::io::Write;
+use std::process::{Command, ExitStatus, Stdio};
+
+#[derive(Debug)]
+pub enum Error {
+    /// Neither `WAYLAND_DISPLAY` nor `DISPLAY` is set
+    NoDisplay,
+    /// None of the clipboard tools for the display server are installed
+    NoTool(&'static [&'static str]),
+    Io(&'static str, std::io::Error),
+    Failed(&'static str, ExitStatus),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoDisplay => write!(f, "no Wayland or X11 display found"),
+            Error::NoTool(tools) => write!(f, "install one of: {}", tools.join(", ")),
+            Error::Io(tool, e) => write!(f, "could not run {tool}: {e}"),
+            Error::Failed(tool, status) => write!(f, "{tool} failed ({status})"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+const WAYLAND_TOOLS: &[(&str, &[&str])] = &[("wl-copy", &[])];
+const X11_TOOLS: &[(&str, &[&str])] = &[
+    ("xclip", &["-selection", "clipboard"]),
+    ("xsel", &["--clipboard", "--input"]),
+];
+
+const WAYLAND_PASTE_TOOLS: &[(&str, &[&str])] = &[("wl-paste", &["--no-newline"])];
+const X11_PASTE_TOOLS: &[(&str, &[&str])] = &[
+    ("xclip", &["-selection", "clipboard", "-out"]),
+    ("xsel", &["--clipboard", "--output"]),
+];
+
+fn is_set(var: &str) -> bool {
+    std::env::var_os(var).is_some_and(|value| !value.is_empty())
+}
+
+/// Puts the text on the clipboard using the first clipboard tool that is installed.
+///
+/// This waits for the tool to read the text so don't call it from async code directly.
+pub fn copy(text: &str) -> Result<(), Error> {
+    let (tools, tool_names): (_, &[&str]) = if is_set("WAYLAND_DISPLAY") {
+        (WAYLAND_TOOLS, &["wl-copy (wl-clipboard)"])
+    } else if is_set("DISPLAY") {
+        (X11_TOOLS, &["xclip", "xsel"])
+    } else {
+        return Err(Error::NoDisplay);
+    };
+    for (tool, args) in tools {
+        let mut child = match Command::new(tool)
+            .args(*args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(Error::Io(tool, e)),
+        };
+        // dropping stdin closes it so the tool knows it has all of the text
+        let written = match child.stdin.take() {
+            Some(mut stdin) => stdin.write_all(text.as_bytes()),
+            None => Ok(()),
+        };
+        let status = child.wait().map_err(|e| Error::Io(tool, e))?;
+        written.map_err(|e| Error::Io(tool, e))?;
+        return match status.success() {
+            true => Ok(()),
+            false => Err(Error::Failed(tool, status)),
+        };
+    }
+    Err(Error::NoTool(tool_names))
+}
+
+/// The text on the clipboard, read with the first clipboard tool that is installed.
+///
+/// This waits for the tool to finish so don't call it from async code directly.
+pub fn paste() -> Result<String, Error> {
+    let (tools, tool_names): (_, &[&str]) = if is_set("WAYLAND_DISPLAY") {
+        (WAYLAND_PASTE_TOOLS, &["wl-paste (wl-clipboard)"])
+    } else if is_set("DISPLAY") {
+        (X11_PASTE_TOOLS, &["xclip", "xsel"])
+    } else {
+        return Err(Error::NoDisplay);
+    };
+    for (tool, args) in tools {
+        let output = match Command::new(tool).args(*args).stderr(Stdio::null()).output() {
+            Ok(output) => output,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(Error::Io(tool, e)),
+        };
+        return match output.status.success() {
+            true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+            false => Err(Error::Failed(tool, output.status)),
+        };
+    }
+    Err(Error::NoTool(tool_names))
+}
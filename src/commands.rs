@@ -0,0 +1,1160 @@
+//! Everything that changes something in the daemon is sent as a [`Command`] to one task, the
+//! [`CommandRunner`]. It has the only copy of the settings that changes are made to, so
+//! changes made in quick succession build on each other and reach the daemon in the order
+//! they were made, and the tray shows the settings the runner hands it.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::{mpsc, watch, Notify};
+use tokio_util::task::TaskTracker;
+use tracing::{debug, warn};
+
+use crate::app::{AccountExpiry, AppState, LocationKind, LocationsStatus, MulltrayApp};
+use crate::daemon::with_retries;
+use crate::format::format_time_remaining;
+use crate::logging::LoggingChannel;
+use crate::model::{
+    AccessMethod, BridgeState, DnsBlocker, GeographicLocation, LocationConstraint,
+    ObfuscationMode, QuantumResistant, Settings, TunnelType,
+};
+use crate::notify::Notifier;
+use crate::proto;
+use crate::proto::management_service_client::ManagementServiceClient;
+
+type Client = ManagementServiceClient<LoggingChannel>;
+
+const COMMAND_FAILURE_SHOWN_FOR: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum Command {
+    Connect,
+    Disconnect,
+    Reconnect,
+    /// `None` lets the daemon pick any relay. With `connect` the tunnel is also brought up if
+    /// it's down.
+    SetLocation {
+        kind: LocationKind,
+        location: Option<LocationConstraint>,
+        connect: bool,
+    },
+    SetBlockWhenDisconnected(bool),
+    SetAutoConnect(bool),
+    SetAllowLan(bool),
+    SetEnableIpv6(bool),
+    SetSplitTunnelEnabled(bool),
+    AddSplitTunnelApp(String),
+    RemoveSplitTunnelApp(String),
+    SetDnsBlocker(DnsBlocker, bool),
+    /// `servers` are used if there are no custom DNS servers yet
+    SetCustomDnsEnabled {
+        enabled: bool,
+        servers: Vec<String>,
+    },
+    SetCustomDnsServers(Vec<String>),
+    SetObfuscation(ObfuscationMode),
+    /// `None` lets the daemon pick the port
+    SetUdp2TcpPort(Option<u32>),
+    SetQuantumResistantTunnel(QuantumResistant),
+    SetDaita(bool),
+    /// `None` lets the daemon pick the MTU
+    SetWireguardMtu(Option<u32>),
+    SetBridgeState(BridgeState),
+    SetMullvadOwnedOnly(bool),
+    /// `all_providers` are the ones in the relay list, allowing all of them is the same as
+    /// not filtering by provider
+    SetProviderEnabled {
+        provider: String,
+        enabled: bool,
+        all_providers: Vec<String>,
+    },
+    SetWireguardPort(Option<u32>),
+    SetTunnelProtocol(Option<TunnelType>),
+    SetMultihop(bool),
+    CreateCustomList(String),
+    AddToCustomList {
+        id: String,
+        location: GeographicLocation,
+    },
+    /// The id of the method
+    SetApiAccessMethod(String),
+    /// Fetches the relay list
+    LoadLocations,
+    LogIn(String),
+    LogOut,
+    RedeemVoucher(String),
+    RotateWireguardKey,
+    /// Settings that the daemon sent or that were fetched from it, boxed since they're big
+    DaemonSettings(Box<proto::Settings>),
+}
+
+/// The relay settings, `None` unless they're the Normal kind that mulltray supports
+fn normal_relay_settings(settings: &proto::Settings) -> Option<&proto::NormalRelaySettings> {
+    match &settings.relay_settings {
+        Some(proto::RelaySettings {
+            endpoint: Some(proto::relay_settings::Endpoint::Normal(norm)),
+        }) => Some(norm),
+        _ => None,
+    }
+}
+
+/// Sets the port of `obfuscation_settings` that udp2tcp connects to, `None` lets the daemon
+/// pick it. The other obfuscation settings are left alone.
+fn with_udp2tcp_port(obfuscation_settings: &mut proto::ObfuscationSettings, port: Option<u32>) {
+    // the daemon may not have sent any udp2tcp settings
+    obfuscation_settings.udp2tcp.get_or_insert_with(Default::default).port = port;
+}
+
+/// The providers in `providers`, where none means any provider, with `provider` enabled or
+/// disabled. `None` if that would disable the last one, which would turn them all back on.
+fn providers_with(
+    providers: &[String],
+    provider: &str,
+    enabled: bool,
+    all_providers: &[String],
+) -> Option<Vec<String>> {
+    let mut providers = match providers.is_empty() {
+        true => all_providers.to_vec(),
+        false => providers.to_vec(),
+    };
+    providers.retain(|p| p != provider);
+    if enabled {
+        providers.push(provider.into());
+    }
+    if providers.is_empty() {
+        return None;
+    }
+    if all_providers.iter().all(|p| providers.contains(p)) {
+        providers.clear();
+    }
+    Some(providers)
+}
+
+fn dns_options_mut(settings: &mut proto::Settings) -> &mut Option<proto::DnsOptions> {
+    &mut settings.tunnel_options.get_or_insert_with(Default::default).dns_options
+}
+
+fn wireguard_options_mut(
+    settings: &mut proto::Settings,
+) -> &mut proto::tunnel_options::WireguardOptions {
+    let tunnel_options = settings.tunnel_options.get_or_insert_with(Default::default);
+    tunnel_options.wireguard.get_or_insert_with(Default::default)
+}
+
+fn split_tunnel_mut(settings: &mut proto::Settings) -> &mut proto::SplitTunnelSettings {
+    settings.split_tunnel.get_or_insert_with(Default::default)
+}
+
+/// Runs the commands, see the module docs
+pub struct CommandRunner {
+    client: Client,
+    /// The settings that changes are made to
+    settings: proto::Settings,
+    /// Whether changes were made since the settings were last taken from the daemon. A
+    /// settings event may be for one of the earlier changes then, and would undo later ones.
+    changed: bool,
+    tray_handle: ksni::Handle<MulltrayApp>,
+    /// Of the tray's state, so it doesn't have to be asked about it
+    state_names: watch::Receiver<&'static str>,
+    notifier: Notifier,
+    /// Requests that have nothing to do with the settings and can take a while, such as the
+    /// ones that go to the Mullvad API, run alongside the commands
+    tasks: TaskTracker,
+}
+
+impl CommandRunner {
+    pub fn new(
+        client: Client,
+        tray_handle: ksni::Handle<MulltrayApp>,
+        state_names: watch::Receiver<&'static str>,
+        notifier: Notifier,
+        tasks: TaskTracker,
+    ) -> Self {
+        Self {
+            client,
+            settings: proto::Settings::default(),
+            changed: false,
+            tray_handle,
+            state_names,
+            notifier,
+            tasks,
+        }
+    }
+
+    /// Runs the commands one at a time. Once `closed` is notified the commands that were
+    /// already sent are still run, and then this returns.
+    pub async fn run(
+        mut self,
+        mut commands: mpsc::UnboundedReceiver<Command>,
+        closed: Arc<Notify>,
+    ) {
+        loop {
+            tokio::select! {
+                command = commands.recv() => match command {
+                    Some(command) => self.run_command(command).await,
+                    None => return,
+                },
+                _ = closed.notified() => break,
+            }
+        }
+        commands.close();
+        while let Some(command) = commands.recv().await {
+            self.run_command(command).await;
+        }
+    }
+
+    async fn run_command(&mut self, command: Command) {
+        debug!(?command, "Running command");
+        match command {
+            Command::Connect => self.connect_tunnel().await,
+            Command::Disconnect => {
+                self.tunnel_command("disconnect", |mut client| async move {
+                    client.disconnect_tunnel(()).await
+                })
+                .await
+            }
+            Command::Reconnect => self.reconnect_tunnel().await,
+            Command::SetLocation { kind, location, connect } => {
+                self.set_location(kind, location, connect).await
+            }
+            Command::SetBlockWhenDisconnected(enabled) => {
+                self.set_block_when_disconnected(enabled).await
+            }
+            Command::SetAutoConnect(enabled) => self.set_auto_connect(enabled).await,
+            Command::SetAllowLan(enabled) => self.set_allow_lan(enabled).await,
+            Command::SetEnableIpv6(enabled) => self.set_enable_ipv6(enabled).await,
+            Command::SetSplitTunnelEnabled(enabled) => self.set_split_tunnel_enabled(enabled).await,
+            Command::AddSplitTunnelApp(path) => self.add_split_tunnel_app(path).await,
+            Command::RemoveSplitTunnelApp(path) => self.remove_split_tunnel_app(path).await,
+            Command::SetDnsBlocker(blocker, enabled) => {
+                let mut dns_options = self.dns_options();
+                let default_options =
+                    dns_options.default_options.get_or_insert_with(Default::default);
+                *blocker.field()(default_options) = enabled;
+                self.set_dns_options("DNS content blockers", dns_options).await;
+            }
+            Command::SetCustomDnsEnabled { enabled, servers } => {
+                self.set_custom_dns_enabled(enabled, servers).await
+            }
+            Command::SetCustomDnsServers(addresses) => {
+                let mut dns_options = self.dns_options();
+                dns_options.custom_options = Some(proto::CustomDnsOptions { addresses });
+                dns_options.set_state(proto::dns_options::DnsState::Custom);
+                self.set_dns_options("custom DNS", dns_options).await;
+            }
+            Command::SetObfuscation(selected) => {
+                self.change_obfuscation(|obfuscation_settings| {
+                    obfuscation_settings.set_selected_obfuscation(selected.into())
+                })
+                .await
+            }
+            Command::SetUdp2TcpPort(port) => {
+                self.change_obfuscation(|obfuscation_settings| {
+                    with_udp2tcp_port(obfuscation_settings, port)
+                })
+                .await
+            }
+            Command::SetQuantumResistantTunnel(state) => {
+                self.set_quantum_resistant_tunnel(state).await
+            }
+            Command::SetDaita(enabled) => self.set_daita(enabled).await,
+            Command::SetWireguardMtu(mtu) => self.set_wireguard_mtu(mtu).await,
+            Command::SetBridgeState(state) => self.set_bridge_state(state).await,
+            Command::SetMullvadOwnedOnly(enabled) => {
+                self.update_relay_settings(|norm| {
+                    norm.set_ownership(match enabled {
+                        true => proto::Ownership::MullvadOwned,
+                        false => proto::Ownership::Any,
+                    })
+                })
+                .await
+            }
+            Command::SetProviderEnabled { provider, enabled, all_providers } => {
+                let providers = normal_relay_settings(&self.settings)
+                    .map(|norm| norm.providers.as_slice())
+                    .unwrap_or_default();
+                let Some(providers) =
+                    providers_with(providers, &provider, enabled, &all_providers)
+                else {
+                    warn!("Not disabling {provider}, it's the only provider left");
+                    return;
+                };
+                self.update_relay_settings(|norm| norm.providers = providers).await
+            }
+            Command::SetWireguardPort(port) => {
+                self.update_relay_settings(|norm| {
+                    norm.wireguard_constraints.get_or_insert_with(Default::default).port = port;
+                })
+                .await
+            }
+            Command::SetTunnelProtocol(tunnel_type) => {
+                self.update_relay_settings(|norm| {
+                    norm.tunnel_type = tunnel_type.map(|t| proto::TunnelType::from(t).into())
+                })
+                .await
+            }
+            Command::SetMultihop(enabled) => {
+                self.update_relay_settings(|norm| {
+                    norm.wireguard_constraints.get_or_insert_with(Default::default).use_multihop =
+                        enabled;
+                })
+                .await
+            }
+            Command::CreateCustomList(name) => {
+                // the menu is updated by the settings event that follows
+                if let Err(e) = self.client.create_custom_list(name).await {
+                    warn!("Could not create custom list: {}", e.message());
+                    self.notifier
+                        .notify("Could not create custom list".into(), e.message().into());
+                }
+            }
+            Command::AddToCustomList { id, location } => {
+                self.add_to_custom_list(id, location).await
+            }
+            Command::SetApiAccessMethod(id) => self.set_api_access_method(id).await,
+            Command::LoadLocations => {
+                self.tasks.spawn(load_locations(self.client.clone(), self.tray_handle.clone()));
+            }
+            Command::LogIn(account_number) => {
+                let (client, notifier) = (self.client.clone(), self.notifier.clone());
+                self.tasks.spawn(log_in(client, notifier, account_number));
+            }
+            Command::LogOut => {
+                self.tasks.spawn(log_out(self.client.clone(), self.notifier.clone()));
+            }
+            Command::RedeemVoucher(voucher) => {
+                let (client, notifier) = (self.client.clone(), self.notifier.clone());
+                let tray_handle = self.tray_handle.clone();
+                self.tasks.spawn(redeem_voucher(client, notifier, tray_handle, voucher));
+            }
+            Command::RotateWireguardKey => {
+                let (client, notifier) = (self.client.clone(), self.notifier.clone());
+                self.tasks.spawn(rotate_wireguard_key(client, notifier));
+            }
+            Command::DaemonSettings(settings) => self.receive_settings(*settings).await,
+        }
+    }
+
+    fn tunnel_is_up(&self) -> bool {
+        AppState::tunnel_is_up(&self.state_names.borrow())
+    }
+
+    /// Shows the settings in the tray
+    fn show_settings(&self) {
+        let settings = Settings::from(self.settings.clone());
+        self.tray_handle.update(|tray: &mut MulltrayApp| tray.settings = settings);
+    }
+
+    async fn receive_settings(&mut self, settings: proto::Settings) {
+        let settings = match self.changed {
+            // reading what the daemon has now is the only way to be sure that the settings have
+            // all of the changes that were made since
+            true => match self.client.get_settings(()).await {
+                Ok(current) => current.into_inner(),
+                Err(e) => {
+                    debug!("Could not fetch the settings, using the ones sent: {}", e.message());
+                    settings
+                }
+            },
+            false => settings,
+        };
+        self.settings = settings;
+        self.changed = false;
+        let settings = Settings::from(self.settings.clone());
+        self.tray_handle.update(|tray: &mut MulltrayApp| {
+            tray.settings = settings;
+            tray.restore_location_if_reset();
+        });
+    }
+
+    async fn connect_tunnel(&mut self) {
+        self.tunnel_command("connect", |mut client| async move { client.connect_tunnel(()).await })
+            .await
+    }
+
+    async fn reconnect_tunnel(&mut self) {
+        self.tunnel_command("reconnect", |mut client| async move {
+            client.reconnect_tunnel(()).await
+        })
+        .await
+    }
+
+    /// Sends a connect/disconnect/reconnect request, showing why in the title and in a
+    /// notification if the daemon refuses
+    async fn tunnel_command<T, F, Fut>(&mut self, action: &'static str, rpc: F)
+    where
+        F: FnOnce(Client) -> Fut,
+        Fut: Future<Output = Result<tonic::Response<T>, tonic::Status>>,
+    {
+        let Err(e) = rpc(self.client.clone()).await else {
+            return;
+        };
+        warn!("Could not {}: {}", action, e.message());
+        let failure = format!("{} failed: {}", action, e.message());
+        let shown = failure.clone();
+        self.tray_handle.update(|tray: &mut MulltrayApp| {
+            tray.last_error = Some(shown.clone());
+            tray.command_failure = Some(shown);
+        });
+        self.notifier.notify(format!("Could not {}", action), e.message().into());
+        let tray_handle = self.tray_handle.clone();
+        // not tracked like the requests since there is no need to wait for it when quitting
+        tokio::spawn(async move {
+            tokio::time::sleep(COMMAND_FAILURE_SHOWN_FOR).await;
+            tray_handle.update(|tray: &mut MulltrayApp| {
+                // a later failure gets its own few seconds
+                if tray.command_failure.as_ref() == Some(&failure) {
+                    tray.command_failure = None;
+                }
+            });
+        });
+    }
+
+    /// Changes a daemon setting and shows the new value right away. Transient failures are
+    /// retried, and if the change still fails the menu goes back to the settings the daemon
+    /// actually has.
+    async fn change_setting<T, F, Fut>(
+        &mut self,
+        description: &'static str,
+        field: fn(&mut proto::Settings) -> &mut T,
+        value: T,
+        mut rpc: F,
+    ) -> Result<(), tonic::Status>
+    where
+        T: Clone,
+        F: FnMut(Client, T) -> Fut,
+        Fut: Future<Output = Result<tonic::Response<()>, tonic::Status>>,
+    {
+        let previous = std::mem::replace(field(&mut self.settings), value.clone());
+        self.changed = true;
+        self.show_settings();
+        let client = self.client.clone();
+        let Err(e) = with_retries(|| rpc(client.clone(), value.clone())).await else {
+            return Ok(());
+        };
+        warn!("Could not change {}: {}", description, e.message());
+        // the daemon may have applied some of the change before failing
+        match self.client.get_settings(()).await {
+            Ok(settings) => {
+                self.settings = settings.into_inner();
+                self.changed = false;
+            }
+            Err(_) => *field(&mut self.settings) = previous,
+        }
+        self.show_settings();
+        self.notifier.notify(format!("Could not change {}", description), e.message().into());
+        Err(e)
+    }
+
+    async fn set_block_when_disconnected(&mut self, enabled: bool) {
+        let _ = self
+            .change_setting(
+                "lockdown mode",
+                |settings| &mut settings.block_when_disconnected,
+                enabled,
+                |mut client, enabled| async move {
+                    client.set_block_when_disconnected(enabled).await
+                },
+            )
+            .await;
+    }
+
+    async fn set_auto_connect(&mut self, enabled: bool) {
+        let _ = self
+            .change_setting(
+                "auto-connect",
+                |settings| &mut settings.auto_connect,
+                enabled,
+                |mut client, enabled| async move { client.set_auto_connect(enabled).await },
+            )
+            .await;
+    }
+
+    async fn set_allow_lan(&mut self, enabled: bool) {
+        let _ = self
+            .change_setting(
+                "LAN access",
+                |settings| &mut settings.allow_lan,
+                enabled,
+                |mut client, enabled| async move { client.set_allow_lan(enabled).await },
+            )
+            .await;
+    }
+
+    async fn set_enable_ipv6(&mut self, enabled: bool) {
+        // the tunnel has to be set up again to get an IPv6 address
+        let reconnect = self.tunnel_is_up();
+        let result = self
+            .change_setting(
+                "IPv6",
+                |settings| {
+                    let tunnel_options =
+                        settings.tunnel_options.get_or_insert_with(Default::default);
+                    &mut tunnel_options.generic.get_or_insert_with(Default::default).enable_ipv6
+                },
+                enabled,
+                |mut client, enabled| async move { client.set_enable_ipv6(enabled).await },
+            )
+            .await;
+        if result.is_ok() && reconnect {
+            self.reconnect_tunnel().await;
+        }
+    }
+
+    async fn set_split_tunnel_enabled(&mut self, enabled: bool) {
+        let result = self
+            .change_setting(
+                "split tunneling",
+                |settings| &mut split_tunnel_mut(settings).enable_exclusions,
+                enabled,
+                |mut client, enabled| async move { client.set_split_tunnel_state(enabled).await },
+            )
+            .await;
+        self.check_split_tunnel_support(&result);
+    }
+
+    async fn add_split_tunnel_app(&mut self, path: String) {
+        // the list in the menu is updated by the settings event that follows
+        let result = self.client.add_split_tunnel_app(path).await;
+        self.check_split_tunnel_support(&result);
+        if let Err(e) = result {
+            warn!("Could not add split tunneling application: {}", e.message());
+            self.notifier.notify("Could not add application".into(), e.message().into());
+        }
+    }
+
+    async fn remove_split_tunnel_app(&mut self, path: String) {
+        let apps = self.settings.split_tunnel
+            .as_ref()
+            .map(|split_tunnel| split_tunnel.apps.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|app| *app != path)
+            .collect();
+        let result = self
+            .change_setting(
+                "split tunneling",
+                |settings| &mut split_tunnel_mut(settings).apps,
+                apps,
+                move |mut client, _| {
+                    let path = path.clone();
+                    async move { client.remove_split_tunnel_app(path).await }
+                },
+            )
+            .await;
+        self.check_split_tunnel_support(&result);
+    }
+
+    /// Hides split tunneling if a call for it shows that the daemon doesn't have it after all
+    fn check_split_tunnel_support<T>(&self, result: &Result<T, tonic::Status>) {
+        if result.as_ref().is_err_and(|e| e.code() == tonic::Code::Unimplemented) {
+            self.tray_handle.update(|tray: &mut MulltrayApp| {
+                tray.split_tunnel_supported = false;
+            });
+        }
+    }
+
+    fn dns_options(&mut self) -> proto::DnsOptions {
+        dns_options_mut(&mut self.settings).clone().unwrap_or_default()
+    }
+
+    async fn set_custom_dns_enabled(&mut self, enabled: bool, servers: Vec<String>) {
+        let mut dns_options = self.dns_options();
+        let custom_options = dns_options.custom_options.get_or_insert_with(Default::default);
+        if enabled && custom_options.addresses.is_empty() {
+            if servers.is_empty() {
+                return;
+            }
+            custom_options.addresses = servers;
+        }
+        dns_options.set_state(match enabled {
+            true => proto::dns_options::DnsState::Custom,
+            false => proto::dns_options::DnsState::Default,
+        });
+        self.set_dns_options("custom DNS", dns_options).await;
+    }
+
+    async fn set_dns_options(&mut self, description: &'static str, dns_options: proto::DnsOptions) {
+        let _ = self
+            .change_setting(
+                description,
+                dns_options_mut,
+                Some(dns_options),
+                |mut client, dns_options| async move {
+                    client.set_dns_options(dns_options.unwrap_or_default()).await
+                },
+            )
+            .await;
+    }
+
+    async fn change_obfuscation(&mut self, f: impl FnOnce(&mut proto::ObfuscationSettings)) {
+        let mut obfuscation_settings =
+            self.settings.obfuscation_settings.clone().unwrap_or_default();
+        f(&mut obfuscation_settings);
+        // obfuscation is only set up when the tunnel is established
+        let reconnect = self.tunnel_is_up();
+        let result = self
+            .change_setting(
+                "obfuscation",
+                |settings| &mut settings.obfuscation_settings,
+                Some(obfuscation_settings),
+                |mut client, obfuscation_settings| async move {
+                    client.set_obfuscation_settings(obfuscation_settings.unwrap_or_default()).await
+                },
+            )
+            .await;
+        if result.is_ok() && reconnect {
+            self.reconnect_tunnel().await;
+        }
+    }
+
+    async fn set_quantum_resistant_tunnel(&mut self, state: QuantumResistant) {
+        let mut quantum_resistant = proto::QuantumResistantState::default();
+        quantum_resistant.set_state(state.into());
+        let _ = self
+            .change_setting(
+                "quantum-resistant tunnel",
+                |settings| &mut wireguard_options_mut(settings).quantum_resistant,
+                Some(quantum_resistant),
+                |mut client, quantum_resistant| async move {
+                    client.set_quantum_resistant_tunnel(quantum_resistant.unwrap_or_default()).await
+                },
+            )
+            .await;
+    }
+
+    async fn set_daita(&mut self, enabled: bool) {
+        let result = self
+            .change_setting(
+                "DAITA",
+                |settings| &mut wireguard_options_mut(settings).daita,
+                Some(proto::DaitaSettings { enabled }),
+                |mut client, daita| async move {
+                    client.set_daita_settings(daita.unwrap_or_default()).await
+                },
+            )
+            .await;
+        // the version has DAITA but this build of the daemon doesn't
+        if result.is_err_and(|e| e.code() == tonic::Code::Unimplemented) {
+            self.tray_handle.update(|tray: &mut MulltrayApp| tray.capabilities.daita = false);
+        }
+    }
+
+    async fn set_wireguard_mtu(&mut self, mtu: Option<u32>) {
+        // the MTU is only applied when the tunnel is set up
+        let reconnect = self.tunnel_is_up();
+        let result = self
+            .change_setting(
+                "MTU",
+                |settings| &mut wireguard_options_mut(settings).mtu,
+                mtu,
+                // 0 tells the daemon to pick the MTU itself
+                |mut client, mtu| async move { client.set_wireguard_mtu(mtu.unwrap_or(0)).await },
+            )
+            .await;
+        if result.is_ok() && reconnect {
+            self.reconnect_tunnel().await;
+        }
+    }
+
+    async fn set_bridge_state(&mut self, state: BridgeState) {
+        let state = proto::bridge_state::State::from(state);
+        let _ = self
+            .change_setting(
+                "bridge mode",
+                |settings| &mut settings.bridge_state,
+                Some(proto::BridgeState { state: state.into() }),
+                |mut client, bridge_state| async move {
+                    client.set_bridge_state(bridge_state.unwrap_or_default()).await
+                },
+            )
+            .await;
+    }
+
+    async fn set_location(
+        &mut self,
+        kind: LocationKind,
+        location: Option<LocationConstraint>,
+        connect: bool,
+    ) {
+        let location = location.map(proto::LocationConstraint::from);
+        match kind {
+            LocationKind::Exit => {
+                self.change_relay_settings(connect, |norm| norm.location = location).await
+            }
+            LocationKind::Entry => {
+                self.change_relay_settings(connect, |norm| {
+                    let wireguard_constraints =
+                        norm.wireguard_constraints.get_or_insert_with(Default::default);
+                    wireguard_constraints.entry_location = location
+                })
+                .await
+            }
+            LocationKind::Bridge => {
+                let mut bridge_settings = self.settings.bridge_settings.clone().unwrap_or_default();
+                bridge_settings.bridge_type = proto::bridge_settings::BridgeType::Normal.into();
+                bridge_settings.normal.get_or_insert_with(Default::default).location = location;
+                let _ = self
+                    .change_setting(
+                        "bridge location",
+                        |settings| &mut settings.bridge_settings,
+                        Some(bridge_settings),
+                        |mut client, bridge_settings| async move {
+                            client.set_bridge_settings(bridge_settings.unwrap_or_default()).await
+                        },
+                    )
+                    .await;
+            }
+        }
+    }
+
+    /// Modifies the relay settings and sends them to the daemon
+    async fn update_relay_settings(&mut self, f: impl FnOnce(&mut proto::NormalRelaySettings)) {
+        self.change_relay_settings(false, f).await;
+    }
+
+    /// Like [`Self::update_relay_settings`], and with `connect` it also connects if the
+    /// tunnel is down once the daemon has the new settings
+    async fn change_relay_settings(
+        &mut self,
+        connect: bool,
+        f: impl FnOnce(&mut proto::NormalRelaySettings),
+    ) {
+        let Some(mut norm) = normal_relay_settings(&self.settings).cloned() else {
+            warn!("Unsupported relay settings (only Normal settings are supported at this time)");
+            return;
+        };
+        f(&mut norm);
+        let relay_settings = proto::RelaySettings {
+            endpoint: Some(proto::relay_settings::Endpoint::Normal(norm)),
+        };
+        // the daemon doesn't always switch relays on its own so we need to reconnect
+        // for the new settings to take effect
+        let reconnect = self.tunnel_is_up();
+        let result = self
+            .change_setting(
+                "relay settings",
+                |settings| &mut settings.relay_settings,
+                Some(relay_settings),
+                |mut client, relay_settings| async move {
+                    client.set_relay_settings(relay_settings.unwrap_or_default()).await
+                },
+            )
+            .await;
+        if result.is_err() {
+            return;
+        }
+        if reconnect {
+            self.reconnect_tunnel().await;
+        } else if connect {
+            self.connect_tunnel().await;
+        }
+    }
+
+    async fn add_to_custom_list(
+        &mut self,
+        id: String,
+        location: GeographicLocation,
+    ) {
+        let location = proto::GeographicLocationConstraint::from(location);
+        let mut custom_lists = self.settings.custom_lists.clone().unwrap_or_default();
+        let Some(list) = custom_lists.custom_lists.iter_mut().find(|list| list.id == id) else {
+            return;
+        };
+        if list.locations.contains(&location) {
+            return;
+        }
+        list.locations.push(location);
+        let list = list.clone();
+        let _ = self
+            .change_setting(
+                "custom list",
+                |settings| &mut settings.custom_lists,
+                Some(custom_lists),
+                move |mut client, _| {
+                    let list = list.clone();
+                    async move { client.update_custom_list(list).await }
+                },
+            )
+            .await;
+    }
+
+    /// The tray already shows `id` as the method in use
+    async fn set_api_access_method(&mut self, id: String) {
+        let id = proto::Uuid { value: id };
+        let client = self.client.clone();
+        let result = with_retries(|| {
+            let (mut client, id) = (client.clone(), id.clone());
+            async move { client.set_api_access_method(id).await }
+        })
+        .await;
+        let Err(e) = result else {
+            return;
+        };
+        warn!("Could not change API access method: {}", e.message());
+        let current = self.client.get_current_api_access_method(()).await;
+        self.tray_handle.update(|tray: &mut MulltrayApp| {
+            tray.api_access_method =
+                current.ok().and_then(|current| AccessMethod::try_from(current.into_inner()).ok());
+        });
+        self.notifier.notify("Could not change API access method".into(), e.message().into());
+    }
+}
+
+async fn load_locations(client: Client, tray_handle: ksni::Handle<MulltrayApp>) {
+    let result = with_retries(|| {
+        let mut client = client.clone();
+        async move { client.get_relay_locations(()).await }
+    })
+    .await;
+    tray_handle.update(|tray: &mut MulltrayApp| match result {
+        Ok(locations) => tray.set_locations(locations.into_inner().into()),
+        Err(e) => {
+            warn!("Could not fetch the relay list: {}", e.message());
+            tray.locations_status = LocationsStatus::Failed;
+        }
+    });
+}
+
+async fn log_in(mut client: Client, notifier: Notifier, account_number: String) {
+    if let Err(e) = client.login_account(account_number).await {
+        warn!("Could not log in: {}", e.message());
+        let body = match e.code() {
+            tonic::Code::ResourceExhausted => {
+                "There are too many devices on the account, remove one first".into()
+            }
+            _ => e.message().into(),
+        };
+        notifier.notify("Could not log in".into(), body);
+    }
+}
+
+async fn log_out(mut client: Client, notifier: Notifier) {
+    if let Err(e) = client.logout_account(()).await {
+        warn!("Could not log out: {}", e.message());
+        notifier.notify("Could not log out".into(), e.message().into());
+    }
+}
+
+async fn redeem_voucher(
+    mut client: Client,
+    notifier: Notifier,
+    tray_handle: ksni::Handle<MulltrayApp>,
+    voucher: String,
+) {
+    match client.submit_voucher(voucher).await {
+        Ok(response) => {
+            let submission = response.into_inner();
+            let added = Duration::from_secs(submission.seconds_added);
+            notifier.notify(
+                "Voucher redeemed".into(),
+                format!("Added {} to the account", format_time_remaining(added)),
+            );
+            let new_expiry =
+                submission.new_expiry.and_then(|expiry| SystemTime::try_from(expiry).ok());
+            if let Some(new_expiry) = new_expiry {
+                tray_handle.update(|tray: &mut MulltrayApp| {
+                    tray.account_expiry = AccountExpiry::At(new_expiry)
+                });
+            }
+        }
+        Err(e) => {
+            warn!("Could not redeem voucher: {}", e.message());
+            let body = match e.code() {
+                tonic::Code::NotFound => "The voucher code is invalid".into(),
+                tonic::Code::ResourceExhausted => "The voucher has already been used".into(),
+                tonic::Code::Unavailable => "Could not reach the Mullvad API".into(),
+                _ => e.message().into(),
+            };
+            notifier.notify("Could not redeem voucher".into(), body);
+        }
+    }
+}
+
+async fn rotate_wireguard_key(mut client: Client, notifier: Notifier) {
+    // the key age is updated by the device event that follows
+    if let Err(e) = client.rotate_wireguard_key(()).await {
+        warn!("Could not rotate the WireGuard key: {}", e.message());
+        let body = match e.code() {
+            tonic::Code::Unavailable => "The Mullvad API can't be reached".into(),
+            tonic::Code::ResourceExhausted => {
+                "The key was rotated too recently, try again later".into()
+            }
+            _ => e.message().into(),
+        };
+        notifier.notify("Could not rotate WireGuard key".into(), body);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::testing::{
+        connected, relay_list, settings, tunnel_state, MockDaemon, MockServer, TestTray,
+    };
+
+    fn exit_location(hostname: &str) -> LocationConstraint {
+        LocationConstraint::Location(GeographicLocation {
+            country: "se".into(),
+            city: Some("got".into()),
+            hostname: Some(hostname.into()),
+        })
+    }
+
+    fn daemon_exit_location(daemon: &MockDaemon) -> Option<LocationConstraint> {
+        let location = normal_relay_settings(&daemon.state().settings)?.location.clone()?;
+        LocationConstraint::try_from(location).ok()
+    }
+
+    /// A tray in sync with a daemon that has `settings()`
+    async fn synced_tray(daemon: &MockDaemon) -> (TestTray, MockServer) {
+        synced_tray_with(daemon, Config::default()).await
+    }
+
+    async fn synced_tray_with(daemon: &MockDaemon, config: Config) -> (TestTray, MockServer) {
+        let tray = TestTray::new(config);
+        daemon.state().settings = settings();
+        let server = tray.serve(daemon);
+        tray.sync_with_daemon();
+        tray.wait_for("the settings", |tray| tray.relay_settings().is_some()).await;
+        (tray, server)
+    }
+
+    #[tokio::test]
+    async fn commands_reach_the_daemon_in_order() {
+        let daemon = MockDaemon::default();
+        let (tray, _server) = synced_tray(&daemon).await;
+        // a slow change would lose the race to anything sent after it if they weren't queued
+        daemon.state().delays.insert("set_relay_settings", Duration::from_millis(200));
+
+        let location = Some(exit_location("se-got-wg-001"));
+        tray.send(Command::SetLocation { kind: LocationKind::Exit, location, connect: false });
+        tray.send(Command::Connect);
+        tray.wait_for("the connect request", |_| daemon.calls().contains(&"connect_tunnel")).await;
+        assert_eq!(daemon.calls(), ["set_relay_settings", "connect_tunnel"]);
+        assert_eq!(daemon_exit_location(&daemon), Some(exit_location("se-got-wg-001")));
+    }
+
+    #[tokio::test]
+    async fn changes_build_on_each_other() {
+        let daemon = MockDaemon::default();
+        let (tray, _server) = synced_tray(&daemon).await;
+        daemon.state().delays.insert("set_allow_lan", Duration::from_millis(200));
+
+        tray.send(Command::SetAllowLan(true));
+        tray.send(Command::SetMultihop(true));
+        // both show up right away, before the daemon has answered
+        tray.wait_for("the changes", |tray| {
+            let multihop = tray.relay_settings().is_some_and(|relay| relay.use_multihop);
+            tray.settings.allow_lan && multihop
+        })
+        .await;
+        tray.wait_for("the daemon", |_| daemon.calls().len() == 2).await;
+        let state = daemon.state();
+        assert!(state.settings.allow_lan);
+        let norm = normal_relay_settings(&state.settings).unwrap();
+        assert!(norm.wireguard_constraints.as_ref().unwrap().use_multihop);
+    }
+
+    #[tokio::test]
+    async fn the_last_location_clicked_wins() {
+        let daemon = MockDaemon::default();
+        let (tray, _server) = synced_tray(&daemon).await;
+        let click = |hostname: &str| {
+            let hostname = Some(hostname.to_string());
+            tray.handle.update(|tray: &mut MulltrayApp| {
+                tray.set_location(LocationKind::Exit, "se".into(), Some("got".into()), hostname)
+            });
+        };
+
+        // the first one is still on its way to the daemon when the second one is clicked,
+        // and would overwrite it if they were sent side by side
+        daemon.state().delays.insert("set_relay_settings", Duration::from_millis(300));
+        click("se-got-wg-001");
+        tray.wait_for("the first request", |_| !daemon.calls().is_empty()).await;
+        daemon.state().delays.clear();
+        click("se-got-wg-002");
+        tray.wait_for("the second request", |_| {
+            daemon.calls().iter().filter(|call| **call == "set_relay_settings").count() == 2
+        })
+        .await;
+        assert_eq!(daemon_exit_location(&daemon), Some(exit_location("se-got-wg-002")));
+        let shown = |tray: &MulltrayApp| tray.location_constraint(LocationKind::Exit).cloned();
+        assert_eq!(tray.read(shown), Some(Some(exit_location("se-got-wg-002"))));
+    }
+
+    #[tokio::test]
+    async fn stale_settings_do_not_undo_changes() {
+        let daemon = MockDaemon::default();
+        let (tray, _server) = synced_tray(&daemon).await;
+        let stale = daemon.state().settings.clone();
+
+        tray.send(Command::SetAllowLan(true));
+        // e.g. an event from before the change that was slow to arrive
+        tray.send(Command::DaemonSettings(Box::new(stale)));
+        tray.send(Command::Connect);
+        tray.wait_for("the connect request", |_| daemon.calls().contains(&"connect_tunnel")).await;
+        assert!(tray.read(|tray| tray.settings.allow_lan));
+    }
+
+    #[tokio::test]
+    async fn failed_changes_show_the_daemon_settings() {
+        let daemon = MockDaemon::default();
+        let (tray, _server) = synced_tray(&daemon).await;
+        daemon.state().unimplemented.push("set_allow_lan");
+        // changed behind our back, the failed change fetches this
+        daemon.state().settings.auto_connect = true;
+
+        tray.send(Command::SetAllowLan(true));
+        tray.wait_for("the daemon settings", |tray| tray.settings.auto_connect).await;
+        assert!(!tray.read(|tray| tray.settings.allow_lan));
+    }
+
+    #[tokio::test]
+    async fn failed_tunnel_commands_are_shown() {
+        let daemon = MockDaemon::default();
+        let (tray, _server) = synced_tray(&daemon).await;
+        daemon.state().unimplemented.push("connect_tunnel");
+
+        tray.send(Command::Connect);
+        tray.wait_for("the failure", |tray| tray.command_failure.is_some()).await;
+        tray.read(|tray| {
+            let failure = tray.command_failure.as_deref().unwrap();
+            assert!(failure.starts_with("connect failed"), "{failure}");
+            assert_eq!(tray.last_error, tray.command_failure);
+        });
+    }
+
+    #[tokio::test]
+    async fn location_changes_reconnect_when_connected() {
+        let daemon = MockDaemon::default();
+        daemon.state().tunnel_state = tunnel_state(connected("se-got-wg-001"));
+        let (tray, _server) = synced_tray(&daemon).await;
+        tray.wait_for("connected", |tray| AppState::tunnel_is_up(tray.app_state.name())).await;
+
+        let location = Some(exit_location("se-got-wg-002"));
+        tray.send(Command::SetLocation { kind: LocationKind::Exit, location, connect: true });
+        tray.wait_for("the reconnect", |_| daemon.calls().len() == 2).await;
+        assert_eq!(daemon.calls(), ["set_relay_settings", "reconnect_tunnel"]);
+    }
+
+    #[tokio::test]
+    async fn connecting_to_the_fastest_relay_connects_once() {
+        let daemon = MockDaemon::default();
+        {
+            let mut state = daemon.state();
+            state.device_state.set_state(proto::device_state::State::LoggedIn);
+            state.relay_list = relay_list(&["se-got-wg-001", "se-got-wg-002"]);
+        }
+        // picking a location connects by itself then
+        let config = Config { apply_location_immediately: true, ..Config::default() };
+        let (tray, _server) = synced_tray_with(&daemon, config).await;
+        tray.wait_for("the relay list", |tray| !tray.locations.countries.is_empty()).await;
+        tray.handle.update(|tray: &mut MulltrayApp| {
+            tray.latencies.record("se-got-wg-001".into(), Some(Duration::from_millis(40)));
+            tray.latencies.record("se-got-wg-002".into(), Some(Duration::from_millis(20)));
+            tray.connect_to_fastest();
+        });
+        // anything the fastest relay sent comes before this
+        tray.send(Command::SetAllowLan(true));
+        tray.wait_for("the last request", |_| daemon.calls().contains(&"set_allow_lan")).await;
+        assert_eq!(daemon.calls(), ["set_relay_settings", "connect_tunnel", "set_allow_lan"]);
+        assert_eq!(daemon_exit_location(&daemon), Some(exit_location("se-got-wg-002")));
+    }
+
+    #[tokio::test]
+    async fn failed_reconnects_after_a_change_are_shown() {
+        let daemon = MockDaemon::default();
+        {
+            let mut state = daemon.state();
+            state.tunnel_state = tunnel_state(connected("se-got-wg-001"));
+            state.unimplemented.push("reconnect_tunnel");
+        }
+        let (tray, _server) = synced_tray(&daemon).await;
+        tray.wait_for("connected", |tray| AppState::tunnel_is_up(tray.app_state.name())).await;
+
+        tray.send(Command::SetMultihop(true));
+        tray.wait_for("the failure", |tray| tray.command_failure.is_some()).await;
+        let failure = tray.read(|tray| tray.command_failure.clone()).unwrap();
+        assert!(failure.starts_with("reconnect failed"), "{failure}");
+    }
+
+    #[test]
+    fn providers_are_picked_out_of_all_of_them() {
+        let all = ["31173".to_string(), "M247".into(), "Tzulo".into()];
+        let check = |providers: &[&str], provider, enabled, expected: Option<&[&str]>| {
+            let providers: Vec<String> = providers.iter().map(|p| p.to_string()).collect();
+            let expected = expected.map(|e| e.iter().map(|p| p.to_string()).collect());
+            assert_eq!(
+                providers_with(&providers, provider, enabled, &all),
+                expected,
+                "{providers:?} {provider} {enabled}"
+            );
+        };
+        check(&[], "M247", false, Some(&["31173", "Tzulo"]));
+        check(&["31173", "Tzulo"], "Tzulo", false, Some(&["31173"]));
+        check(&["31173"], "M247", true, Some(&["31173", "M247"]));
+        // enabling every provider is the same as any provider
+        check(&["31173", "M247"], "Tzulo", true, Some(&[]));
+        check(&[], "M247", true, Some(&[]));
+        // disabling the last one would enable them all
+        check(&["M247"], "M247", false, None);
+    }
+
+    #[tokio::test]
+    async fn the_last_provider_is_not_disabled() {
+        let daemon = MockDaemon::default();
+        let (tray, _server) = synced_tray(&daemon).await;
+        let all_providers = vec!["M247".to_string(), "Tzulo".into()];
+        let set_provider_enabled = |provider: &str, enabled| Command::SetProviderEnabled {
+            provider: provider.into(),
+            enabled,
+            all_providers: all_providers.clone(),
+        };
+        let providers = |daemon: &MockDaemon| {
+            normal_relay_settings(&daemon.state().settings).unwrap().providers.clone()
+        };
+
+        tray.send(set_provider_enabled("Tzulo", false));
+        tray.send(set_provider_enabled("M247", false));
+        tray.send(Command::SetAllowLan(true));
+        tray.wait_for("the last request", |_| daemon.calls().contains(&"set_allow_lan")).await;
+        assert_eq!(daemon.calls(), ["set_relay_settings", "set_allow_lan"]);
+        assert_eq!(providers(&daemon), ["M247"]);
+        let shown = |tray: &MulltrayApp| tray.relay_settings().map(|norm| norm.providers.clone());
+        assert_eq!(tray.read(shown), Some(vec!["M247".to_string()]));
+    }
+
+    #[test]
+    fn the_udp2tcp_port_is_set_in_place() {
+        use proto::obfuscation_settings::SelectedObfuscation;
+        let mut obfuscation_settings = proto::ObfuscationSettings::default();
+        obfuscation_settings.set_selected_obfuscation(SelectedObfuscation::Udp2tcp);
+        with_udp2tcp_port(&mut obfuscation_settings, Some(80));
+        let expected = proto::Udp2TcpObfuscationSettings { port: Some(80) };
+        assert_eq!(obfuscation_settings.udp2tcp, Some(expected));
+        with_udp2tcp_port(&mut obfuscation_settings, None);
+        assert_eq!(obfuscation_settings.udp2tcp, Some(Default::default()));
+        assert_eq!(obfuscation_settings.selected_obfuscation(), SelectedObfuscation::Udp2tcp);
+    }
+
+    #[tokio::test]
+    async fn udp2tcp_ports_reach_the_daemon() {
+        use proto::obfuscation_settings::SelectedObfuscation;
+        let daemon = MockDaemon::default();
+        let (tray, _server) = synced_tray(&daemon).await;
+        tray.send(Command::SetObfuscation(ObfuscationMode::Udp2Tcp));
+        tray.send(Command::SetUdp2TcpPort(Some(5001)));
+        tray.wait_for("the port", |tray| tray.settings.udp2tcp_port == Some(5001)).await;
+        tray.wait_for("the daemon", |_| daemon.calls().len() == 2).await;
+
+        let obfuscation_settings = daemon.state().settings.obfuscation_settings.clone().unwrap();
+        assert_eq!(obfuscation_settings.selected_obfuscation(), SelectedObfuscation::Udp2tcp);
+        assert_eq!(obfuscation_settings.udp2tcp.and_then(|udp2tcp| udp2tcp.port), Some(5001));
+        assert_eq!(tray.read(|tray| tray.settings.obfuscation), ObfuscationMode::Udp2Tcp);
+    }
+}
@@ -0,0 +1,310 @@
+//! User configuration, read from `$XDG_CONFIG_HOME/mulltray/config.toml`
+
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Whether notifications are shown at startup, they can still be toggled from the menu
+    pub notifications: bool,
+    pub icon_theme: IconTheme,
+    /// Whether the location menu lists individual relays or stops at cities
+    pub show_relays: bool,
+    /// Path to the management socket of the daemon, the command line takes precedence
+    pub socket_path: Option<PathBuf>,
+    /// DNS servers that can be picked from the "Custom DNS" menu
+    pub dns_servers: Vec<DnsServer>,
+    /// File with the account number to log in with, instead of asking for it in a dialog
+    pub account_number_file: Option<PathBuf>,
+    /// Whether to notify when a new version of the Mullvad app is available
+    pub update_notifications: bool,
+    /// Whether latency to the relays is measured at startup, it can still be toggled from
+    /// the menu. Off by default because it connects to every relay now and then.
+    pub measure_latency: bool,
+    /// Order of the countries, cities and relays in the location menus
+    pub sort_locations: SortLocations,
+    /// Template for the tray title such as `"{state} {hostname} {city}"`, with the
+    /// placeholders `state`, `hostname`, `city`, `country`, `exit_ip` and `duration`
+    pub title_format: Option<String>,
+    /// Whether the icon is marked as passive while disconnected, which lets panels that
+    /// support it hide the icon until there's something to show
+    pub hide_when_disconnected: bool,
+    /// Scrolling up on the icon connects and scrolling down disconnects, unless this is set
+    pub invert_scroll: bool,
+    /// Whether the status menu shows how much data went through the tunnel since connecting
+    pub show_usage: bool,
+    /// Whether choosing a location also connects, it can be toggled from the menu
+    pub apply_location_immediately: bool,
+    /// Whether country names are shown in the language of the locale instead of in English,
+    /// with the translations of the iso-codes package
+    pub translate_country_names: bool,
+    /// Whether to connect when mulltray starts and the tunnel is down, unlike the daemon's
+    /// auto-connect which connects when the daemon starts
+    pub connect_on_start: bool,
+    /// Whether the last exit location chosen in mulltray is set again when the daemon has
+    /// none, e.g. after its settings were reset
+    pub restore_location: bool,
+    /// Whether the countries and cities in the location menus show how many relays they have
+    pub show_relay_counts: bool,
+    /// dmenu-style command for "Search location…" such as `["rofi", "-dmenu"]`, by default
+    /// the first of rofi, fuzzel, wofi and dmenu that is installed
+    pub chooser: Option<Vec<String>>,
+    /// Whether to bring the tunnel back up after resuming from suspend if it was up before
+    /// and the daemon hasn't reconnected by itself within `resume_grace_period`
+    pub reconnect_after_resume: bool,
+    /// Seconds the tunnel may stay down after resuming before mulltray reconnects
+    pub resume_grace_period: u64,
+    /// Sections of the menu to leave out, such as `["settings"]`
+    pub hide_menu_sections: Vec<MenuSection>,
+    /// Whether the menu only has a connect/disconnect item and Quit. The relay list isn't
+    /// fetched then, which saves a large request and makes the menu quick to open.
+    pub minimal_menu: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            notifications: true,
+            icon_theme: IconTheme::default(),
+            show_relays: true,
+            socket_path: None,
+            dns_servers: vec![],
+            account_number_file: None,
+            update_notifications: true,
+            measure_latency: false,
+            sort_locations: SortLocations::default(),
+            title_format: None,
+            hide_when_disconnected: false,
+            invert_scroll: false,
+            show_usage: false,
+            apply_location_immediately: false,
+            translate_country_names: false,
+            connect_on_start: false,
+            restore_location: false,
+            show_relay_counts: true,
+            chooser: None,
+            reconnect_after_resume: false,
+            resume_grace_period: 20,
+            hide_menu_sections: vec![],
+            minimal_menu: false,
+        }
+    }
+}
+
+/// Which icons to use: variants of the freedesktop `network-vpn` icons, or our own
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IconTheme {
+    /// Monochrome icons that follow the panel's color scheme
+    #[default]
+    Symbolic,
+    /// Full color icons
+    Color,
+    /// Shield icons shipped with mulltray, for icon themes without the `network-vpn` icons
+    Bundled,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortLocations {
+    #[default]
+    Alphabetical,
+    /// Lowest measured latency first
+    Latency,
+    /// Most recently connected to first
+    Recent,
+}
+
+impl SortLocations {
+    /// The value as written in the config file
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SortLocations::Alphabetical => "alphabetical",
+            SortLocations::Latency => "latency",
+            SortLocations::Recent => "recent",
+        }
+    }
+}
+
+/// The parts of the menu that can be hidden. The last part, with "Quit", can't be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MenuSection {
+    /// The device, the account and the WireGuard key
+    Account,
+    /// The state of the tunnel and its details
+    Status,
+    /// Connecting and disconnecting
+    Actions,
+    /// Favorites and the location menus
+    Location,
+    Settings,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DnsServer {
+    pub name: String,
+    pub addresses: Vec<IpAddr>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    /// Parsed fine, but doesn't make sense
+    Invalid(PathBuf, String),
+    Edit(PathBuf, toml_edit::TomlError),
+    Write(PathBuf, std::io::Error),
+    /// There is no home directory to put the config file in
+    NoConfigDir,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Read(path, e) => write!(f, "Could not read {}: {}", path.display(), e),
+            Error::Parse(path, e) => write!(f, "Invalid config file {}: {}", path.display(), e),
+            Error::Invalid(path, e) => write!(f, "Invalid config file {}: {}", path.display(), e),
+            Error::Edit(path, e) => write!(f, "Invalid config file {}: {}", path.display(), e),
+            Error::Write(path, e) => write!(f, "Could not write {}: {}", path.display(), e),
+            Error::NoConfigDir => write!(f, "Could not find the config directory"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// `$XDG_CONFIG_HOME`, falling back to `~/.config`
+pub fn config_home() -> Option<PathBuf> {
+    match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => Some(PathBuf::from(std::env::var_os("HOME")?).join(".config")),
+    }
+}
+
+/// `$XDG_CONFIG_HOME/mulltray`, falling back to `~/.config/mulltray`
+pub fn config_dir() -> Option<PathBuf> {
+    Some(config_home()?.join("mulltray"))
+}
+
+impl Config {
+    /// Reads the config file, using the defaults if there is none
+    pub fn load() -> Result<Self, Error> {
+        let Some(path) = config_dir().map(|dir| dir.join("config.toml")) else {
+            return Ok(Config::default());
+        };
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(Error::Read(path, e)),
+        };
+        Self::parse(path, &contents)
+    }
+
+    fn parse(path: PathBuf, contents: &str) -> Result<Self, Error> {
+        let config: Config = match toml::from_str(contents) {
+            Ok(config) => config,
+            Err(e) => return Err(Error::Parse(path, e)),
+        };
+        match config.validate() {
+            Ok(()) => Ok(config),
+            Err(e) => Err(Error::Invalid(path, e)),
+        }
+    }
+
+    /// What serde can't check by itself
+    fn validate(&self) -> Result<(), String> {
+        // picking one without addresses would leave the daemon with no DNS servers at all
+        match self.dns_servers.iter().find(|server| server.addresses.is_empty()) {
+            Some(server) => Err(format!("the DNS server \"{}\" has no addresses", server.name)),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets a top-level option in the config file, keeping the rest of the file (and its
+    /// comments) as it is
+    pub fn save_option(key: &str, value: impl Into<toml_edit::Value>) -> Result<(), Error> {
+        let dir = config_dir().ok_or(Error::NoConfigDir)?;
+        let path = dir.join("config.toml");
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(Error::Read(path, e)),
+        };
+        let mut document: toml_edit::DocumentMut = match contents.parse() {
+            Ok(document) => document,
+            Err(e) => return Err(Error::Edit(path, e)),
+        };
+        document[key] = toml_edit::value(value);
+        std::fs::create_dir_all(&dir).map_err(|e| Error::Write(dir, e))?;
+        std::fs::write(&path, document.to_string()).map_err(|e| Error::Write(path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Result<Config, Error> {
+        Config::parse("config.toml".into(), contents)
+    }
+
+    #[test]
+    fn dns_servers_are_parsed() {
+        let config = parse(
+            r#"
+            [[dns_servers]]
+            name = "Quad9"
+            addresses = ["9.9.9.9", "2620:fe::fe"]
+
+            [[dns_servers]]
+            name = "Home"
+            addresses = ["192.168.1.1"]
+            "#,
+        )
+        .unwrap();
+        let servers: Vec<(&str, Vec<String>)> = config
+            .dns_servers
+            .iter()
+            .map(|server| {
+                let addresses = server.addresses.iter().map(IpAddr::to_string).collect();
+                (server.name.as_str(), addresses)
+            })
+            .collect();
+        assert_eq!(
+            servers,
+            [
+                ("Quad9", vec!["9.9.9.9".to_string(), "2620:fe::fe".into()]),
+                ("Home", vec!["192.168.1.1".to_string()]),
+            ]
+        );
+        assert!(config.dns_servers[0].addresses[1].is_ipv6());
+    }
+
+    #[test]
+    fn broken_dns_servers_are_refused() {
+        let cases = [
+            ("9.9.9.300", "invalid IP address syntax"),
+            ("dns.quad9.net", "invalid IP address syntax"),
+            ("", "invalid IP address syntax"),
+        ];
+        for (address, expected) in cases {
+            let contents =
+                format!("dns_servers = [{{ name = \"Quad9\", addresses = [\"{address}\"] }}]");
+            let error = parse(&contents).unwrap_err().to_string();
+            assert!(error.contains(expected), "{address:?}: {error}");
+        }
+
+        let error = parse("dns_servers = [{ name = \"Nothing\", addresses = [] }]").unwrap_err();
+        assert!(matches!(error, Error::Invalid(..)), "{error}");
+        assert_eq!(
+            error.to_string(),
+            "Invalid config file config.toml: the DNS server \"Nothing\" has no addresses"
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! A D-Bus interface for controlling mulltray from scripts, e.g.
+//! `busctl --user call io.github.mulltray /io/github/mulltray io.github.mulltray Connect`
+//!
+//! Owning the bus name also keeps a second mulltray from starting. The bus releases the name
+//! when the process exits however it exits, so there is no stale lock to worry about.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use dbus::blocking::stdintf::org_freedesktop_dbus::RequestNameReply;
+use dbus::blocking::Connection;
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::message::MatchRule;
+use dbus::Message;
+use dbus_crossroads::{Crossroads, MethodErr};
+use tokio::sync::watch;
+use tracing::warn;
+
+use crate::app::{LocationKind, MulltrayApp};
+
+const BUS_NAME: &str = "io.github.mulltray";
+const INTERFACE: &str = "io.github.mulltray";
+const OBJECT_PATH: &str = "/io/github/mulltray";
+// how often the stop flag and the state are checked while waiting for calls
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How long the instance being replaced gets to quit, it waits for its daemon requests first
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum Error {
+    /// Another mulltray owns the bus name
+    AlreadyRunning,
+    DBus(dbus::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::AlreadyRunning => write!(f, "mulltray is already running"),
+            Error::DBus(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<dbus::Error> for Error {
+    fn from(e: dbus::Error) -> Self {
+        Error::DBus(e)
+    }
+}
+
+fn request_name(conn: &Connection) -> Result<bool, dbus::Error> {
+    let reply = conn.request_name(BUS_NAME, false, true, true)?;
+    Ok(reply == RequestNameReply::PrimaryOwner)
+}
+
+/// Claims the bus name of mulltray. With `replace` the instance that has it is asked to quit
+/// first.
+///
+/// This blocks while waiting for the other instance to quit.
+pub fn claim_bus_name(replace: bool) -> Result<Connection, Error> {
+    let conn = Connection::new_session()?;
+    if request_name(&conn)? {
+        return Ok(conn);
+    }
+    if !replace {
+        return Err(Error::AlreadyRunning);
+    }
+    let proxy = conn.with_proxy(BUS_NAME, OBJECT_PATH, Duration::from_secs(5));
+    proxy.method_call::<(), _, _, _>(INTERFACE, "Quit", ())?;
+    let started = Instant::now();
+    while started.elapsed() < REPLACE_TIMEOUT {
+        std::thread::sleep(POLL_INTERVAL);
+        if request_name(&conn)? {
+            return Ok(conn);
+        }
+    }
+    Err(Error::AlreadyRunning)
+}
+
+struct Control {
+    tray_handle: ksni::Handle<MulltrayApp>,
+    state: watch::Receiver<&'static str>,
+}
+
+impl Control {
+    fn call(&self, f: impl FnOnce(&mut MulltrayApp) + Send) -> Result<(), MethodErr> {
+        self.tray_handle.update(f);
+        Ok(())
+    }
+}
+
+/// The running D-Bus service, stopped with [`ControlService::shutdown`]
+pub struct ControlService {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+fn register(cr: &mut Crossroads) -> dbus_crossroads::IfaceToken<Control> {
+    cr.register(INTERFACE, |b| {
+        b.signal::<(String,), _>("StateChanged", ("state",));
+        b.property("State").get(|_, control: &mut Control| {
+            Ok(control.state.borrow().to_string())
+        });
+        b.method("Connect", (), (), |_, control: &mut Control, ()| {
+            control.call(|tray| tray.connect())
+        });
+        b.method("Disconnect", (), (), |_, control: &mut Control, ()| {
+            control.call(|tray| tray.disconnect())
+        });
+        b.method("Reconnect", (), (), |_, control: &mut Control, ()| {
+            control.call(|tray| tray.reconnect())
+        });
+        b.method("Quit", (), (), |_, control: &mut Control, ()| {
+            control.call(|tray| tray.quit())
+        });
+        // empty strings leave out the city or relay, an empty country lets the daemon choose
+        b.method(
+            "SetLocation",
+            ("country", "city", "hostname"),
+            (),
+            |_, control: &mut Control, (country, city, hostname): (String, String, String)| {
+                let non_empty = |s: String| Some(s).filter(|s| !s.is_empty());
+                control.call(move |tray| match non_empty(country) {
+                    Some(country) => tray.set_location(
+                        LocationKind::Exit,
+                        country,
+                        non_empty(city),
+                        non_empty(hostname),
+                    ),
+                    None => tray.clear_location(LocationKind::Exit),
+                })
+            },
+        );
+    })
+}
+
+fn serve(conn: Connection, control: Control, stop: &AtomicBool) -> Result<(), dbus::Error> {
+    let mut state = control.state.clone();
+    let mut cr = Crossroads::new();
+    let token = register(&mut cr);
+    cr.insert(OBJECT_PATH, &[token], control);
+    conn.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |msg, conn| {
+            let _ = cr.handle_message(msg, conn);
+            true
+        }),
+    );
+    while !stop.load(Ordering::Relaxed) {
+        conn.process(POLL_INTERVAL)?;
+        if state.has_changed().unwrap_or(false) {
+            let name = state.borrow_and_update().to_string();
+            let signal = Message::new_signal(OBJECT_PATH, INTERFACE, "StateChanged")
+                .map_err(|e| dbus::Error::new_failed(&e))?
+                .append1(name);
+            let _ = conn.send(signal);
+        }
+    }
+    Ok(())
+}
+
+impl ControlService {
+    /// Serves the interface in a thread of its own on the connection from [`claim_bus_name`]
+    pub fn spawn(
+        conn: Connection,
+        tray_handle: ksni::Handle<MulltrayApp>,
+        state: watch::Receiver<&'static str>,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = std::thread::spawn({
+            let stop = stop.clone();
+            move || {
+                let control = Control { tray_handle, state };
+                if let Err(e) = serve(conn, control, &stop) {
+                    warn!("The D-Bus interface stopped working: {}", e);
+                }
+            }
+        });
+        Self { stop, thread }
+    }
+
+    /// Stops serving and gives up the bus name. This blocks for a moment.
+    pub fn shutdown(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
@@ -0,0 +1,285 @@
+//! A unix socket for controlling mulltray from shell scripts without D-Bus, e.g.
+//! `echo connect | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/mulltray.sock`
+//!
+//! Every line is a command and gets a line back, `ok`, the status, or `error: ` and why.
+
+use std::fs::DirBuilder;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, warn};
+
+use crate::app::{LocationKind, MulltrayApp};
+use crate::status::Status;
+
+const SOCKET_NAME: &str = "mulltray.sock";
+const USAGE: &str = "commands are connect, disconnect, reconnect, status and \
+                     set-location [country [city [hostname]]]";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Connect,
+    Disconnect,
+    Reconnect,
+    Status,
+    /// No country lets the daemon choose
+    SetLocation {
+        country: Option<String>,
+        city: Option<String>,
+        hostname: Option<String>,
+    },
+}
+
+fn parse(line: &str) -> Result<Command, String> {
+    let mut words = line.split_whitespace();
+    let Some(name) = words.next() else {
+        return Err(format!("empty command, {USAGE}"));
+    };
+    let args: Vec<String> = words.map(String::from).collect();
+    let command = match name {
+        "connect" => Command::Connect,
+        "disconnect" => Command::Disconnect,
+        "reconnect" => Command::Reconnect,
+        "status" => Command::Status,
+        "set-location" => {
+            let mut args = args.into_iter();
+            let command = Command::SetLocation {
+                country: args.next(),
+                city: args.next(),
+                hostname: args.next(),
+            };
+            if args.next().is_some() {
+                return Err("set-location takes a country, a city and a hostname".into());
+            }
+            return Ok(command);
+        }
+        _ => return Err(format!("unknown command {name:?}, {USAGE}")),
+    };
+    if !args.is_empty() {
+        return Err(format!("{name} doesn't take arguments"));
+    }
+    Ok(command)
+}
+
+/// Runs a command and returns the reply, without the line break
+fn run(command: Command, tray_handle: &ksni::Handle<MulltrayApp>) -> String {
+    // the daemon's answer to the commands is reflected in the status, not in the reply,
+    // just like when one is picked from the menu
+    tray_handle.update(|tray: &mut MulltrayApp| match command {
+        Command::Connect => {
+            tray.connect();
+            "ok".into()
+        }
+        Command::Disconnect => {
+            tray.disconnect();
+            "ok".into()
+        }
+        Command::Reconnect => {
+            tray.reconnect();
+            "ok".into()
+        }
+        Command::Status => Status::new(&tray.app_state).text(),
+        Command::SetLocation { country: None, .. } => {
+            tray.clear_location(LocationKind::Exit);
+            "ok".into()
+        }
+        Command::SetLocation { country: Some(country), city, hostname } => {
+            tray.set_location(LocationKind::Exit, country, city, hostname);
+            "ok".into()
+        }
+    })
+}
+
+async fn handle(stream: UnixStream, tray_handle: ksni::Handle<MulltrayApp>) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let reply = match parse(&line) {
+            Ok(command) => run(command, &tray_handle),
+            Err(e) => format!("error: {e}"),
+        };
+        writer.write_all(format!("{reply}\n").as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// Binds a socket at `private_path` that only the user can connect to and moves it to `path`
+fn bind_restricted(private_path: &Path, path: &Path) -> io::Result<UnixListener> {
+    let listener = UnixListener::bind(private_path)?;
+    std::fs::set_permissions(private_path, std::fs::Permissions::from_mode(0o600))?;
+    std::fs::rename(private_path, path)?;
+    Ok(listener)
+}
+
+/// The listening socket, removed again with [`ControlSocket::remove`]
+pub struct ControlSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    /// Listens on `$XDG_RUNTIME_DIR/mulltray.sock`, `None` if that can't be done
+    pub fn bind() -> Option<Self> {
+        let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) else {
+            debug!("XDG_RUNTIME_DIR is not set, not listening for commands on a socket");
+            return None;
+        };
+        let path = PathBuf::from(dir).join(SOCKET_NAME);
+        match Self::bind_at(path.clone()) {
+            Ok(control_socket) => Some(control_socket),
+            Err(e) => {
+                warn!("Could not listen on {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Listens on `path` unless another mulltray already does, which can happen when it
+    /// couldn't claim the D-Bus name
+    fn bind_at(path: PathBuf) -> io::Result<Self> {
+        match std::os::unix::net::UnixStream::connect(&path) {
+            Ok(_) => {
+                let e = "another mulltray is listening on it";
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, e));
+            }
+            // left behind by a mulltray that didn't get to clean up, replaced below
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        // bound in a directory that only the user can get into and moved in place once it's
+        // restricted, so nobody else gets to connect in between
+        let parent = path.parent().unwrap_or(Path::new("."));
+        let private_dir = parent.join(format!(".{}-{}", SOCKET_NAME, std::process::id()));
+        let _ = std::fs::remove_dir_all(&private_dir);
+        DirBuilder::new().mode(0o700).create(&private_dir)?;
+        let listener = bind_restricted(&private_dir.join(SOCKET_NAME), &path);
+        if let Err(e) = std::fs::remove_dir_all(&private_dir) {
+            debug!("Could not remove {}: {}", private_dir.display(), e);
+        }
+        Ok(Self { listener: listener?, path })
+    }
+
+    /// Answers commands until dropped, every connection in a task of its own
+    pub async fn serve(&self, tray_handle: &ksni::Handle<MulltrayApp>) {
+        loop {
+            match self.listener.accept().await {
+                Ok((stream, _)) => {
+                    let tray_handle = tray_handle.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle(stream, tray_handle).await {
+                            debug!("Control socket connection failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    warn!("Could not accept a control socket connection: {}", e);
+                    // e.g. out of file descriptors, which takes a while to change
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    pub fn remove(self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Could not remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::AppState;
+    use crate::config::Config;
+    use crate::testing::TestTray;
+
+    fn set_location(country: &str, city: Option<&str>, hostname: Option<&str>) -> Command {
+        Command::SetLocation {
+            country: Some(country.into()),
+            city: city.map(String::from),
+            hostname: hostname.map(String::from),
+        }
+    }
+
+    #[test]
+    fn commands_are_parsed() {
+        assert_eq!(parse("connect"), Ok(Command::Connect));
+        assert_eq!(parse("  disconnect "), Ok(Command::Disconnect));
+        assert_eq!(parse("reconnect"), Ok(Command::Reconnect));
+        assert_eq!(parse("status"), Ok(Command::Status));
+        let automatic = Command::SetLocation { country: None, city: None, hostname: None };
+        assert_eq!(parse("set-location"), Ok(automatic));
+        assert_eq!(parse("set-location se"), Ok(set_location("se", None, None)));
+        assert_eq!(parse("set-location se got"), Ok(set_location("se", Some("got"), None)));
+        assert_eq!(
+            parse("set-location se got se-got-wg-001"),
+            Ok(set_location("se", Some("got"), Some("se-got-wg-001")))
+        );
+    }
+
+    #[test]
+    fn bad_commands_say_what_is_wrong() {
+        assert_eq!(parse(""), Err(format!("empty command, {USAGE}")));
+        assert_eq!(parse("conect"), Err(format!("unknown command \"conect\", {USAGE}")));
+        assert_eq!(parse("connect now"), Err("connect doesn't take arguments".into()));
+        assert_eq!(
+            parse("set-location se got se-got-wg-001 extra"),
+            Err("set-location takes a country, a city and a hostname".into())
+        );
+    }
+
+    /// Sends `lines` over a new connection to `path` and returns the replies
+    async fn send(path: &Path, lines: &[&str]) -> Vec<String> {
+        let stream = UnixStream::connect(path).await.expect("Could not connect");
+        let (reader, mut writer) = stream.into_split();
+        let mut replies = BufReader::new(reader).lines();
+        let mut received = vec![];
+        for line in lines {
+            writer.write_all(format!("{line}\n").as_bytes()).await.unwrap();
+            received.push(replies.next_line().await.unwrap().expect("No reply"));
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn commands_get_replies_over_the_socket() {
+        let tray = TestTray::new(Config::default());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(SOCKET_NAME);
+        let control_socket = ControlSocket::bind_at(path.clone()).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let replies = tokio::select! {
+            replies = send(&path, &["status", "bogus", "connect"]) => replies,
+            _ = control_socket.serve(&tray.handle) => unreachable!(),
+        };
+        let status = Status::new(&AppState::DaemonUnavailable).text();
+        assert_eq!(replies[0], status);
+        assert!(replies[1].starts_with("error: unknown command"), "{}", replies[1]);
+        assert_eq!(replies[2], "ok");
+        control_socket.remove();
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn a_live_socket_is_not_taken_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(SOCKET_NAME);
+        let first = ControlSocket::bind_at(path.clone()).unwrap();
+        let e = ControlSocket::bind_at(path.clone()).err().expect("Took over a live socket");
+        assert_eq!(e.kind(), io::ErrorKind::AddrInUse);
+        // the way a mulltray that was killed leaves its socket behind
+        drop(first);
+        assert!(path.exists());
+        let second = ControlSocket::bind_at(path.clone()).unwrap();
+        second.remove();
+        // nothing is left of the directory the socket was bound in
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
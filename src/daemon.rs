@@ -0,0 +1,749 @@
+//! Talking to the Mullvad daemon over its gRPC management interface
+
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use tokio::net::UnixStream;
+use tokio::sync::mpsc;
+use tonic::transport::Channel;
+use tonic::Streaming;
+use tower::service_fn;
+use tracing::{debug, warn};
+
+use crate::app::{AccountExpiry, AppState, MulltrayApp, TrayUpdate};
+use crate::capabilities::DaemonCapabilities;
+use crate::debounce::Debouncer;
+use crate::error::Error;
+use crate::logging::LoggingChannel;
+use crate::model::{AccessMethod, DeviceState, TunnelState};
+use crate::proto;
+use crate::proto::management_service_client::ManagementServiceClient;
+
+// how long to wait before trying to reach the daemon again, doubled after every failed attempt
+pub const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+pub const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+const TUNNEL_STATE_DEBOUNCE: Duration = Duration::from_millis(300);
+// requests that failed for a reason that tends to go away are sent again after these delays
+const RETRY_DELAYS: [Duration; 2] = [Duration::from_millis(250), Duration::from_secs(1)];
+
+/// Creates a channel to the daemon that connects on first use, so the daemon doesn't
+/// need to be running yet (mulltray is often autostarted before it)
+pub fn daemon_channel(socket_path: PathBuf) -> Result<Channel, tonic::transport::Error> {
+    // (this tonic API is idiotic) the uri is ignored because unix sockets don't use it
+    let channel = tonic::transport::Endpoint::try_from("http://[::]:50051")?
+        .connect_with_connector_lazy(service_fn(move |_: tonic::transport::Uri| {
+            UnixStream::connect(socket_path.clone())
+        }));
+    Ok(channel)
+}
+
+/// What the tray shows for a tunnel state the daemon sent
+pub fn app_state(tunnel_state: proto::TunnelState) -> AppState {
+    match TunnelState::try_from(tunnel_state) {
+        Ok(tunnel_state) => tunnel_state.into(),
+        Err(e) => {
+            debug!("Showing an unknown tunnel state as inactive: {}", e);
+            AppState::Inactive
+        }
+    }
+}
+
+/// Whether a failed request might succeed if it's simply sent again, e.g. when the daemon
+/// was busy or restarting
+fn is_transient(status: &tonic::Status) -> bool {
+    use tonic::Code;
+    matches!(
+        status.code(),
+        Code::Unavailable | Code::DeadlineExceeded | Code::ResourceExhausted | Code::Aborted
+    )
+}
+
+/// Sends a request, and sends it again a couple of times if it fails for a transient reason
+pub async fn with_retries<T, Fut>(mut request: impl FnMut() -> Fut) -> Result<T, tonic::Status>
+where
+    Fut: Future<Output = Result<T, tonic::Status>>,
+{
+    for delay in RETRY_DELAYS {
+        match request().await {
+            Err(e) if is_transient(&e) => {
+                debug!("Retrying in {:?} after a transient error: {}", delay, e.message());
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+    request().await
+}
+
+/// Fetches the current state of the daemon into the tray and subscribes to further changes
+pub async fn sync_with_daemon(
+    client: &mut ManagementServiceClient<LoggingChannel>,
+    tray_handle: &ksni::Handle<MulltrayApp>,
+) -> Result<Streaming<proto::DaemonEvent>, Error> {
+    let app_state = app_state(client.get_tunnel_state(()).await?.into_inner());
+    let stream = client.events_listen(()).await?.into_inner();
+    let settings = client.get_settings(()).await?.into_inner();
+    let device_state = device_state(client.get_device(()).await?.into_inner());
+    let wireguard_key_created = fetch_wireguard_key_created(client).await;
+    // the daemon only knows this after it has talked to the Mullvad API
+    let version_info = match client.get_version_info(()).await {
+        Ok(version_info) => Some(version_info.into_inner().into()),
+        Err(e) => {
+            debug!("Could not fetch version info: {}", e.message());
+            None
+        }
+    };
+    let daemon_version = match client.get_current_version(()).await {
+        Ok(version) => Some(version.into_inner()),
+        Err(e) => {
+            debug!("Could not fetch the daemon version: {}", e.message());
+            None
+        }
+    };
+    // the daemon may have been built without some of the features its version has, those
+    // are turned off when the first call for them fails
+    let mut capabilities =
+        daemon_version.as_deref().map(DaemonCapabilities::for_version).unwrap_or_default();
+    debug!(?daemon_version, ?capabilities, "Daemon capabilities");
+    // the daemon leaves out the split tunneling settings on platforms that don't have it
+    let split_tunnel_supported = settings.split_tunnel.is_some();
+    let mut api_access_method = None;
+    if capabilities.api_access_methods {
+        match client.get_current_api_access_method(()).await {
+            Ok(method) => api_access_method = AccessMethod::try_from(method.into_inner()).ok(),
+            Err(e) if e.code() == tonic::Code::Unimplemented => {
+                capabilities.api_access_methods = false;
+            }
+            Err(e) => debug!("Could not fetch the API access method: {}", e.message()),
+        }
+    }
+    tray_handle.update(|tray: &mut MulltrayApp| {
+        tray.set_app_state(app_state);
+        tray.connect_on_start();
+        tray.receive_settings(settings);
+        tray.capabilities = capabilities;
+        tray.split_tunnel_supported = split_tunnel_supported;
+        tray.api_access_method = api_access_method;
+        if let Some(device_state) = device_state {
+            tray.set_device_state(device_state);
+        }
+        tray.wireguard_key_created = wireguard_key_created;
+        tray.daemon_version = daemon_version;
+        if let Some(version_info) = version_info {
+            tray.set_version_info(version_info);
+        }
+        // the relay list is big so it's fetched separately to not hold up everything else
+        tray.load_locations();
+    });
+    Ok(stream)
+}
+
+/// `None` if the daemon is in a state that this version doesn't know about
+fn device_state(device_state: proto::DeviceState) -> Option<DeviceState> {
+    DeviceState::try_from(device_state).inspect_err(|e| warn!("{}", e)).ok()
+}
+
+/// When the WireGuard key was created, `None` if there is no key because we're logged out
+async fn fetch_wireguard_key_created(
+    client: &mut ManagementServiceClient<LoggingChannel>,
+) -> Option<SystemTime> {
+    match client.get_wireguard_key(()).await {
+        Ok(key) => key.into_inner().created.and_then(|created| created.try_into().ok()),
+        Err(e) => {
+            if e.code() != tonic::Code::NotFound {
+                warn!("Could not fetch the WireGuard key: {}", e.message());
+            }
+            None
+        }
+    }
+}
+
+/// Sends the tray what goes with a new device state
+async fn refresh_device_details(
+    mut client: ManagementServiceClient<LoggingChannel>,
+    tray_updates: mpsc::UnboundedSender<TrayUpdate>,
+) {
+    // logging in and rotating the key both come with a new key
+    let wireguard_key_created = fetch_wireguard_key_created(&mut client).await;
+    // another account may have been logged in to
+    let account_expiry = match fetch_account_expiry(&mut client).await {
+        Ok(expiry) => Some(expiry),
+        Err(e) => {
+            warn!("Could not fetch account data: {}", e);
+            None
+        }
+    };
+    let _ = tray_updates.send(Box::new(move |tray: &mut MulltrayApp| {
+        tray.wireguard_key_created = wireguard_key_created;
+        if let Some(account_expiry) = account_expiry {
+            tray.account_expiry = account_expiry;
+        }
+    }));
+}
+
+/// Mirrors daemon events into the tray until the event stream ends
+pub async fn listen_daemon_events(
+    mut stream: Streaming<proto::DaemonEvent>,
+    client: &mut ManagementServiceClient<LoggingChannel>,
+    tray_handle: &ksni::Handle<MulltrayApp>,
+) -> Result<(), Error> {
+    // repeated notifications are dropped so that reconnecting doesn't spam the user
+    let mut last_notification = None;
+    // the daemon goes through several states in quick succession when reconnecting and
+    // rebuilding the menu for each of them makes it flicker, so only the last one is shown
+    let mut tunnel_states: Debouncer<AppState> = Debouncer::new(TUNNEL_STATE_DEBOUNCE);
+    // the daemon sometimes sends the same state again, which isn't worth redrawing the tray
+    let mut last_state: Option<AppState> = None;
+    loop {
+        let message = tokio::select! {
+            message = stream.message() => message?,
+            app_state = tunnel_states.ready() => {
+                if last_state.as_ref().is_some_and(|last| last.shows_same_as(&app_state)) {
+                    debug!("Skipping a repeated tunnel state");
+                    continue;
+                }
+                let notification = app_state
+                    .notification()
+                    .filter(|notification| last_notification.as_ref() != Some(notification));
+                let shown = tray_handle.update(|tray: &mut MulltrayApp| {
+                    tray.set_app_state(app_state);
+                    if let Some((summary, body)) = notification.clone() {
+                        tray.notify_state(summary, body);
+                    }
+                    tray.app_state.clone()
+                });
+                last_state = Some(shown);
+                if notification.is_some() {
+                    last_notification = notification;
+                }
+                continue;
+            }
+        };
+        let Some(message) = message else {
+            break;
+        };
+        // newer daemons have events that this proto doesn't know about
+        let Some(event) = message.event else {
+            debug!("Ignoring an unknown daemon event");
+            continue;
+        };
+        use proto::daemon_event::Event::*;
+        match event {
+            TunnelState(tunnel_state) => tunnel_states.push(app_state(tunnel_state)),
+            Settings(settings) => {
+                tray_handle.update(|tray: &mut MulltrayApp| tray.receive_settings(settings));
+            }
+            RelayList(locations) => {
+                tray_handle.update(|tray: &mut MulltrayApp| tray.set_locations(locations.into()));
+            }
+            VersionInfo(version_info) => {
+                let version_info = version_info.into();
+                tray_handle.update(|tray: &mut MulltrayApp| tray.set_version_info(version_info));
+            }
+            Device(proto::DeviceEvent { new_state: Some(new_state), .. }) => {
+                let device_state = device_state(new_state);
+                let client = client.clone();
+                tray_handle.update(|tray: &mut MulltrayApp| {
+                    if let Some(device_state) = device_state {
+                        tray.set_device_state(device_state);
+                    }
+                    // the account data comes from the Mullvad API, which can take long enough
+                    // to hold up the events behind this one
+                    tray.spawn(refresh_device_details(client, tray.tray_updates.clone()));
+                });
+            }
+            Device(proto::DeviceEvent { new_state: None, .. }) => {
+                warn!("{}", Error::MissingField("the new state of the device"));
+            }
+            RemoveDevice(event) => {
+                let device_ids: Vec<String> =
+                    event.new_device_list.into_iter().map(|device| device.id).collect();
+                tray_handle.update(|tray: &mut MulltrayApp| {
+                    tray.device_removed(&event.account_token, &device_ids)
+                });
+            }
+            NewAccessMethod(method) => match AccessMethod::try_from(method) {
+                Ok(method) => tray_handle.update(|tray: &mut MulltrayApp| {
+                    tray.api_access_method = Some(method);
+                }),
+                Err(e) => warn!("{}", e),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Keeps the tray in sync with the daemon, reconnecting whenever the connection is lost
+pub async fn stay_in_sync_with_daemon(
+    mut client: ManagementServiceClient<LoggingChannel>,
+    tray_handle: &ksni::Handle<MulltrayApp>,
+) {
+    let mut reconnect_delay = MIN_RECONNECT_DELAY;
+    loop {
+        match sync_with_daemon(&mut client, tray_handle).await {
+            Ok(stream) => {
+                reconnect_delay = MIN_RECONNECT_DELAY;
+                match listen_daemon_events(stream, &mut client, tray_handle).await {
+                    Ok(()) => warn!("Daemon closed the event stream"),
+                    Err(e) => warn!("{}", e),
+                }
+            }
+            Err(e) => warn!("{}", e),
+        }
+        tray_handle.update(|tray: &mut MulltrayApp| {
+            tray.set_app_state(AppState::DaemonUnavailable)
+        });
+        tokio::time::sleep(reconnect_delay).await;
+        reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+// the daemon has to ask the Mullvad API for the account data so don't do it too often
+const ACCOUNT_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const ACCOUNT_RETRY_DELAY: Duration = Duration::from_secs(60);
+const ACCOUNT_EXPIRY_WARNING: Duration = Duration::from_secs(3 * 24 * 60 * 60);
+
+pub async fn fetch_account_expiry(
+    client: &mut ManagementServiceClient<LoggingChannel>,
+) -> Result<AccountExpiry, Error> {
+    let device_state = client.get_device(()).await?.into_inner();
+    let logged_in = device_state.state() == proto::device_state::State::LoggedIn;
+    let Some(device) = device_state.device.filter(|_| logged_in) else {
+        return Ok(AccountExpiry::NoAccount);
+    };
+    let account_data = client.get_account_data(device.account_token).await?.into_inner();
+    let expiry = account_data.expiry
+        .and_then(|expiry| SystemTime::try_from(expiry).ok())
+        .map_or(AccountExpiry::Unknown, AccountExpiry::At);
+    Ok(expiry)
+}
+
+/// Keeps the account expiry up to date, warning once when the account is about to expire
+pub async fn refresh_account_expiry(
+    mut client: ManagementServiceClient<LoggingChannel>,
+    tray_handle: &ksni::Handle<MulltrayApp>,
+) {
+    let mut warning_shown = false;
+    loop {
+        let delay = match fetch_account_expiry(&mut client).await {
+            Ok(expiry) => {
+                let warn = !warning_shown
+                    && expiry.time_remaining().is_some_and(|t| t < ACCOUNT_EXPIRY_WARNING);
+                tray_handle.update(|tray: &mut MulltrayApp| {
+                    tray.account_expiry = expiry;
+                    if warn {
+                        tray.notify("Mullvad account is about to expire".into(), expiry.label());
+                    }
+                });
+                warning_shown |= warn;
+                ACCOUNT_REFRESH_INTERVAL
+            }
+            Err(e) => {
+                warn!("Could not fetch account data: {}", e);
+                ACCOUNT_RETRY_DELAY
+            }
+        };
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::LocationsStatus;
+    use crate::config::Config;
+    use crate::model::RelayInfo;
+    use crate::testing::{
+        connected, disconnected, find_item, is_visible, relay_list, submenu_labels, tunnel_state,
+        MockDaemon, TestTray,
+    };
+    use ksni::MenuItem;
+    use proto::daemon_event::Event;
+    use proto::tunnel_state::State;
+
+    fn connected_to(tray: &MulltrayApp, hostname: &str) -> bool {
+        let relay_info = tray.app_state.relay_info();
+        matches!(tray.app_state, AppState::Connected(_))
+            && relay_info.and_then(RelayInfo::hostname).is_some_and(|h| h == hostname)
+    }
+
+    #[tokio::test]
+    async fn startup_fetches_the_daemon_state() {
+        let tray = TestTray::new(Config::default());
+        let daemon = MockDaemon::default();
+        {
+            let mut state = daemon.state();
+            state.tunnel_state = tunnel_state(connected("se-got-wg-001"));
+            state.settings.allow_lan = true;
+            state.relay_list = relay_list(&["se-got-wg-001", "de-ber-wg-002"]);
+        }
+        let _server = tray.serve(&daemon);
+        tray.sync_with_daemon();
+
+        tray.wait_for("the relay list", |tray| tray.locations_status == LocationsStatus::Loaded)
+            .await;
+        // only reading, nothing was changed in the daemon
+        assert_eq!(daemon.calls(), Vec::<&str>::new());
+        tray.read(|tray| {
+            assert!(connected_to(tray, "se-got-wg-001"), "{:?}", tray.app_state);
+            assert!(tray.settings.allow_lan);
+            assert_eq!(tray.daemon_version.as_deref(), Some("2024.8"));
+            assert!(tray.capabilities.custom_lists);
+            let countries: Vec<&str> =
+                tray.locations.countries.iter().map(|country| country.code.as_str()).collect();
+            assert_eq!(countries, ["de", "se"]);
+        });
+    }
+
+    #[tokio::test]
+    async fn daita_is_turned_off_when_the_daemon_does_not_have_it() {
+        let tray = TestTray::new(Config::default());
+        let daemon = MockDaemon::default();
+        daemon.state().unimplemented.push("set_daita_settings");
+        let _server = tray.serve(&daemon);
+        tray.sync_with_daemon();
+        tray.wait_for("the daemon version", |tray| tray.daemon_version.is_some()).await;
+        // the version has DAITA, and the daemon isn't asked whether it really does
+        assert!(tray.read(|tray| tray.capabilities.daita));
+        let daita_calls = || daemon.calls().into_iter().filter(|c| *c == "set_daita_settings");
+        assert_eq!(daita_calls().count(), 0);
+
+        tray.handle.update(|tray: &mut MulltrayApp| tray.set_daita(true));
+        tray.wait_for("DAITA to be turned off", |tray| !tray.capabilities.daita).await;
+        tray.wait_for("the settings to be restored", |tray| !tray.settings.daita).await;
+        assert_eq!(daita_calls().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn slow_account_data_does_not_hold_up_other_events() {
+        let tray = TestTray::new(Config::default());
+        let daemon = MockDaemon::default();
+        let _server = tray.serve(&daemon);
+        tray.sync_with_daemon();
+        tray.wait_for("the daemon version", |tray| tray.daemon_version.is_some()).await;
+
+        let device_state = {
+            let mut state = daemon.state();
+            state.device_state.set_state(proto::device_state::State::LoggedIn);
+            state.device_state.device = Some(proto::AccountAndDevice {
+                account_token: "1234123412341234".into(),
+                device: Some(proto::Device { name: "Happy Otter".into(), ..Default::default() }),
+            });
+            state.delays.insert("get_account_data", Duration::from_millis(500));
+            state.device_state.clone()
+        };
+        let new_state = Some(device_state);
+        daemon.send_event(Event::Device(proto::DeviceEvent { new_state, ..Default::default() }));
+        daemon.send_event(Event::TunnelState(tunnel_state(connected("se-got-wg-001"))));
+        tray.wait_for("connected", |tray| connected_to(tray, "se-got-wg-001")).await;
+        let has_expiry = |tray: &MulltrayApp| matches!(tray.account_expiry, AccountExpiry::At(_));
+        assert!(tray.read(|tray| tray.is_logged_in() && !has_expiry(tray)));
+        tray.wait_for("the account expiry", has_expiry).await;
+    }
+
+    fn split_tunnel_shown(tray: &MulltrayApp) -> bool {
+        let menu = ksni::Tray::menu(tray);
+        find_item(&menu, &["Settings", "Split tunneling"]).is_some_and(is_visible)
+    }
+
+    #[tokio::test]
+    async fn split_tunnel_support_comes_from_the_settings() {
+        for supported in [false, true] {
+            let tray = TestTray::new(Config::default());
+            let daemon = MockDaemon::default();
+            if supported {
+                daemon.state().settings.split_tunnel = Some(Default::default());
+            }
+            let _server = tray.serve(&daemon);
+            tray.sync_with_daemon();
+            tray.wait_for("the daemon version", |tray| tray.daemon_version.is_some()).await;
+            assert_eq!(tray.read(|tray| tray.split_tunnel_supported), supported);
+            assert_eq!(tray.read(split_tunnel_shown), supported);
+            assert_eq!(daemon.calls(), Vec::<&str>::new());
+        }
+    }
+
+    #[tokio::test]
+    async fn split_tunnel_is_hidden_if_the_daemon_does_not_have_it() {
+        let tray = TestTray::new(Config::default());
+        let daemon = MockDaemon::default();
+        {
+            let mut state = daemon.state();
+            state.settings.split_tunnel = Some(Default::default());
+            state.unimplemented.push("set_split_tunnel_state");
+        }
+        let _server = tray.serve(&daemon);
+        tray.sync_with_daemon();
+        tray.wait_for("split tunneling", split_tunnel_shown).await;
+
+        tray.click(&["Settings", "Split tunneling", "Enabled"]);
+        tray.wait_for("split tunneling to be hidden", |tray| !split_tunnel_shown(tray)).await;
+        assert_eq!(daemon.calls(), ["set_split_tunnel_state"]);
+    }
+
+    #[tokio::test]
+    async fn split_tunnel_apps_follow_the_settings() {
+        let tray = TestTray::new(Config::default());
+        let daemon = MockDaemon::default();
+        daemon.state().settings.split_tunnel = Some(Default::default());
+        let _server = tray.serve(&daemon);
+        tray.sync_with_daemon();
+        tray.wait_for("split tunneling", split_tunnel_shown).await;
+
+        // e.g. added with the CLI
+        let mut settings = daemon.state().settings.clone();
+        let split_tunnel = settings.split_tunnel.as_mut().unwrap();
+        split_tunnel.enable_exclusions = true;
+        split_tunnel.apps = vec!["/usr/bin/firefox".into(), "/opt/steam/steam".into()];
+        daemon.state().settings = settings.clone();
+        daemon.send_event(Event::Settings(settings));
+        let path = ["Settings", "Split tunneling"];
+        let apps_shown = |tray: &MulltrayApp| {
+            let labels = submenu_labels(&ksni::Tray::menu(tray), &path);
+            labels.contains(&"firefox".to_string()) && labels.contains(&"steam".to_string())
+        };
+        tray.wait_for("the applications", apps_shown).await;
+        let menu = tray.read(ksni::Tray::menu);
+        let Some(MenuItem::Checkmark(enabled)) = find_item(&menu, &[path[0], path[1], "Enabled"])
+        else {
+            panic!("No checkmark for enabling split tunneling");
+        };
+        assert!(enabled.checked);
+
+        tray.click(&["Settings", "Split tunneling", "firefox", "Remove"]);
+        tray.wait_for("firefox to be removed", |tray| {
+            !submenu_labels(&ksni::Tray::menu(tray), &path).contains(&"firefox".to_string())
+        })
+        .await;
+        tray.wait_for("the daemon to remove it", |_| !daemon.calls().is_empty()).await;
+        assert_eq!(daemon.calls(), ["remove_split_tunnel_app"]);
+        let apps = daemon.state().settings.split_tunnel.clone().unwrap().apps;
+        assert_eq!(apps, ["/opt/steam/steam"]);
+    }
+
+    #[tokio::test]
+    async fn tunnel_state_events_are_shown() {
+        let tray = TestTray::new(Config::default());
+        let daemon = MockDaemon::default();
+        let _server = tray.serve(&daemon);
+        tray.sync_with_daemon();
+        tray.wait_for("disconnected", |tray| tray.app_state == AppState::Disconnected).await;
+        tray.read(MulltrayApp::connect);
+        tray.wait_for("the connect request", |_| daemon.calls().contains(&"connect_tunnel")).await;
+
+        // a burst like the one when reconnecting only shows the last state
+        daemon.send_event(Event::TunnelState(tunnel_state(State::Disconnecting(
+            Default::default(),
+        ))));
+        daemon.send_event(Event::TunnelState(tunnel_state(disconnected())));
+        daemon.send_event(Event::TunnelState(tunnel_state(State::Connecting(Default::default()))));
+        daemon.send_event(Event::TunnelState(tunnel_state(connected("se-got-wg-001"))));
+        tray.wait_for("connected", |tray| connected_to(tray, "se-got-wg-001")).await;
+        tray.read(|tray| {
+            assert!(tray.connected_since.is_some());
+            assert!(tray.last_used.contains_key("se-got-wg-001"));
+        });
+
+        daemon.send_event(Event::TunnelState(tunnel_state(State::Error(Default::default()))));
+        tray.wait_for("the error", |tray| matches!(tray.app_state, AppState::Error(_))).await;
+        tray.read(|tray| {
+            assert!(tray.connected_since.is_none());
+            assert!(tray.last_error.is_some());
+        });
+
+        let locked_down =
+            proto::tunnel_state::Disconnected { locked_down: true, ..Default::default() };
+        daemon.send_event(Event::TunnelState(tunnel_state(State::Disconnected(locked_down))));
+        tray.wait_for("blocked", |tray| tray.app_state == AppState::Blocked).await;
+    }
+
+    #[tokio::test]
+    async fn relay_list_events_replace_the_locations() {
+        let tray = TestTray::new(Config::default());
+        let daemon = MockDaemon::default();
+        daemon.state().relay_list = relay_list(&["se-got-wg-001"]);
+        let _server = tray.serve(&daemon);
+        tray.sync_with_daemon();
+        tray.wait_for("the relay list", |tray| tray.locations_status == LocationsStatus::Loaded)
+            .await;
+        let version = tray.read(|tray| tray.locations_version);
+
+        daemon.send_event(Event::RelayList(relay_list(&["se-got-wg-001", "se-sto-wg-003"])));
+        tray.wait_for("the new relay list", |tray| tray.locations_version > version).await;
+        tray.read(|tray| {
+            let cities: Vec<&str> = tray.locations.countries[0].cities
+                .iter()
+                .map(|city| city.code.as_str())
+                .collect();
+            assert_eq!(cities, ["got", "sto"]);
+        });
+
+        // an empty list is a hiccup, the relays from before are kept
+        let version = tray.read(|tray| tray.locations_version);
+        daemon.send_event(Event::RelayList(proto::RelayList::default()));
+        daemon.send_event(Event::RelayList(relay_list(&["no-osl-wg-001"])));
+        tray.wait_for("the third relay list", |tray| tray.locations_version > version).await;
+        tray.read(|tray| {
+            assert_eq!(tray.locations_version, version + 1);
+            assert_eq!(tray.locations.countries[0].code, "no");
+        });
+    }
+
+    #[tokio::test]
+    async fn daemon_restart_is_followed() {
+        let tray = TestTray::new(Config::default());
+        let daemon = MockDaemon::default();
+        daemon.state().tunnel_state = tunnel_state(connected("se-got-wg-001"));
+        let server = tray.serve(&daemon);
+        tray.sync_with_daemon();
+        tray.wait_for("connected", |tray| connected_to(tray, "se-got-wg-001")).await;
+
+        server.stop().await;
+        tray.wait_for("the daemon to be gone", |tray| {
+            tray.app_state == AppState::DaemonUnavailable
+        })
+        .await;
+
+        // the daemon comes back with a different state, and a newer version
+        {
+            let mut state = daemon.state();
+            state.tunnel_state = tunnel_state(connected("de-ber-wg-002"));
+            state.version = "2025.1".into();
+        }
+        let _server = tray.serve(&daemon);
+        tray.wait_for("reconnected", |tray| connected_to(tray, "de-ber-wg-002")).await;
+        assert_eq!(tray.read(|tray| tray.daemon_version.clone()).as_deref(), Some("2025.1"));
+
+        // and its events are listened to again
+        daemon.send_event(Event::TunnelState(tunnel_state(disconnected())));
+        tray.wait_for("disconnected", |tray| tray.app_state == AppState::Disconnected).await;
+    }
+
+    /// Calls `with_retries` with a request that fails with `errors` before succeeding, and
+    /// returns its result along with when each attempt was made
+    async fn retry(errors: &[tonic::Code]) -> (Result<(), tonic::Code>, Vec<Duration>) {
+        let start = tokio::time::Instant::now();
+        let attempts = std::cell::RefCell::new(vec![]);
+        let result = with_retries(|| {
+            let mut attempts = attempts.borrow_mut();
+            attempts.push(start.elapsed());
+            let result = match errors.get(attempts.len() - 1) {
+                Some(&code) => Err(tonic::Status::new(code, "failed")),
+                None => Ok(()),
+            };
+            async { result }
+        })
+        .await;
+        (result.map_err(|e| e.code()), attempts.into_inner())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn transient_errors_are_retried_after_a_delay() {
+        use tonic::Code;
+        let ms = Duration::from_millis;
+        assert_eq!(retry(&[]).await, (Ok(()), vec![ms(0)]));
+        assert_eq!(retry(&[Code::Unavailable]).await, (Ok(()), vec![ms(0), ms(250)]));
+        assert_eq!(
+            retry(&[Code::Unavailable, Code::DeadlineExceeded]).await,
+            (Ok(()), vec![ms(0), ms(250), ms(1250)])
+        );
+        // gives up after the last delay
+        assert_eq!(
+            retry(&[Code::Unavailable; 3]).await,
+            (Err(Code::Unavailable), vec![ms(0), ms(250), ms(1250)])
+        );
+        // the request itself was wrong, sending it again wouldn't help
+        assert_eq!(
+            retry(&[Code::InvalidArgument]).await,
+            (Err(Code::InvalidArgument), vec![ms(0)])
+        );
+        assert_eq!(
+            retry(&[Code::Aborted, Code::NotFound]).await,
+            (Err(Code::NotFound), vec![ms(0), ms(250)])
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reconnecting_backs_off() {
+        let tray = TestTray::new(Config::default());
+        let start = tokio::time::Instant::now();
+        tray.sync_with_daemon();
+        // there is no daemon, so it is tried at 0, 1, 3 and 7 seconds
+        tokio::time::sleep(Duration::from_secs(4)).await;
+        assert!(tray.read(|tray| tray.app_state == AppState::DaemonUnavailable));
+        let daemon = MockDaemon::default();
+        daemon.state().tunnel_state = tunnel_state(disconnected());
+        let _server = tray.serve(&daemon);
+        tray.wait_for("the daemon", |tray| tray.app_state == AppState::Disconnected).await;
+        let reconnected = start.elapsed();
+        assert!(reconnected >= Duration::from_secs(7), "{reconnected:?}");
+        assert!(reconnected < Duration::from_secs(8), "{reconnected:?}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_backoff_starts_over_after_a_successful_sync() {
+        let tray = TestTray::new(Config::default());
+        let daemon = MockDaemon::default();
+        daemon.state().tunnel_state = tunnel_state(disconnected());
+        let server = tray.serve(&daemon);
+        tray.sync_with_daemon();
+        tray.wait_for("the daemon", |tray| tray.app_state == AppState::Disconnected).await;
+
+        // long enough for the delay to have grown if it wasn't reset
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        server.stop().await;
+        let stopped = tokio::time::Instant::now();
+        tray.wait_for("the daemon to go", |tray| tray.app_state == AppState::DaemonUnavailable)
+            .await;
+        let _server = tray.serve(&daemon);
+        tray.wait_for("the daemon", |tray| tray.app_state == AppState::Disconnected).await;
+        let reconnected = stopped.elapsed();
+        assert!(reconnected >= MIN_RECONNECT_DELAY, "{reconnected:?}");
+        assert!(reconnected < MIN_RECONNECT_DELAY * 2, "{reconnected:?}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn repeated_tunnel_states_are_only_shown_once() {
+        let tray = TestTray::new(Config::default());
+        let daemon = MockDaemon::default();
+        daemon.state().tunnel_state = tunnel_state(disconnected());
+        let _server = tray.serve(&daemon);
+        tray.sync_with_daemon();
+        tray.wait_for("disconnected", |tray| tray.app_state == AppState::Disconnected).await;
+        // bumped every time a connection to a relay is shown
+        let shown = || tray.read(|tray| tray.metrics_version);
+        let before = shown();
+
+        let without_info = State::Connected(proto::tunnel_state::Connected { relay_info: None });
+        // spaced out so that the debouncer doesn't merge them
+        for state in [connected("se-got-wg-001"), connected("se-got-wg-001"), without_info] {
+            daemon.send_event(Event::TunnelState(tunnel_state(state)));
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        tray.wait_for("connected", |tray| connected_to(tray, "se-got-wg-001")).await;
+        assert_eq!(shown(), before + 1);
+
+        daemon.send_event(Event::TunnelState(tunnel_state(connected("de-ber-wg-001"))));
+        tray.wait_for("the other relay", |tray| connected_to(tray, "de-ber-wg-001")).await;
+        assert_eq!(shown(), before + 2);
+    }
+
+    #[test]
+    fn states_that_look_the_same_are_repeats() {
+        let relay = |hostname| match app_state(tunnel_state(connected(hostname))) {
+            AppState::Connected(relay_info) => relay_info,
+            app_state => panic!("not connected: {app_state:?}"),
+        };
+        let got = AppState::Connected(relay("se-got-wg-001"));
+        assert!(got.shows_same_as(&got.clone()));
+        assert!(got.shows_same_as(&AppState::Connected(None)));
+        assert!(!AppState::Connected(None).shows_same_as(&got));
+        assert!(!got.shows_same_as(&AppState::Connected(relay("de-ber-wg-001"))));
+        assert!(!got.shows_same_as(&AppState::Connecting(relay("se-got-wg-001"))));
+        let connecting = AppState::Connecting(relay("se-got-wg-001"));
+        assert!(connecting.shows_same_as(&AppState::Connecting(None)));
+        assert!(AppState::Disconnected.shows_same_as(&AppState::Disconnected));
+        assert!(!AppState::Disconnected.shows_same_as(&AppState::Blocked));
+    }
+}
@@ -0,0 +1,95 @@
+//! Coalescing bursts of values, such as the tunnel states the daemon goes through when
+//! reconnecting, so that only the last one has to be acted on
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+pub struct Debouncer<T> {
+    window: Duration,
+    pending: Option<(T, Instant)>,
+}
+
+impl<T> Debouncer<T> {
+    pub fn new(window: Duration) -> Self {
+        Self { window, pending: None }
+    }
+
+    /// Replaces the pending value. The window starts from the first value and isn't extended
+    /// by later ones, so no value is held back for longer than the window.
+    pub fn push(&mut self, value: T) {
+        let deadline = match &self.pending {
+            Some((_, deadline)) => *deadline,
+            None => Instant::now() + self.window,
+        };
+        self.pending = Some((value, deadline));
+    }
+
+    /// Waits until the window is over and returns the latest value, never returns if there is
+    /// nothing pending. Cancelling this doesn't lose the value, so it can be used in `select!`.
+    pub async fn ready(&mut self) -> T {
+        if let Some((_, deadline)) = &self.pending {
+            tokio::time::sleep_until(*deadline).await;
+        }
+        match self.pending.take() {
+            Some((value, _)) => value,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_millis(300);
+
+    #[tokio::test(start_paused = true)]
+    async fn only_the_last_value_of_a_burst_is_returned() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(WINDOW);
+        debouncer.push("disconnecting");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        debouncer.push("connecting");
+        debouncer.push("connected");
+        assert_eq!(debouncer.ready().await, "connected");
+        assert_eq!(start.elapsed(), WINDOW);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn later_values_do_not_extend_the_window() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(WINDOW);
+        for i in 0..10 {
+            debouncer.push(i);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        // the window ran out while the values kept coming
+        assert_eq!(debouncer.ready().await, 9);
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+
+        // and the next one waits for a window of its own
+        let start = Instant::now();
+        debouncer.push(10);
+        assert_eq!(debouncer.ready().await, 10);
+        assert_eq!(start.elapsed(), WINDOW);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelling_keeps_the_value() {
+        let mut debouncer = Debouncer::new(WINDOW);
+        debouncer.push("error");
+        let early = tokio::time::timeout(Duration::from_millis(100), debouncer.ready()).await;
+        assert!(early.is_err());
+        let start = Instant::now();
+        assert_eq!(debouncer.ready().await, "error");
+        assert_eq!(start.elapsed(), Duration::from_millis(200));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn nothing_pending_never_returns() {
+        let mut debouncer = Debouncer::<()>::new(WINDOW);
+        let ready = tokio::time::timeout(Duration::from_secs(60), debouncer.ready()).await;
+        assert!(ready.is_err());
+    }
+}
@@ -0,0 +1,91 @@
+//! The block of text "Copy diagnostics" puts on the clipboard for bug reports
+
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::app::{AppState, MulltrayApp};
+use crate::format::{connection_details, state_summary};
+
+/// Everything that goes into the diagnostics, anything not known yet is `None`
+#[derive(Debug)]
+pub struct Diagnostics<'a> {
+    pub mulltray_version: &'a str,
+    pub daemon_version: Option<&'a str>,
+    pub socket_path: Option<&'a Path>,
+    pub app_state: Option<&'a AppState>,
+    pub last_error: Option<&'a str>,
+}
+
+impl<'a> Diagnostics<'a> {
+    pub fn from_app(app: &'a MulltrayApp) -> Self {
+        Self {
+            mulltray_version: env!("CARGO_PKG_VERSION"),
+            daemon_version: app.daemon_version.as_deref(),
+            socket_path: Some(&app.socket_path),
+            app_state: Some(&app.app_state),
+            last_error: app.last_error.as_deref(),
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let unknown = "unknown";
+        let mut text = String::new();
+        let _ = writeln!(text, "mulltray: {}", self.mulltray_version);
+        let _ = writeln!(text, "daemon: {}", self.daemon_version.unwrap_or(unknown));
+        let _ = writeln!(text, "os: {} {}", std::env::consts::OS, std::env::consts::ARCH);
+        let socket_path = self.socket_path.map(|path| path.display().to_string());
+        let _ = writeln!(text, "socket: {}", socket_path.as_deref().unwrap_or(unknown));
+        let state = self.app_state.map(state_summary);
+        let _ = writeln!(text, "state: {}", state.as_deref().unwrap_or(unknown));
+        if let Some(relay_info) = self.app_state.and_then(AppState::relay_info) {
+            let _ = writeln!(text, "connection: {}", connection_details(relay_info));
+        }
+        let _ = write!(text, "last error: {}", self.last_error.unwrap_or("none"));
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::app_state;
+    use crate::testing::{connected, tunnel_state};
+
+    #[test]
+    fn nothing_known_is_still_a_report() {
+        let diagnostics = Diagnostics {
+            mulltray_version: "0.1.0",
+            daemon_version: None,
+            socket_path: None,
+            app_state: None,
+            last_error: None,
+        };
+        let text = diagnostics.text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[..2], ["mulltray: 0.1.0", "daemon: unknown"]);
+        assert!(lines[2].starts_with("os: "), "{text}");
+        assert_eq!(lines[3..], ["socket: unknown", "state: unknown", "last error: none"]);
+    }
+
+    #[test]
+    fn everything_known_is_reported() {
+        let app_state = app_state(tunnel_state(connected("se-got-wg-001")));
+        let diagnostics = Diagnostics {
+            mulltray_version: "0.1.0",
+            daemon_version: Some("2024.8"),
+            socket_path: Some(Path::new("/var/run/mullvad-vpn")),
+            app_state: Some(&app_state),
+            last_error: Some("error: Failed to start the tunnel"),
+        };
+        let text = diagnostics.text();
+        for line in [
+            "daemon: 2024.8",
+            "socket: /var/run/mullvad-vpn",
+            "state: connected to se-got-wg-001",
+            "connection: Relay: se-got-wg-001",
+            "last error: error: Failed to start the tunnel",
+        ] {
+            assert!(text.lines().any(|l| l == line), "{line:?} missing from {text}");
+        }
+    }
+}
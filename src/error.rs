@@ -0,0 +1,38 @@
+//! Errors that stop mulltray or its connection to the daemon
+
+use std::path::PathBuf;
+
+use crate::{config, install};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The channel to the daemon socket couldn't be set up
+    #[error("Could not connect to the daemon at {}: {1}", .0.display())]
+    Channel(PathBuf, #[source] tonic::transport::Error),
+    /// A request to the daemon failed, either because the daemon couldn't be reached or
+    /// because it refused
+    #[error("{}", daemon_error_text(.0))]
+    Daemon(#[from] tonic::Status),
+    /// The daemon sent a message without a field that it always fills in
+    #[error("The daemon sent a message without {0}")]
+    MissingField(&'static str),
+    #[error(transparent)]
+    Config(#[from] config::Error),
+    #[error(transparent)]
+    Install(#[from] install::Error),
+    /// What we were doing and what went wrong
+    #[error("Could not {0}: {1}")]
+    Io(&'static str, #[source] std::io::Error),
+    #[error("The tray icon stopped working: {0}")]
+    TrayStopped(#[source] dbus::Error),
+    /// The thread serving the tray icon panicked
+    #[error("The tray icon crashed, the log has the details")]
+    TrayCrashed,
+}
+
+fn daemon_error_text(status: &tonic::Status) -> String {
+    match status.code() {
+        tonic::Code::Unavailable => format!("Could not reach the daemon: {}", status.message()),
+        code => format!("The daemon refused the request: {} ({:?})", status.message(), code),
+    }
+}
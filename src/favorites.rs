@@ -0,0 +1,85 @@
+//! Favorite locations, stored in `$XDG_CONFIG_HOME/mulltray/favorites.toml`
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::config_dir;
+
+/// A country, a city or a single relay, identified by the codes used in the relay list
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Favorite {
+    pub country: String,
+    pub city: Option<String>,
+    pub hostname: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Favorites {
+    #[serde(rename = "favorite")]
+    pub favorites: Vec<Favorite>,
+}
+
+fn favorites_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("favorites.toml"))
+}
+
+impl Favorites {
+    /// Reads the favorites file, starting with no favorites if it doesn't exist or can't be read
+    pub fn load() -> Self {
+        let Some(path) = favorites_path() else {
+            return Favorites::default();
+        };
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Favorites::default(),
+            Err(e) => {
+                warn!("Could not read {}: {}", path.display(), e);
+                return Favorites::default();
+            }
+        };
+        match toml::from_str(&contents) {
+            Ok(favorites) => favorites,
+            Err(e) => {
+                warn!("Invalid favorites file {}: {}", path.display(), e);
+                Favorites::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let Some(path) = favorites_path() else {
+            warn!("Could not save favorites: no config directory");
+            return;
+        };
+        let contents = match toml::to_string(self) {
+            Ok(contents) => contents,
+            Err(e) => {
+                warn!("Could not save favorites: {}", e);
+                return;
+            }
+        };
+        let result = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| std::fs::write(&path, contents));
+        if let Err(e) = result {
+            warn!("Could not write {}: {}", path.display(), e);
+        }
+    }
+
+    pub fn add(&mut self, favorite: Favorite) {
+        if !self.favorites.contains(&favorite) {
+            self.favorites.push(favorite);
+            self.save();
+        }
+    }
+
+    pub fn remove(&mut self, favorite: &Favorite) {
+        self.favorites.retain(|f| f != favorite);
+        self.save();
+    }
+}
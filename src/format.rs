@@ -0,0 +1,795 @@
+//! Turning daemon state into text for the menu, notifications and tooltip
+
+use std::time::{Duration, SystemTime};
+
+use crate::app::AppState;
+use crate::model::{
+    AuthFailedError, Endpoint, ErrorCause, ErrorState, FirewallPolicyError, Location,
+    ObfuscationType, ProxyType, RelayInfo, TransportProtocol, TunnelParameterError, TunnelType,
+};
+
+/// What the tunnel is doing, such as "connected to se-got-wg-001", for the tray title and
+/// for scripts
+pub fn state_summary(app_state: &AppState) -> String {
+    let hostname = app_state.relay_info().and_then(RelayInfo::hostname);
+    let entry = app_state.relay_info().and_then(RelayInfo::entry_relay);
+    match (app_state, hostname) {
+        (AppState::Connected(_), Some(hostname)) => match entry {
+            Some(entry) => format!("connected via {} → {}", entry, hostname),
+            None => format!("connected to {}", hostname),
+        },
+        (AppState::Connecting(_), Some(hostname)) => match entry {
+            Some(entry) => format!("connecting via {} → {}..", entry, hostname),
+            None => format!("connecting to {}..", hostname),
+        },
+        (AppState::Error(Some(error_state)), _) if !app_state.is_deliberately_blocked() => {
+            format!("error: {}", error_summary(error_state))
+        }
+        _ => state_label(app_state).into(),
+    }
+}
+
+/// The state without any details, such as "connected"
+pub fn state_label(app_state: &AppState) -> &'static str {
+    match app_state {
+        AppState::DaemonUnavailable => "waiting for daemon..",
+        AppState::Inactive => "inactive",
+        AppState::Connected(_) => "connected",
+        AppState::Connecting(_) => "connecting..",
+        AppState::Disconnecting => "disconnecting..",
+        AppState::Disconnected => "disconnected",
+        AppState::Blocked => "blocked (lockdown)",
+        AppState::Error(_) if app_state.is_deliberately_blocked() => "blocked (offline)",
+        AppState::Error(_) => "error",
+    }
+}
+
+#[derive(Debug)]
+enum TemplatePart<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Splits a template into text and `{name}` placeholders. `{{` and `}}` are literal braces
+/// and a `{` that is never closed is left as it is.
+fn parse_template(template: &str) -> Vec<TemplatePart<'_>> {
+    let mut parts = vec![];
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        parts.push(TemplatePart::Text(&rest[..i]));
+        let (brace, after) = rest[i..].split_at(1);
+        if after.starts_with(brace) {
+            parts.push(TemplatePart::Text(brace));
+            rest = &after[1..];
+            continue;
+        }
+        match after.find('}').filter(|_| brace == "{") {
+            Some(end) => {
+                parts.push(TemplatePart::Placeholder(after[..end].trim()));
+                rest = &after[end + 1..];
+            }
+            None => {
+                parts.push(TemplatePart::Text(brace));
+                rest = after;
+            }
+        }
+    }
+    parts.push(TemplatePart::Text(rest));
+    parts
+}
+
+/// The placeholders in `template` that aren't `known`
+pub fn unknown_placeholders<'a>(template: &'a str, known: &[&str]) -> Vec<&'a str> {
+    parse_template(template)
+        .into_iter()
+        .filter_map(|part| match part {
+            TemplatePart::Placeholder(name) if !known.contains(&name) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Fills in the placeholders of a template such as `"{state} {hostname}"` with `value`.
+/// Unknown placeholders and missing values render as nothing, and the whitespace left
+/// around them is collapsed.
+pub fn fill_template(template: &str, value: impl Fn(&str) -> Option<String>) -> String {
+    let filled: String = parse_template(template)
+        .into_iter()
+        .map(|part| match part {
+            TemplatePart::Text(text) => text.to_string(),
+            TemplatePart::Placeholder(name) => value(name).unwrap_or_default(),
+        })
+        .collect();
+    filled.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// One line per known detail of the relay we're using, for showing the user
+pub fn connection_details(relay_info: &RelayInfo) -> String {
+    let mut lines = vec![];
+    if let Some(hostname) = relay_info.hostname() {
+        lines.push(format!("Relay: {}", hostname));
+    }
+    if let Some(entry) = relay_info.entry_relay() {
+        lines.push(format!("Entry relay: {}", entry));
+    }
+    if let Some(endpoint) = &relay_info.endpoint {
+        lines.push(format!("Endpoint: {}", endpoint.address));
+        lines.push(format!("Tunnel type: {}", tunnel_type_name(endpoint.tunnel_type)));
+    }
+    if let Some(location) = &relay_info.location {
+        lines.extend(exit_addresses(location));
+    }
+    lines.join("\n")
+}
+
+fn tunnel_type_name(tunnel_type: TunnelType) -> &'static str {
+    match tunnel_type {
+        TunnelType::WireGuard => "WireGuard",
+        TunnelType::OpenVpn => "OpenVPN",
+    }
+}
+
+/// Everything known about the tunnel, one line each, leaving out whatever is unknown
+pub fn status_lines(relay_info: &RelayInfo) -> Vec<String> {
+    let mut lines = vec![];
+    let endpoint = relay_info.endpoint.as_ref();
+    let location = relay_info.location.as_ref();
+    if let Some(endpoint) = endpoint {
+        lines.push(format!("Protocol: {}", tunnel_type_name(endpoint.tunnel_type)));
+    }
+    if let Some(hostname) = relay_info.hostname() {
+        lines.push(format!("Relay: {}", hostname));
+    }
+    if let Some(entry) = relay_info.entry_relay() {
+        lines.push(format!("Entry relay: {}", entry));
+    }
+    if let Some(place) = location.and_then(geoip_place) {
+        lines.push(format!("Location: {}", place));
+    }
+    if let Some(location) = location {
+        lines.extend(exit_addresses(location));
+    }
+    if let Some(endpoint) = endpoint {
+        lines.extend(endpoint_lines(endpoint));
+    }
+    lines
+}
+
+/// How the tunnel reaches the relay: obfuscation, quantum resistance and the OpenVPN proxy
+pub fn endpoint_lines(endpoint: &Endpoint) -> Vec<String> {
+    let obfuscation = match &endpoint.obfuscation {
+        Some(obfuscation) => {
+            let name = match obfuscation.obfuscation_type {
+                ObfuscationType::Udp2Tcp => "udp2tcp",
+                ObfuscationType::Shadowsocks => "Shadowsocks",
+            };
+            format!("{} via {}", name, obfuscation.socket_address())
+        }
+        None => "off".into(),
+    };
+    let mut lines = vec![
+        format!("Obfuscation: {}", obfuscation),
+        format!("Quantum-resistant: {}", if endpoint.quantum_resistant { "yes" } else { "no" }),
+    ];
+    if let Some(proxy) = &endpoint.proxy {
+        let name = match proxy.proxy_type {
+            ProxyType::Shadowsocks => "Shadowsocks",
+            ProxyType::Custom => "custom proxy",
+        };
+        let protocol = match proxy.protocol {
+            TransportProtocol::Udp => "UDP",
+            TransportProtocol::Tcp => "TCP",
+        };
+        lines.push(format!("Proxy: {} via {} ({})", name, proxy.address, protocol));
+    }
+    lines
+}
+
+/// "City, Country", or just the country if the city is unknown
+pub fn geoip_place(location: &Location) -> Option<String> {
+    let country = location.country.as_ref()?;
+    match &location.city {
+        Some(city) => Some(format!("{city}, {country}")),
+        None => Some(country.clone()),
+    }
+}
+
+pub fn exit_addresses(location: &Location) -> Vec<String> {
+    let mut lines = vec![];
+    if let Some(ipv4) = &location.ipv4 {
+        lines.push(format!("Exit IPv4: {}", ipv4));
+    }
+    if let Some(ipv6) = &location.ipv6 {
+        lines.push(format!("Exit IPv6: {}", ipv6));
+    }
+    lines
+}
+
+fn error_cause_text(cause: &ErrorCause) -> &'static str {
+    match cause {
+        ErrorCause::AuthFailed(_) => "Authentication failed",
+        ErrorCause::Ipv6Unavailable => "IPv6 is unavailable",
+        ErrorCause::SetFirewallPolicy(_) => "Failed to configure the firewall",
+        ErrorCause::SetDns => "Failed to set the system DNS servers",
+        ErrorCause::StartTunnel => "Failed to start the tunnel",
+        ErrorCause::CreateTunnelDevice(_) => "Failed to create the tunnel device",
+        ErrorCause::TunnelParameter(_) => "Failed to choose tunnel parameters",
+        ErrorCause::IsOffline => "This device is offline",
+        ErrorCause::VpnPermissionDenied => "Permission to create a VPN was denied",
+        ErrorCause::SplitTunnel => "Split tunneling failed",
+    }
+}
+
+fn firewall_error_text(error: &FirewallPolicyError) -> String {
+    match error {
+        FirewallPolicyError::Generic => "Firewall error".into(),
+        FirewallPolicyError::Locked { pid, name: Some(name) } => {
+            format!("Firewall locked by {} (pid {})", name, pid)
+        }
+        FirewallPolicyError::Locked { pid, name: None } => {
+            format!("Firewall locked by pid {}", pid)
+        }
+    }
+}
+
+/// What the daemon said about the cause, e.g. which part of an authentication failed
+fn error_cause_detail(cause: &ErrorCause) -> Option<String> {
+    match cause {
+        ErrorCause::AuthFailed(error) => Some(match error {
+            AuthFailedError::Unknown => "unknown reason".into(),
+            AuthFailedError::InvalidAccount => "the account number is invalid".into(),
+            AuthFailedError::ExpiredAccount => "the account is out of time".into(),
+            AuthFailedError::TooManyConnections => "too many simultaneous connections".into(),
+        }),
+        ErrorCause::TunnelParameter(error) => Some(match error {
+            TunnelParameterError::NoMatchingRelay => {
+                "no relay matches the current settings".into()
+            }
+            TunnelParameterError::NoMatchingBridgeRelay => {
+                "no bridge matches the current settings".into()
+            }
+            TunnelParameterError::NoWireguardKey => "the device has no WireGuard key".into(),
+            TunnelParameterError::CustomTunnelHostResolution => {
+                "could not resolve the custom tunnel host".into()
+            }
+        }),
+        ErrorCause::SetFirewallPolicy(error) => error.as_ref().map(firewall_error_text),
+        ErrorCause::CreateTunnelDevice(code) => code.map(|code| format!("error code {code}")),
+        _ => None,
+    }
+}
+
+/// What the user can do about the error, if there is something obvious
+fn error_suggestion(cause: &ErrorCause) -> Option<String> {
+    let suggestion = match cause {
+        ErrorCause::AuthFailed(error) => match error {
+            AuthFailedError::InvalidAccount => "Log in again with a valid account number",
+            AuthFailedError::TooManyConnections => "Disconnect one of your other devices",
+            AuthFailedError::Unknown | AuthFailedError::ExpiredAccount => {
+                "Check that your account has time left"
+            }
+        },
+        ErrorCause::TunnelParameter(error) => match error {
+            TunnelParameterError::NoMatchingRelay => {
+                "Choose another location or relax the filters"
+            }
+            TunnelParameterError::NoMatchingBridgeRelay => "Choose another bridge location",
+            TunnelParameterError::NoWireguardKey => "Rotate the WireGuard key",
+            TunnelParameterError::CustomTunnelHostResolution => "Check the custom tunnel host",
+        },
+        ErrorCause::SetFirewallPolicy(error) => {
+            return Some(match error {
+                Some(FirewallPolicyError::Locked { name: Some(name), .. }) => {
+                    format!("Another VPN or firewall is active: {name}")
+                }
+                _ => "Check that no other VPN or firewall is active".into(),
+            });
+        }
+        ErrorCause::Ipv6Unavailable => "Turn off \"Enable IPv6\" in the settings",
+        ErrorCause::CreateTunnelDevice(_) => "Check that the WireGuard kernel module is loaded",
+        ErrorCause::IsOffline => "Check your network connection",
+        ErrorCause::SetDns => "Check the DNS configuration of the system",
+        ErrorCause::StartTunnel | ErrorCause::VpnPermissionDenied | ErrorCause::SplitTunnel => {
+            return None
+        }
+    };
+    Some(suggestion.into())
+}
+
+/// The cause of the error with the details on one line, e.g. "Authentication failed: the
+/// account is out of time"
+pub fn error_summary(error_state: &ErrorState) -> String {
+    let cause = error_cause_text(&error_state.cause);
+    match error_cause_detail(&error_state.cause) {
+        Some(detail) => format!("{cause}: {detail}"),
+        None => cause.into(),
+    }
+}
+
+/// The cause of the error, followed by any details the daemon gave, what to do about it
+/// and whether traffic is being blocked
+pub fn error_details(error_state: &ErrorState) -> Vec<String> {
+    let mut lines = vec![error_cause_text(&error_state.cause).to_string()];
+    if let Some(detail) = error_cause_detail(&error_state.cause) {
+        let mut chars = detail.chars();
+        lines.extend(chars.next().map(|first| first.to_uppercase().chain(chars).collect()));
+    }
+    lines.extend(error_suggestion(&error_state.cause));
+    match &error_state.blocking_error {
+        Some(error) => {
+            lines.push(format!("Traffic is NOT blocked: {}", firewall_error_text(error)))
+        }
+        None => lines.push("All traffic is blocked".into()),
+    }
+    lines
+}
+
+/// In binary units with one decimal below 10 of the unit, e.g. "1.2 GiB" or "340 MiB"
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes} B"),
+        _ if value < 10.0 => format!("{:.1} {}", value, UNITS[unit]),
+        _ => format!("{:.0} {}", value, UNITS[unit]),
+    }
+}
+
+/// Rounded down to whole days, or to whole hours if less than a day is left
+pub fn format_time_remaining(remaining: Duration) -> String {
+    const HOUR: u64 = 60 * 60;
+    const DAY: u64 = 24 * HOUR;
+    let plural = |n: u64, unit: &str| match n {
+        1 => format!("1 {unit}"),
+        n => format!("{n} {unit}s"),
+    };
+    match remaining.as_secs() {
+        secs if secs >= DAY => plural(secs / DAY, "day"),
+        secs if secs >= HOUR => plural(secs / HOUR, "hour"),
+        _ => "less than an hour".into(),
+    }
+}
+
+/// How long ago the WireGuard key was created, in whole days
+pub fn key_age_label(created: SystemTime) -> String {
+    let days = created.elapsed().unwrap_or_default().as_secs() / (24 * 60 * 60);
+    match days {
+        0 => "Key created today".into(),
+        1 => "Key created 1 day ago".into(),
+        days => format!("Key created {days} days ago"),
+    }
+}
+
+/// Hours and minutes, e.g. "2h 14m"
+pub fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    match minutes / 60 {
+        0 => format!("{}m", minutes),
+        hours => format!("{}h {}m", hours, minutes % 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto;
+
+    fn geoip(
+        country: &str,
+        city: Option<&str>,
+        ipv4: Option<&str>,
+        ipv6: Option<&str>,
+    ) -> Location {
+        Location::from(proto::GeoIpLocation {
+            country: country.into(),
+            city: city.map(Into::into),
+            ipv4: ipv4.map(Into::into),
+            ipv6: ipv6.map(Into::into),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn places_need_a_country() {
+        let cases = [
+            (geoip("Sweden", Some("Gothenburg"), None, None), Some("Gothenburg, Sweden")),
+            (geoip("Sweden", None, None, None), Some("Sweden")),
+            // proto3 sends empty strings for missing ones
+            (geoip("Sweden", Some(""), None, None), Some("Sweden")),
+            (geoip("", Some("Gothenburg"), None, None), None),
+            (geoip("", None, None, None), None),
+        ];
+        for (location, expected) in cases {
+            assert_eq!(geoip_place(&location).as_deref(), expected, "{location:?}");
+        }
+    }
+
+    #[test]
+    fn exit_addresses_are_listed_when_known() {
+        let cases: [(_, &[&str]); 5] = [
+            (
+                geoip("Sweden", None, Some("185.213.154.1"), Some("2a03:1b20::1")),
+                &["Exit IPv4: 185.213.154.1", "Exit IPv6: 2a03:1b20::1"],
+            ),
+            (geoip("Sweden", None, Some("185.213.154.1"), None), &["Exit IPv4: 185.213.154.1"]),
+            (geoip("Sweden", None, None, Some("2a03:1b20::1")), &["Exit IPv6: 2a03:1b20::1"]),
+            (geoip("Sweden", None, Some(""), Some("")), &[]),
+            (geoip("", None, None, None), &[]),
+        ];
+        for (location, expected) in cases {
+            assert_eq!(exit_addresses(&location), expected, "{location:?}");
+        }
+    }
+
+    #[test]
+    fn every_error_cause_is_explained() {
+        use AuthFailedError::*;
+        use TunnelParameterError::*;
+        let locked = |name: Option<&str>| FirewallPolicyError::Locked {
+            pid: 1234,
+            name: name.map(Into::into),
+        };
+        let cases = [
+            (
+                ErrorCause::AuthFailed(Unknown),
+                "Authentication failed: unknown reason",
+                Some("Check that your account has time left"),
+            ),
+            (
+                ErrorCause::AuthFailed(InvalidAccount),
+                "Authentication failed: the account number is invalid",
+                Some("Log in again with a valid account number"),
+            ),
+            (
+                ErrorCause::AuthFailed(ExpiredAccount),
+                "Authentication failed: the account is out of time",
+                Some("Check that your account has time left"),
+            ),
+            (
+                ErrorCause::AuthFailed(TooManyConnections),
+                "Authentication failed: too many simultaneous connections",
+                Some("Disconnect one of your other devices"),
+            ),
+            (
+                ErrorCause::Ipv6Unavailable,
+                "IPv6 is unavailable",
+                Some("Turn off \"Enable IPv6\" in the settings"),
+            ),
+            (
+                ErrorCause::SetFirewallPolicy(None),
+                "Failed to configure the firewall",
+                Some("Check that no other VPN or firewall is active"),
+            ),
+            (
+                ErrorCause::SetFirewallPolicy(Some(FirewallPolicyError::Generic)),
+                "Failed to configure the firewall: Firewall error",
+                Some("Check that no other VPN or firewall is active"),
+            ),
+            (
+                ErrorCause::SetFirewallPolicy(Some(locked(Some("nordvpnd")))),
+                "Failed to configure the firewall: Firewall locked by nordvpnd (pid 1234)",
+                Some("Another VPN or firewall is active: nordvpnd"),
+            ),
+            (
+                ErrorCause::SetFirewallPolicy(Some(locked(None))),
+                "Failed to configure the firewall: Firewall locked by pid 1234",
+                Some("Check that no other VPN or firewall is active"),
+            ),
+            (
+                ErrorCause::SetDns,
+                "Failed to set the system DNS servers",
+                Some("Check the DNS configuration of the system"),
+            ),
+            (ErrorCause::StartTunnel, "Failed to start the tunnel", None),
+            (
+                ErrorCause::CreateTunnelDevice(Some(19)),
+                "Failed to create the tunnel device: error code 19",
+                Some("Check that the WireGuard kernel module is loaded"),
+            ),
+            (
+                ErrorCause::CreateTunnelDevice(None),
+                "Failed to create the tunnel device",
+                Some("Check that the WireGuard kernel module is loaded"),
+            ),
+            (
+                ErrorCause::TunnelParameter(NoMatchingRelay),
+                "Failed to choose tunnel parameters: no relay matches the current settings",
+                Some("Choose another location or relax the filters"),
+            ),
+            (
+                ErrorCause::TunnelParameter(NoMatchingBridgeRelay),
+                "Failed to choose tunnel parameters: no bridge matches the current settings",
+                Some("Choose another bridge location"),
+            ),
+            (
+                ErrorCause::TunnelParameter(NoWireguardKey),
+                "Failed to choose tunnel parameters: the device has no WireGuard key",
+                Some("Rotate the WireGuard key"),
+            ),
+            (
+                ErrorCause::TunnelParameter(CustomTunnelHostResolution),
+                "Failed to choose tunnel parameters: could not resolve the custom tunnel host",
+                Some("Check the custom tunnel host"),
+            ),
+            (
+                ErrorCause::IsOffline,
+                "This device is offline",
+                Some("Check your network connection"),
+            ),
+            (ErrorCause::VpnPermissionDenied, "Permission to create a VPN was denied", None),
+            (ErrorCause::SplitTunnel, "Split tunneling failed", None),
+        ];
+        for (cause, summary, suggestion) in cases {
+            let error_state = ErrorState { cause: cause.clone(), blocking_error: None };
+            assert_eq!(error_summary(&error_state), summary);
+            assert_eq!(error_suggestion(&cause).as_deref(), suggestion, "{cause:?}");
+        }
+    }
+
+    #[test]
+    fn error_details_say_whether_traffic_is_blocked() {
+        let error_state = ErrorState {
+            cause: ErrorCause::AuthFailed(AuthFailedError::ExpiredAccount),
+            blocking_error: None,
+        };
+        assert_eq!(
+            error_details(&error_state),
+            [
+                "Authentication failed",
+                "The account is out of time",
+                "Check that your account has time left",
+                "All traffic is blocked",
+            ]
+        );
+        let error_state = ErrorState {
+            cause: ErrorCause::StartTunnel,
+            blocking_error: Some(FirewallPolicyError::Generic),
+        };
+        assert_eq!(
+            error_details(&error_state),
+            ["Failed to start the tunnel", "Traffic is NOT blocked: Firewall error"]
+        );
+    }
+
+    fn fill(template: &str) -> String {
+        fill_template(template, |name| match name {
+            "state" => Some("connected".into()),
+            "hostname" => Some("se-got-wg-001".into()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn templates_are_filled_in() {
+        let cases = [
+            ("{state} {hostname}", "connected se-got-wg-001"),
+            ("{ state }: {hostname}", "connected: se-got-wg-001"),
+            ("mullvad", "mullvad"),
+            ("", ""),
+            // unknown placeholders and the gaps they leave disappear
+            ("{state}  {nonsense}  {hostname}", "connected se-got-wg-001"),
+            ("{}", ""),
+            ("{{state}}", "{state}"),
+            ("{{{state}}}", "{connected}"),
+            ("a }} b {{", "a } b {"),
+            ("{state", "{state"),
+            ("state}", "state}"),
+            ("{state} {", "connected {"),
+            ("}{state}{", "}connected{"),
+        ];
+        for (template, expected) in cases {
+            assert_eq!(fill(template), expected, "{template:?}");
+        }
+    }
+
+    #[test]
+    fn unknown_placeholders_are_found() {
+        let known = ["state", "hostname"];
+        assert_eq!(unknown_placeholders("{state} {hostname}", &known), [] as [&str; 0]);
+        assert_eq!(unknown_placeholders("{stat} {{host}} {city }", &known), ["stat", "city"]);
+        assert_eq!(unknown_placeholders("{state", &known), [] as [&str; 0]);
+    }
+
+    #[test]
+    fn bytes_are_shown_in_binary_units() {
+        let cases = [
+            (0, "0 B"),
+            (1023, "1023 B"),
+            (1024, "1.0 KiB"),
+            (1536, "1.5 KiB"),
+            (10 * 1024 - 1, "10.0 KiB"),
+            (10 * 1024, "10 KiB"),
+            (340 * 1024 * 1024, "340 MiB"),
+            (1288490189, "1.2 GiB"),
+            (5 * 1024_u64.pow(4), "5.0 TiB"),
+            // there is no bigger unit
+            (2048 * 1024_u64.pow(4), "2048 TiB"),
+            (u64::MAX, "16777216 TiB"),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(format_bytes(bytes), expected, "{bytes}");
+        }
+    }
+
+    #[test]
+    fn error_states_from_the_daemon_are_explained() {
+        use proto::error_state::firewall_policy_error::ErrorType;
+        use proto::error_state::{AuthFailedError, Cause, FirewallPolicyError, GenerationError};
+        let error_state = |cause: Cause| proto::ErrorState {
+            cause: cause as i32,
+            ..Default::default()
+        };
+        let locked = Some(FirewallPolicyError {
+            r#type: ErrorType::Locked as i32,
+            lock_pid: 1234,
+            lock_name: Some("nordvpnd".into()),
+        });
+        let cases: [(_, &[&str]); 10] = [
+            (
+                proto::ErrorState {
+                    auth_failed_error: AuthFailedError::ExpiredAccount as i32,
+                    ..error_state(Cause::AuthFailed)
+                },
+                &[
+                    "Authentication failed",
+                    "The account is out of time",
+                    "Check that your account has time left",
+                ],
+            ),
+            (
+                error_state(Cause::Ipv6Unavailable),
+                &["IPv6 is unavailable", "Turn off \"Enable IPv6\" in the settings"],
+            ),
+            (
+                proto::ErrorState {
+                    policy_error: locked,
+                    ..error_state(Cause::SetFirewallPolicyError)
+                },
+                &[
+                    "Failed to configure the firewall",
+                    "Firewall locked by nordvpnd (pid 1234)",
+                    "Another VPN or firewall is active: nordvpnd",
+                ],
+            ),
+            (
+                error_state(Cause::SetDnsError),
+                &[
+                    "Failed to set the system DNS servers",
+                    "Check the DNS configuration of the system",
+                ],
+            ),
+            (error_state(Cause::StartTunnelError), &["Failed to start the tunnel"]),
+            (
+                proto::ErrorState {
+                    create_tunnel_error: Some(19),
+                    ..error_state(Cause::CreateTunnelDevice)
+                },
+                &[
+                    "Failed to create the tunnel device",
+                    "Error code 19",
+                    "Check that the WireGuard kernel module is loaded",
+                ],
+            ),
+            (
+                proto::ErrorState {
+                    parameter_error: GenerationError::NoWireguardKey as i32,
+                    ..error_state(Cause::TunnelParameterError)
+                },
+                &[
+                    "Failed to choose tunnel parameters",
+                    "The device has no WireGuard key",
+                    "Rotate the WireGuard key",
+                ],
+            ),
+            (
+                error_state(Cause::IsOffline),
+                &["This device is offline", "Check your network connection"],
+            ),
+            (
+                error_state(Cause::VpnPermissionDenied),
+                &["Permission to create a VPN was denied"],
+            ),
+            (error_state(Cause::SplitTunnelError), &["Split tunneling failed"]),
+        ];
+        for (error_state, expected) in cases {
+            let error_state = ErrorState::try_from(error_state).unwrap();
+            let lines = error_details(&error_state);
+            assert_eq!(lines.last().map(String::as_str), Some("All traffic is blocked"));
+            assert_eq!(lines[..lines.len() - 1], *expected, "{error_state:?}");
+        }
+    }
+
+    #[test]
+    fn every_endpoint_combination_is_described() {
+        use crate::model::{Obfuscation, Proxy};
+        let obfuscation = |obfuscation_type, address: &str| Obfuscation {
+            obfuscation_type,
+            address: address.into(),
+            port: 443,
+        };
+        let udp2tcp = |address| Some(obfuscation(ObfuscationType::Udp2Tcp, address));
+        let shadowsocks = Some(obfuscation(ObfuscationType::Shadowsocks, "198.51.100.8"));
+        let obfuscations = [
+            (None, "Obfuscation: off"),
+            (udp2tcp("198.51.100.7"), "Obfuscation: udp2tcp via 198.51.100.7:443"),
+            (udp2tcp("2001:db8::7"), "Obfuscation: udp2tcp via [2001:db8::7]:443"),
+            (shadowsocks, "Obfuscation: Shadowsocks via 198.51.100.8:443"),
+        ];
+        let quantum_resistance =
+            [(false, "Quantum-resistant: no"), (true, "Quantum-resistant: yes")];
+        let proxy = |proxy_type, protocol| Proxy {
+            proxy_type,
+            address: "203.0.113.9:1080".into(),
+            protocol,
+        };
+        let proxies = [
+            (None, None),
+            (
+                Some(proxy(ProxyType::Shadowsocks, TransportProtocol::Udp)),
+                Some("Proxy: Shadowsocks via 203.0.113.9:1080 (UDP)"),
+            ),
+            (
+                Some(proxy(ProxyType::Custom, TransportProtocol::Tcp)),
+                Some("Proxy: custom proxy via 203.0.113.9:1080 (TCP)"),
+            ),
+        ];
+        for (obfuscation, obfuscation_line) in &obfuscations {
+            for (quantum_resistant, quantum_line) in quantum_resistance {
+                for (proxy, proxy_line) in &proxies {
+                    let endpoint = Endpoint {
+                        address: "185.213.154.1:51820".into(),
+                        tunnel_type: TunnelType::WireGuard,
+                        quantum_resistant,
+                        obfuscation: obfuscation.clone(),
+                        proxy: proxy.clone(),
+                        entry_address: None,
+                        tunnel_interface: None,
+                    };
+                    let mut expected = vec![*obfuscation_line, quantum_line];
+                    expected.extend(*proxy_line);
+                    assert_eq!(endpoint_lines(&endpoint), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn endpoints_from_a_newer_daemon_are_left_out() {
+        let relay_info = |proxy_type| {
+            RelayInfo::from(proto::TunnelStateRelayInfo {
+                tunnel_endpoint: Some(proto::TunnelEndpoint {
+                    address: "185.213.154.1:1194".into(),
+                    tunnel_type: proto::TunnelType::Openvpn as i32,
+                    quantum_resistant: true,
+                    proxy: Some(proto::ProxyEndpoint {
+                        address: "203.0.113.9:1080".into(),
+                        proxy_type,
+                        ..Default::default()
+                    }),
+                    entry_endpoint: Some(proto::Endpoint {
+                        address: "198.51.100.2:51820".into(),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                location: None,
+            })
+        };
+        assert_eq!(
+            status_lines(&relay_info(proto::ProxyType::Shadowsocks as i32)),
+            [
+                "Protocol: OpenVPN",
+                "Entry relay: 198.51.100.2",
+                "Obfuscation: off",
+                "Quantum-resistant: yes",
+                "Proxy: Shadowsocks via 203.0.113.9:1080 (UDP)",
+            ]
+        );
+        assert_eq!(status_lines(&relay_info(1000)), [] as [&str; 0]);
+    }
+}
@@ -0,0 +1,108 @@
+//! Icons bundled into the binary, for icon themes that don't have the `network-vpn` icons
+
+use std::sync::OnceLock;
+
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy)]
+pub enum BundledIcon {
+    Connected,
+    Connecting,
+    Disconnected,
+    Error,
+    /// Not connected, but traffic is blocked on purpose
+    Blocked,
+}
+
+const PNGS: [&[u8]; 5] = [
+    include_bytes!("../assets/icons/connected.png"),
+    include_bytes!("../assets/icons/connecting.png"),
+    include_bytes!("../assets/icons/disconnected.png"),
+    include_bytes!("../assets/icons/error.png"),
+    include_bytes!("../assets/icons/blocked.png"),
+];
+
+/// The pixels as ARGB32 in network byte order, as the StatusNotifierItem spec wants them
+fn to_argb(color_type: png::ColorType, pixels: &[u8]) -> Option<Vec<u8>> {
+    let argb = match color_type {
+        png::ColorType::Rgba => pixels
+            .chunks_exact(4)
+            .flat_map(|rgba| [rgba[3], rgba[0], rgba[1], rgba[2]])
+            .collect(),
+        png::ColorType::Rgb => pixels
+            .chunks_exact(3)
+            .flat_map(|rgb| [0xff, rgb[0], rgb[1], rgb[2]])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks_exact(2)
+            .flat_map(|ga| [ga[1], ga[0], ga[0], ga[0]])
+            .collect(),
+        png::ColorType::Grayscale => pixels.iter().flat_map(|&g| [0xff, g, g, g]).collect(),
+        // palettes are expanded by the decoder
+        png::ColorType::Indexed => return None,
+    };
+    Some(argb)
+}
+
+fn decode(bytes: &[u8]) -> Result<ksni::Icon, String> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).map_err(|e| e.to_string())?;
+    pixels.truncate(info.buffer_size());
+    let data = to_argb(info.color_type, &pixels)
+        .ok_or_else(|| format!("unsupported color type {:?}", info.color_type))?;
+    Ok(ksni::Icon {
+        width: info.width as i32,
+        height: info.height as i32,
+        data,
+    })
+}
+
+/// The icon for the state, decoded on first use. Empty if the icon is broken.
+pub fn pixmap(icon: BundledIcon) -> Vec<ksni::Icon> {
+    static ICONS: OnceLock<Vec<Option<ksni::Icon>>> = OnceLock::new();
+    let icons = ICONS.get_or_init(|| {
+        PNGS
+            .iter()
+            .map(|bytes| match decode(bytes) {
+                Ok(icon) => Some(icon),
+                Err(e) => {
+                    warn!("Could not decode a bundled icon: {}", e);
+                    None
+                }
+            })
+            .collect()
+    });
+    icons[icon as usize].iter().cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixels_become_argb() {
+        use png::ColorType::*;
+        let cases: [(_, &[u8], &[u8]); 4] = [
+            (Rgba, &[1, 2, 3, 4, 5, 6, 7, 8], &[4, 1, 2, 3, 8, 5, 6, 7]),
+            (Rgb, &[1, 2, 3, 4, 5, 6], &[0xff, 1, 2, 3, 0xff, 4, 5, 6]),
+            (GrayscaleAlpha, &[1, 2, 3, 4], &[2, 1, 1, 1, 4, 3, 3, 3]),
+            (Grayscale, &[1, 2], &[0xff, 1, 1, 1, 0xff, 2, 2, 2]),
+        ];
+        for (color_type, pixels, argb) in cases {
+            assert_eq!(to_argb(color_type, pixels).as_deref(), Some(argb), "{color_type:?}");
+        }
+        assert_eq!(to_argb(Indexed, &[0, 1]), None);
+    }
+
+    #[test]
+    fn bundled_icons_decode() {
+        for bytes in PNGS {
+            let icon = decode(bytes).unwrap();
+            assert!(icon.width > 0 && icon.height > 0);
+            assert_eq!(icon.data.len(), (icon.width * icon.height * 4) as usize);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Installing mulltray as an XDG autostart application, see
+//! <https://specifications.freedesktop.org/autostart-spec/latest/>
+
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::config::config_home;
+
+/// Marks desktop files that mulltray wrote so that it won't touch anyone else's
+const MARKER: &str = "X-Mulltray-Generated=true";
+/// Seconds to wait after login, the daemon is often still starting
+const AUTOSTART_DELAY: u32 = 5;
+
+#[derive(Debug)]
+pub enum Error {
+    NoConfigDir,
+    NoExecutable(std::io::Error),
+    /// The desktop file exists and wasn't created by mulltray
+    NotOurs(PathBuf),
+    Io(PathBuf, std::io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoConfigDir => write!(f, "Could not find the config directory"),
+            Error::NoExecutable(e) => write!(f, "Could not find the mulltray executable: {}", e),
+            Error::NotOurs(path) => write!(
+                f,
+                "{} was not created by mulltray, use --force to replace or remove it",
+                path.display()
+            ),
+            Error::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn desktop_file_path() -> Result<PathBuf, Error> {
+    Ok(config_home().ok_or(Error::NoConfigDir)?.join("autostart/mulltray.desktop"))
+}
+
+/// Quotes an argument of the `Exec` key as the desktop entry spec says. The value is also a
+/// string whose escapes are undone before the quoting, so every backslash is doubled again.
+fn quote_exec_arg(arg: &str) -> String {
+    if !arg.contains(|c: char| c.is_whitespace() || "\"'\\><~|&;$*?#()`".contains(c)) {
+        return arg.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '\\' => quoted.push_str(r"\\\\"),
+            '"' | '`' | '$' => {
+                quoted.push_str(r"\\");
+                quoted.push(c);
+            }
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Quotes an argument of `ExecStart` the way systemd splits command lines
+fn quote_unit_arg(arg: &str) -> String {
+    let arg = arg.replace('$', "$$");
+    if !arg.contains(|c: char| c.is_whitespace() || "\"'\\;".contains(c)) {
+        return arg;
+    }
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        if matches!(c, '"' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn check_ours(path: &PathBuf, force: bool) -> Result<bool, Error> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) if !force && !contents.lines().any(|line| line == MARKER) => {
+            Err(Error::NotOurs(path.clone()))
+        }
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(Error::Io(path.clone(), e)),
+    }
+}
+
+/// Writes the desktop file, returning what was done for the user
+pub fn install_autostart(force: bool) -> Result<String, Error> {
+    let path = desktop_file_path()?;
+    let existed = check_ours(&path, force)?;
+    let exe = std::env::current_exe().map_err(Error::NoExecutable)?;
+    let exec = quote_exec_arg(&exe.to_string_lossy()).replace('%', "%%");
+    let contents = format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=Mulltray\n\
+         Comment=Tray icon for the Mullvad VPN daemon\n\
+         Exec={exec}\n\
+         Icon=network-vpn\n\
+         Terminal=false\n\
+         X-GNOME-Autostart-Delay={AUTOSTART_DELAY}\n\
+         {MARKER}\n"
+    );
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| Error::Io(dir.to_path_buf(), e))?;
+    }
+    std::fs::write(&path, contents).map_err(|e| Error::Io(path.clone(), e))?;
+    let action = if existed { "Replaced" } else { "Created" };
+    Ok(format!("{action} {}, mulltray will start on login", path.display()))
+}
+
+/// Removes the desktop file, returning what was done for the user
+pub fn uninstall_autostart(force: bool) -> Result<String, Error> {
+    let path = desktop_file_path()?;
+    if !check_ours(&path, force)? {
+        return Ok(format!("{} doesn't exist, nothing to do", path.display()));
+    }
+    std::fs::remove_file(&path).map_err(|e| Error::Io(path.clone(), e))?;
+    Ok(format!("Removed {}, mulltray won't start on login anymore", path.display()))
+}
+
+/// A systemd user unit for running mulltray as a service, with readiness and watchdog
+/// notifications
+pub fn systemd_unit() -> Result<String, Error> {
+    let exe = std::env::current_exe().map_err(Error::NoExecutable)?;
+    let exec = quote_unit_arg(&exe.to_string_lossy()).replace('%', "%%");
+    Ok(format!(
+        "[Unit]\n\
+         Description=Tray icon for the Mullvad VPN daemon\n\
+         PartOf=graphical-session.target\n\
+         After=graphical-session.target\n\
+         Requisite=graphical-session.target\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         ExecStart={exec}\n\
+         Restart=on-failure\n\
+         # the daemon isn't always up when the session starts\n\
+         TimeoutStartSec=infinity\n\
+         WatchdogSec=60\n\
+         \n\
+         [Install]\n\
+         WantedBy=graphical-session.target\n"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exec_args_are_quoted() {
+        let cases = [
+            ("/usr/bin/mulltray", "/usr/bin/mulltray"),
+            ("/opt/my apps/mulltray", "\"/opt/my apps/mulltray\""),
+            ("/opt/\"quoted\"/mulltray", "\"/opt/\\\\\"quoted\\\\\"/mulltray\""),
+            ("/opt/$HOME/mulltray", "\"/opt/\\\\$HOME/mulltray\""),
+            ("/opt/back\\slash/mulltray", "\"/opt/back\\\\\\\\slash/mulltray\""),
+        ];
+        for (arg, expected) in cases {
+            assert_eq!(quote_exec_arg(arg), expected, "{arg}");
+        }
+    }
+
+    #[test]
+    fn unit_args_are_quoted() {
+        let cases = [
+            ("/usr/bin/mulltray", "/usr/bin/mulltray"),
+            ("/opt/my apps/mulltray", "\"/opt/my apps/mulltray\""),
+            ("/opt/back\\slash/mulltray", "\"/opt/back\\\\slash/mulltray\""),
+            ("/opt/$HOME/mulltray", "/opt/$$HOME/mulltray"),
+        ];
+        for (arg, expected) in cases {
+            assert_eq!(quote_unit_arg(arg), expected, "{arg}");
+        }
+    }
+
+    #[test]
+    fn only_our_own_desktop_files_are_touched() {
+        let dir = tempfile::tempdir().unwrap();
+        let ours = dir.path().join("ours.desktop");
+        std::fs::write(&ours, format!("[Desktop Entry]\nName=Mulltray\n{MARKER}\n")).unwrap();
+        let theirs = dir.path().join("theirs.desktop");
+        std::fs::write(&theirs, "[Desktop Entry]\nName=Mulltray\n").unwrap();
+        let missing = dir.path().join("missing.desktop");
+
+        assert!(check_ours(&ours, false).unwrap());
+        assert!(check_ours(&ours, true).unwrap());
+        assert!(matches!(check_ours(&theirs, false), Err(Error::NotOurs(path)) if path == theirs));
+        assert!(check_ours(&theirs, true).unwrap());
+        assert!(!check_ours(&missing, false).unwrap());
+        assert!(!check_ours(&missing, true).unwrap());
+    }
+}
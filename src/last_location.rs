@@ -0,0 +1,64 @@
+//! The last exit location chosen in mulltray, stored in
+//! `$XDG_STATE_HOME/mulltray/last_location.toml` so that it survives the daemon's settings
+//! being reset
+
+use std::path::PathBuf;
+
+use tracing::warn;
+
+use crate::favorites::Favorite;
+use crate::logging::state_dir;
+
+fn last_location_path() -> Option<PathBuf> {
+    Some(state_dir()?.join("last_location.toml"))
+}
+
+/// `None` if no location has been chosen or letting the daemon pick was chosen last
+pub fn load() -> Option<Favorite> {
+    let path = last_location_path()?;
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Could not read {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    match toml::from_str(&contents) {
+        Ok(location) => Some(location),
+        Err(e) => {
+            warn!("Invalid last location file {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Remembers the location, or forgets the last one with `None`
+pub fn save(location: Option<&Favorite>) {
+    let Some(path) = last_location_path() else {
+        warn!("Could not save the last location: no state directory");
+        return;
+    };
+    let result = match location {
+        Some(location) => {
+            let contents = match toml::to_string(location) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    warn!("Could not save the last location: {}", e);
+                    return;
+                }
+            };
+            path
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|()| std::fs::write(&path, contents))
+        }
+        None => match std::fs::remove_file(&path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        },
+    };
+    if let Err(e) = result {
+        warn!("Could not write {}: {}", path.display(), e);
+    }
+}
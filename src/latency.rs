@@ -0,0 +1,153 @@
+//! Rough latency to each relay, measured as the time it takes to open a TCP connection
+//!
+//! Relays answer a connection attempt with either a handshake or a reset, both of which take
+//! one round trip, so no raw sockets are needed.
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+
+use crate::app::MulltrayApp;
+
+const PROBE_PORT: u16 = 443;
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+// at most this many connection attempts per second so we don't look like a port scan
+const PROBE_INTERVAL: Duration = Duration::from_millis(200);
+const ROUND_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Measurements older than this aren't shown
+const STALE_AFTER: Duration = Duration::from_secs(45 * 60);
+// the menu is redrawn after this many measurements instead of after every one
+const BATCH_SIZE: usize = 20;
+// connection attempts in flight at once when there's no time to go through relays slowly
+const PARALLEL_PROBES: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    pub latency: Duration,
+    pub measured_at: Instant,
+}
+
+/// Latest measurement for each relay by hostname
+#[derive(Debug, Default)]
+pub struct Latencies(HashMap<String, Measurement>);
+
+impl Latencies {
+    /// `None` if the relay hasn't been measured recently or didn't respond
+    pub fn get(&self, hostname: &str) -> Option<Duration> {
+        self.0
+            .get(hostname)
+            .filter(|measurement| measurement.measured_at.elapsed() < STALE_AFTER)
+            .map(|measurement| measurement.latency)
+    }
+
+    /// The latest measurement even if it's stale, which is still a hint of how far the relay is
+    pub fn last_known(&self, hostname: &str) -> Option<Duration> {
+        self.0.get(hostname).map(|measurement| measurement.latency)
+    }
+
+    pub fn record(&mut self, hostname: String, latency: Option<Duration>) {
+        match latency {
+            Some(latency) => {
+                let measured_at = Instant::now();
+                self.0.insert(hostname, Measurement { latency, measured_at });
+            }
+            None => {
+                self.0.remove(&hostname);
+            }
+        }
+    }
+}
+
+async fn probe(addr: Ipv4Addr) -> Option<Duration> {
+    let started = Instant::now();
+    match tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect((addr, PROBE_PORT))).await {
+        Ok(Ok(_)) => Some(started.elapsed()),
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionRefused => Some(started.elapsed()),
+        _ => None,
+    }
+}
+
+/// Measures a few relays at a time in the given order, for when there's no time to go through
+/// them slowly. Relays that don't answer or aren't reached within `timeout` are left out.
+pub async fn probe_all(
+    relays: Vec<(String, Ipv4Addr)>,
+    timeout: Duration,
+) -> Vec<(String, Duration)> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut relays = relays.into_iter();
+    let mut probes = tokio::task::JoinSet::new();
+    let mut results = vec![];
+    loop {
+        while probes.len() < PARALLEL_PROBES {
+            let Some((hostname, addr)) = relays.next() else {
+                break;
+            };
+            probes.spawn(async move { (hostname, probe(addr).await) });
+        }
+        match tokio::time::timeout_at(deadline, probes.join_next()).await {
+            Ok(Some(Ok((hostname, Some(latency))))) => results.push((hostname, latency)),
+            Ok(Some(_)) => {}
+            // dropping the rest of the probes aborts them
+            Ok(None) | Err(_) => return results,
+        }
+    }
+}
+
+/// The active relays as (hostname, address) pairs, empty if measuring is turned off
+pub fn relays_to_measure(tray: &MulltrayApp) -> Vec<(String, Ipv4Addr)> {
+    if !tray.measure_latency {
+        return vec![];
+    }
+    tray.locations.countries
+        .iter()
+        .flat_map(|country| &country.cities)
+        .flat_map(|city| &city.relays)
+        .filter(|relay| relay.active)
+        .filter_map(|relay| Some((relay.hostname.clone(), relay.ipv4_addr_in?)))
+        .collect()
+}
+
+/// Measures every relay in `to_measure` periodically. A new round starts right away when
+/// it gets turned on.
+pub async fn measure_latencies(
+    mut to_measure: watch::Receiver<Vec<(String, Ipv4Addr)>>,
+    tray_handle: &ksni::Handle<MulltrayApp>,
+) {
+    loop {
+        let relays = to_measure.borrow_and_update().clone();
+        if relays.is_empty() {
+            if to_measure.changed().await.is_err() {
+                return std::future::pending().await;
+            }
+            continue;
+        }
+        let mut results = Vec::with_capacity(BATCH_SIZE);
+        for (hostname, addr) in relays {
+            results.push((hostname, probe(addr).await));
+            if results.len() == BATCH_SIZE {
+                let batch = std::mem::take(&mut results);
+                let still_enabled = tray_handle.update(|tray: &mut MulltrayApp| {
+                    batch.into_iter().for_each(|(host, l)| tray.latencies.record(host, l));
+                    tray.metrics_version += 1;
+                    tray.measure_latency
+                });
+                if !still_enabled {
+                    break;
+                }
+            }
+            tokio::time::sleep(PROBE_INTERVAL).await;
+        }
+        tray_handle.update(|tray: &mut MulltrayApp| {
+            results.into_iter().for_each(|(host, l)| tray.latencies.record(host, l));
+            tray.metrics_version += 1;
+        });
+        // a new relay list waits for the next round
+        tokio::select! {
+            _ = tokio::time::sleep(ROUND_INTERVAL) => {}
+            Ok(_) = to_measure.wait_for(Vec::is_empty) => {}
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Starting other programs without waiting for them, such as the Mullvad app
+
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Command, Stdio};
+
+pub const ACCOUNT_PAGE_URL: &str = "https://mullvad.net/account";
+const FLATPAK_ID: &str = "net.mullvad.MullvadVPN";
+
+/// Whether `program` is an executable file in one of the `PATH` directories
+pub fn on_path(program: &str) -> bool {
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+    std::env::split_paths(&path).any(|dir| {
+        dir
+            .join(program)
+            .metadata()
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    })
+}
+
+/// Starts a program with its output discarded. It is waited for in a thread of its own so
+/// that it doesn't linger as a zombie after it exits.
+pub fn spawn_detached(program: &str, args: &[&str]) -> io::Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// The programs that were found at startup
+#[derive(Debug)]
+pub struct Launchers {
+    /// Command that starts the Mullvad app, `None` if it isn't installed
+    pub mullvad_app: Option<(&'static str, Vec<&'static str>)>,
+    pub xdg_open: bool,
+}
+
+impl Launchers {
+    /// Looks for the programs. This asks flatpak about the app so it blocks for a moment.
+    pub fn detect() -> Self {
+        let flatpak_installed = || {
+            Command::new("flatpak")
+                .args(["info", FLATPAK_ID])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success())
+        };
+        let mullvad_app = if on_path("mullvad-gui") {
+            Some(("mullvad-gui", vec![]))
+        } else if on_path("mullvad-vpn") {
+            Some(("mullvad-vpn", vec![]))
+        } else if on_path("flatpak") && flatpak_installed() {
+            Some(("flatpak", vec!["run", FLATPAK_ID]))
+        } else {
+            None
+        };
+        Self {
+            mullvad_app,
+            xdg_open: on_path("xdg-open"),
+        }
+    }
+}
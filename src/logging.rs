@@ -0,0 +1,137 @@
+//! Diagnostics through `tracing`, filtered with the `MULLTRAY_LOG` environment variable
+//! (e.g. `MULLTRAY_LOG=debug`)
+
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use tonic::body::BoxBody;
+use tonic::codegen::http;
+use tonic::transport::Channel;
+use tracing::{debug, error, warn};
+use tracing_subscriber::EnvFilter;
+
+use crate::notify::Notifier;
+
+const LOG_ENV_VAR: &str = "MULLTRAY_LOG";
+/// The log file is moved aside at startup once it's bigger than this, keeping one old file
+const MAX_LOG_FILE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// `$XDG_STATE_HOME/mulltray`, falling back to `~/.local/state/mulltray`
+pub fn state_dir() -> Option<PathBuf> {
+    let state_home = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state_home.join("mulltray"))
+}
+
+fn open_log_file() -> std::io::Result<(PathBuf, File)> {
+    let dir = state_dir().ok_or(std::io::ErrorKind::NotFound)?;
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("mulltray.log");
+    if std::fs::metadata(&path).is_ok_and(|metadata| metadata.len() > MAX_LOG_FILE_SIZE) {
+        std::fs::rename(&path, path.with_extension("log.old"))?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    Ok((path, file))
+}
+
+/// Logs to stderr, or to a file in the state directory if `log_to_file` is set.
+/// `log_level` takes precedence over the environment variable.
+pub fn init(log_level: Option<&str>, log_to_file: bool) {
+    let filter = EnvFilter::builder()
+        .with_default_directive(tracing::Level::INFO.into())
+        .with_env_var(LOG_ENV_VAR);
+    let filter = match log_level {
+        Some(log_level) => filter.parse_lossy(log_level),
+        None => filter.from_env_lossy(),
+    };
+    // the default is stdout, which is where `status` and `watch` print their output
+    let builder = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(filter);
+    if !log_to_file {
+        builder.init();
+        return;
+    }
+    match open_log_file() {
+        Ok((path, file)) => {
+            builder.with_ansi(false).with_writer(Mutex::new(file)).init();
+            debug!("Logging to {}", path.display());
+        }
+        Err(e) => {
+            builder.init();
+            warn!("Could not open the log file, logging to stderr instead: {}", e);
+        }
+    }
+}
+
+/// Logs panics with a backtrace instead of printing them to stderr, where nobody sees them
+/// when mulltray was started by the desktop, and tells the user with a notification
+pub fn install_panic_hook(notifier: Notifier) {
+    std::panic::set_hook(Box::new(move |info| {
+        let backtrace = std::backtrace::Backtrace::force_capture();
+        error!("{}\n{}", info, backtrace);
+        notifier.notify(
+            "mulltray crashed internally".into(),
+            "The state shown by the icon may be stale".into(),
+        );
+    }));
+}
+
+/// A channel that logs every gRPC call with how long the daemon took to respond
+#[derive(Debug, Clone)]
+pub struct LoggingChannel(Channel);
+
+impl LoggingChannel {
+    pub fn new(channel: Channel) -> Self {
+        Self(channel)
+    }
+}
+
+type ResponseResult = Result<http::Response<tonic::transport::Body>, tonic::transport::Error>;
+type ResponseFuture = Pin<Box<dyn Future<Output = ResponseResult> + Send>>;
+
+impl tower::Service<http::Request<BoxBody>> for LoggingChannel {
+    type Response = http::Response<tonic::transport::Body>;
+    type Error = tonic::transport::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let method = request.uri().path().to_string();
+        let start = Instant::now();
+        let response = self.0.call(request);
+        Box::pin(async move {
+            let response = response.await;
+            let latency = start.elapsed();
+            match &response {
+                // errors usually come without a body, with the status in the headers
+                Ok(response) => {
+                    let status = response
+                        .headers()
+                        .get("grpc-status")
+                        .and_then(|status| status.to_str().ok())
+                        .and_then(|status| status.parse::<i32>().ok())
+                        .map(tonic::Code::from);
+                    match status {
+                        Some(code) if code != tonic::Code::Ok => {
+                            debug!(%method, ?latency, ?code, "gRPC call failed")
+                        }
+                        _ => debug!(%method, ?latency, "gRPC call"),
+                    }
+                }
+                Err(e) => debug!(%method, ?latency, error = %e, "gRPC call failed"),
+            }
+            response
+        })
+    }
+}
@@ -1,261 +1,282 @@
-use ksni::MenuItem;
-use tokio::net::UnixStream;
-use tonic::transport::Channel;
-use tower::service_fn;
+use std::collections::HashMap;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
 
+use clap::Parser;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, oneshot, watch, Notify};
+use tokio_util::task::TaskTracker;
+use tracing::{debug, warn};
+
+use crate::app::{
+    apply_tray_updates, check_title_format, reload_config_on_sighup, tick_while_connected,
+    AccountExpiry, AppState, Connection, LocationsStatus, MulltrayApp,
+};
+use crate::capabilities::DaemonCapabilities;
+use crate::cli::{Cli, Command, DEFAULT_SOCKET_PATH};
+use crate::commands::CommandRunner;
+use crate::config::Config;
+use crate::control::ControlService;
+use crate::control_socket::ControlSocket;
+use crate::daemon::{daemon_channel, refresh_account_expiry, stay_in_sync_with_daemon};
+use crate::error::Error;
+use crate::favorites::Favorites;
+use crate::latency::{measure_latencies, Latencies};
+use crate::launch::Launchers;
+use crate::logging::LoggingChannel;
+use crate::menu::LocationsCache;
+use crate::model::{RelayList, Settings};
+use crate::names::CountryNames;
+use crate::notify::Notifier;
 use crate::proto::management_service_client::ManagementServiceClient;
 
-pub mod proto {
-    tonic::include_proto!("mullvad_daemon.management_interface");
-}
+mod app;
+mod capabilities;
+mod cli;
+mod clipboard;
+mod commands;
+mod config;
+mod control;
+mod control_socket;
+mod daemon;
+mod debounce;
+mod diagnostics;
+mod error;
+mod favorites;
+mod format;
+mod icons;
+mod install;
+mod last_location;
+mod latency;
+mod launch;
+mod logging;
+mod menu;
+mod model;
+mod names;
+mod notify;
+mod prompt;
+mod resume;
+mod status;
+mod systemd;
+#[cfg(test)]
+mod testing;
+mod usage;
+pub mod proto;
 
-#[derive(Debug)]
-enum AppState {
-    Inactive,
-    Connected(proto::TunnelStateRelayInfo),
-    Connecting(proto::TunnelStateRelayInfo),
-    Disconnecting,
-    Disconnected,
-    Error(proto::tunnel_state::Error),
-}
-
-impl From<proto::TunnelState> for AppState {
-    fn from(value: proto::TunnelState) -> Self {
-        use crate::proto::tunnel_state::State;
-        match value.state {
-            None => AppState::Inactive,
-            Some(state) => match state {
-                State::Connecting(proto::tunnel_state::Connecting { relay_info }) => {
-                    AppState::Connecting(relay_info.unwrap_or_default())
-                }
-                State::Connected(proto::tunnel_state::Connected { relay_info }) => {
-                    AppState::Connected(relay_info.unwrap_or_default())
-                }
-                State::Disconnecting(_) => AppState::Disconnecting,
-                State::Disconnected(_) => AppState::Disconnected,
-                State::Error(x) => AppState::Error(x),
-            },
-        }
-    }
-}
+/// How long in-flight requests to the daemon get to finish when quitting
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(2);
 
-impl From<proto::GeographicLocationConstraint> for proto::LocationConstraint {
-    fn from(geo_loc_constraint: proto::GeographicLocationConstraint) -> Self {
-        Self {
-            r#type: Some(proto::location_constraint::Type::Location(geo_loc_constraint)),
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
         }
     }
 }
 
-#[derive(Debug)]
-struct MulltrayApp {
-    client: ManagementServiceClient<Channel>,
-    locations: proto::RelayList,
-    app_state: AppState,
-    tokio_handle: tokio::runtime::Handle,
-}
-
-impl MulltrayApp {
-    fn connect(&self) {
-        let mut client = self.client.clone();
-        self.tokio_handle.spawn(async move {
-            let _ = client.connect_tunnel(()).await;
-        });
-    }
-
-    fn disconnect(&self) {
-        let mut client = self.client.clone();
-        self.tokio_handle.spawn(async move {
-            let _ = client.disconnect_tunnel(()).await;
-        });
-    }
-
-    fn set_location(&self, country: String, city: Option<String>, hostname: Option<String>) {
-        let mut client = self.client.clone();
-        self.tokio_handle.spawn(async move {
-            match client.get_settings(()).await {
-                Ok(settings) => {
-                    let mut relay_settings = settings.into_inner().relay_settings.expect("there should be relay settings");
-                    let Some(proto::relay_settings::Endpoint::Normal(mut norm)) = relay_settings.endpoint else {
-                        eprintln!("Unsupported relay settings (only Normal settings are supported at this time)");
-                        return
-                    };
-                    norm.location = Some(proto::GeographicLocationConstraint { country, city, hostname }.into());
-                    relay_settings.endpoint = Some(proto::relay_settings::Endpoint::Normal(norm));
-                    if let Err(e) = client.set_relay_settings(relay_settings).await {
-                        eprintln!("Could not set relay location: {}", e.message());
-                    }
-                }
-                Err(e) => eprintln!("Could not get relay settings: {}", e.message()),
-            };
-        });
-    }
-}
-
-impl ksni::Tray for MulltrayApp {
-    fn activate(&mut self, _x: i32, _y: i32) {
-        eprintln!("{:?}", self.app_state);
-    }
-    fn title(&self) -> String {
-        fn find_hostname(relay_info: &proto::TunnelStateRelayInfo) -> &Option<String> {
-            match &relay_info.location {
-                Some(proto::GeoIpLocation { hostname, .. }) => hostname,
-                _ => &None,
-            }
-        }
-        let state = match &self.app_state {
-            AppState::Inactive => "inactive",
-            AppState::Connected(relay_info) => {
-                if let Some(hostname) = find_hostname(relay_info) {
-                    &format!("connected to {}", hostname)
-                } else {
-                    "connected to an unknown server"
-                }
-            }
-            AppState::Connecting(relay_info) => {
-                if let Some(hostname) = find_hostname(relay_info) {
-                    &format!("connecting to {}..", hostname)
-                } else {
-                    "connecting.."
-                }
-            }
-            AppState::Disconnecting => "disconnecting..",
-            AppState::Disconnected => "disconnected",
-            AppState::Error(err) => {
-                if let Some(proto::ErrorState { cause, .. }) = &err.error_state {
-                    &format!("error {}", cause)
-                } else {
-                    "error"
-                }
-            }
+async fn run() -> Result<(), Error> {
+    let cli = Cli::parse();
+    logging::init(cli.log_level.as_deref(), cli.log_file);
+    if cli.install_autostart || cli.uninstall_autostart {
+        let done = if cli.install_autostart {
+            install::install_autostart(cli.force)?
+        } else {
+            install::uninstall_autostart(cli.force)?
         };
-        format!("mulltray - {state}")
+        println!("{done}");
+        return Ok(());
     }
-    fn icon_name(&self) -> String {
-        match self.app_state {
-            AppState::Inactive => String::from("network-vpn-offline-symbolic"),
-            AppState::Error(_) => String::from("network-vpn-error-symbolic"),
-            AppState::Connecting(_) => String::from("network-vpn-acquiring-symbolic"),
-            AppState::Disconnecting => String::from("network-vpn-acquiring-symbolic"),
-            AppState::Disconnected => String::from("network-vpn-disconnected-symbolic"),
-            AppState::Connected(_) => String::from("network-vpn-symbolic"),
-        }
+    if cli.print_systemd_unit {
+        print!("{}", install::systemd_unit()?);
+        return Ok(());
     }
-    fn menu(&self) -> Vec<MenuItem<Self>> {
-        use ksni::menu::*;
-        let mut can_connect = false;
-        let mut can_disconnect = false;
-        match self.app_state {
-            AppState::Connected(_) | AppState::Connecting(_) => {
-                can_disconnect = true;
-            }
-            AppState::Disconnected => {
-                can_connect = true;
-            }
-            AppState::Disconnecting | AppState::Error(_) | AppState::Inactive => {}
-        }
-        let disconnect_item = StandardItem {
-            label: "Disconnect".into(),
-            visible: can_disconnect,
-            activate: Box::new(|this: &mut Self| this.disconnect()),
-            ..Default::default()
+    let tokio_handle = tokio::runtime::Handle::current();
+    let notifier = Notifier::new(tokio_handle.clone());
+    let config = match Config::load() {
+        Ok(config) => config,
+        // the subcommands have no icon to carry on with, and the config may name the socket
+        Err(e) if cli.command.is_some() => return Err(e.into()),
+        Err(e) => {
+            warn!("{}", e);
+            notifier.notify("Could not load the mulltray config".into(), e.to_string());
+            Config::default()
         }
-        .into();
-        let connect_item = StandardItem {
-            label: "Connect".into(),
-            visible: can_connect,
-            activate: Box::new(|this: &mut Self| this.connect()),
-            ..Default::default()
+    };
+    check_title_format(&config);
+    let country_names = if config.translate_country_names {
+        CountryNames::load().unwrap_or_else(|e| {
+            warn!("Showing the country names in English: {}", e);
+            CountryNames::default()
+        })
+    } else {
+        CountryNames::default()
+    };
+    let socket_path = cli.socket_path
+        .clone()
+        .or_else(|| config.socket_path.clone())
+        .unwrap_or_else(|| DEFAULT_SOCKET_PATH.into());
+    // the channel re-establishes the connection by itself when the daemon comes back
+    let channel = daemon_channel(socket_path.clone())
+        .map_err(|e| Error::Channel(socket_path.clone(), e))?;
+    let channel = LoggingChannel::new(channel);
+    let client = ManagementServiceClient::new(channel);
+    match cli.command {
+        Some(Command::Status { json }) => return status::print_status(client, json).await,
+        Some(Command::Watch { format }) => {
+            return status::watch(client, format)
+                .await
+                .map_err(|e| Error::Io("write the state to stdout", e));
         }
-        .into();
-
-        let mut locations_menu = vec![];
-        for country in &self.locations.countries {
-            let mut submenu: Vec<MenuItem<Self>> = vec![];
-            for city in &country.cities {
-                for relay in &city.relays {
-                    if relay.endpoint_type == proto::relay::RelayType::Wireguard.into() {
-                        let country_code = country.code.clone();
-                        let city_code = city.code.clone();
-                        let hostname = relay.hostname.clone();
-                        submenu.push(
-                            StandardItem {
-                                label: relay.hostname.to_string(),
-                                enabled: true,
-                                activate: Box::new(move |this: &mut Self| {
-                                    this.set_location(
-                                        country_code.clone(),
-                                        city_code.clone().into(),
-                                        hostname.clone().into(),
-                                    );
-                                }),
-                                ..Default::default()
-                            }
-                            .into(),
-                        )
-                    }
-                }
-            }
-            locations_menu.push(
-                SubMenu {
-                    label: country.name.clone(),
-                    submenu,
-                    ..Default::default()
-                }
-                .into(),
-            );
+        None => {}
+    }
+    let replace = cli.replace;
+    // waiting for the instance that's being replaced to quit would hold up a runtime worker
+    let claimed = tokio::task::spawn_blocking(move || control::claim_bus_name(replace)).await;
+    let bus = match claimed.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic())) {
+        Ok(bus) => Some(bus),
+        Err(control::Error::AlreadyRunning) => {
+            eprintln!("mulltray is already running, use --replace to replace it");
+            return Ok(());
         }
-        let locations_item = SubMenu {
-            label: "Choose location".into(),
-            submenu: locations_menu,
-            ..Default::default()
+        Err(e) => {
+            warn!("Could not claim the D-Bus name, not checking for other instances: {}", e);
+            None
         }
-        .into();
-        vec![locations_item, connect_item, disconnect_item]
-    }
-}
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let tokio_handle = tokio::runtime::Handle::current();
-    // (this tonic API is idiotic) the uri is ignored because unix sockets don't use it
-    let channel = tonic::transport::Endpoint::try_from("http://[::]:50051")?
-        .connect_with_connector(service_fn(|_: tonic::transport::Uri| {
-            let path = "/var/run/mullvad-vpn";
-            UnixStream::connect(path)
-        }))
-        .await?;
-    let mut client = ManagementServiceClient::new(channel);
-
-    let app_state = client.get_tunnel_state(()).await?.into_inner().into();
-    let streaming_response = client.events_listen(()).await?;
-    let mut stream = streaming_response.into_inner();
-    let locations = client.get_relay_locations(()).await?.into_inner();
+    };
+    let connect_on_start = cli.connect_on_start || config.connect_on_start;
+    logging::install_panic_hook(notifier.clone());
+    let quit = Arc::new(Notify::new());
+    let (connection, connection_rx) = watch::channel(Connection::Down);
+    let (relays_to_measure, relays_to_measure_rx) = watch::channel(vec![]);
+    let (state_names, state_names_rx) = watch::channel(AppState::DaemonUnavailable.name());
+    let (tray_updates, tray_updates_rx) = mpsc::unbounded_channel();
+    let (commands, commands_rx) = mpsc::unbounded_channel();
+    let commands_closed = Arc::new(Notify::new());
+    let tasks = TaskTracker::new();
 
     let app = MulltrayApp {
-        client,
-        locations,
-        app_state,
+        locations: RelayList::default(),
+        locations_status: LocationsStatus::Loading,
+        settings: Settings::default(),
+        app_state: AppState::DaemonUnavailable,
+        notifier: notifier.clone(),
+        notifications_enabled: config.notifications,
+        measure_latency: config.measure_latency,
         tokio_handle,
+        quit: quit.clone(),
+        tray_updates,
+        commands,
+        tasks: tasks.clone(),
+        config,
+        favorites: Favorites::load(),
+        last_location: last_location::load(),
+        account_expiry: AccountExpiry::Unknown,
+        device_state: None,
+        wireguard_key_created: None,
+        version_info: None,
+        latencies: Latencies::default(),
+        command_failure: None,
+        last_used: HashMap::new(),
+        locations_version: 0,
+        metrics_version: 0,
+        locations_cache: LocationsCache::default(),
+        relays_to_measure,
+        connected_since: None,
+        disconnect_requested: false,
+        disconnected_unexpectedly: false,
+        connection,
+        state_names,
+        scrolled: None,
+        session_usage: None,
+        measuring_fastest: false,
+        connect_on_start,
+        daemon_version: None,
+        socket_path,
+        last_error: None,
+        capabilities: DaemonCapabilities::default(),
+        split_tunnel_supported: false,
+        api_access_method: None,
+        launchers: Launchers::detect(),
+        country_names,
+        show_inactive_relays: false,
     };
+    let mut terminate = signal(SignalKind::terminate())
+        .map_err(|e| Error::Io("listen for SIGTERM", e))?;
     let tray = ksni::TrayService::new(app);
     let tray_handle = tray.handle();
-    tray.spawn();
+    let runner = CommandRunner::new(
+        client.clone(),
+        tray_handle.clone(),
+        state_names_rx.clone(),
+        notifier.clone(),
+        tasks.clone(),
+    );
+    tasks.spawn(runner.run(commands_rx, commands_closed.clone()));
+    // unlike `spawn` this lets us notice when the tray thread dies, a panic in one of the
+    // tray callbacks would otherwise leave a frozen icon behind
+    let (tray_stopped, tray_stopped_rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = tray_stopped.send(tray.run());
+    });
+    let systemd_state_names = state_names_rx.clone();
+    let resume_state_names = state_names_rx.clone();
+    let control = bus.map(|bus| ControlService::spawn(bus, tray_handle.clone(), state_names_rx));
+    let control_socket = ControlSocket::bind();
+    let serve_control_socket = async {
+        match &control_socket {
+            Some(control_socket) => control_socket.serve(&tray_handle).await,
+            None => std::future::pending().await,
+        }
+    };
 
-    while let Some(proto::DaemonEvent { event: Some(event) }) = stream.message().await? {
-        use proto::daemon_event::Event::*;
-        match event {
-            TunnelState(tunnel_state) => {
-                tray_handle
-                    .update(|tray: &mut MulltrayApp| tray.app_state = AppState::from(tunnel_state));
-            }
-            Settings(_) => {}
-            RelayList(_) => {}
-            VersionInfo(_) => {}
-            Device(_) => {}
-            RemoveDevice(_) => {}
-            NewAccessMethod(_) => {}
+    let result = tokio::select! {
+        result = tray_stopped_rx => match result {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(Error::TrayStopped(e)),
+            Err(_) => Err(Error::TrayCrashed),
+        },
+        _ = stay_in_sync_with_daemon(client.clone(), &tray_handle) => Ok(()),
+        _ = refresh_account_expiry(client, &tray_handle) => Ok(()),
+        _ = apply_tray_updates(tray_updates_rx, &tray_handle) => Ok(()),
+        _ = tick_while_connected(connection_rx, &tray_handle) => Ok(()),
+        _ = measure_latencies(relays_to_measure_rx, &tray_handle) => Ok(()),
+        _ = systemd::report_state(systemd_state_names) => Ok(()),
+        _ = systemd::keep_watchdog_happy() => Ok(()),
+        _ = reload_config_on_sighup(&tray_handle) => Ok(()),
+        _ = resume::reconnect_after_resume(resume_state_names, &tray_handle) => Ok(()),
+        _ = serve_control_socket => Ok(()),
+        _ = quit.notified() => Ok(()),
+        _ = terminate.recv() => {
+            debug!("Received SIGTERM");
+            Ok(())
         }
+        _ = tokio::signal::ctrl_c() => {
+            debug!("Received SIGINT");
+            Ok(())
+        }
+    };
+    systemd::notify("STOPPING=1");
+    // unregister the icon before anything else so it doesn't linger in the panel
+    tray_handle.shutdown();
+    notifier.shutdown();
+    if let Some(control) = control {
+        let _ = tokio::task::spawn_blocking(move || control.shutdown()).await;
+    }
+    if let Some(control_socket) = control_socket {
+        control_socket.remove();
+    }
+    // let a location the user just picked reach the daemon, but don't hang if it's unresponsive
+    commands_closed.notify_one();
+    tasks.close();
+    if tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, tasks.wait()).await.is_err() {
+        warn!("Gave up waiting for requests to the daemon to finish");
     }
-    Ok(())
+    // returning shuts down the runtime which cancels any RPCs still in flight and closes
+    // the channel along with the last client. A dead tray is an error so that a supervisor
+    // such as systemd can start mulltray again.
+    result
 }
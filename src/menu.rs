@@ -0,0 +1,2047 @@
+//! Menu construction from the tray state
+
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use ksni::MenuItem;
+use tracing::debug;
+
+use crate::app::{AccountExpiry, AppState, LocationKind, LocationsStatus, MulltrayApp};
+use crate::config::{MenuSection, SortLocations};
+use crate::diagnostics::Diagnostics;
+use crate::favorites::Favorite;
+use crate::format::{error_details, format_bytes, key_age_label, status_lines};
+use crate::latency::Latencies;
+use crate::model::{
+    BridgeState, City, Country, DeviceState, DnsBlocker, LocationConstraint, ObfuscationMode,
+    Ownership, QuantumResistant, Relay, RelayList, TunnelType,
+};
+
+/// DNS content blockers in the order they are shown in the menu
+const DNS_BLOCKERS: [(&str, DnsBlocker); 6] = [
+    ("Ads", DnsBlocker::Ads),
+    ("Trackers", DnsBlocker::Trackers),
+    ("Malware", DnsBlocker::Malware),
+    ("Adult content", DnsBlocker::AdultContent),
+    ("Gambling", DnsBlocker::Gambling),
+    ("Social media", DnsBlocker::SocialMedia),
+];
+
+/// Ports worth offering from the WireGuard port ranges: small ranges are listed fully while
+/// big ranges only get their endpoints and well-known ports that firewalls tend to let through
+fn wireguard_port_choices(port_ranges: &[RangeInclusive<u32>]) -> Vec<u32> {
+    const WELL_KNOWN_PORTS: [u32; 5] = [53, 80, 123, 443, 51820];
+    const SMALL_RANGE: u32 = 10;
+    let mut ports = vec![];
+    for range in port_ranges {
+        if range.end().saturating_sub(*range.start()) < SMALL_RANGE {
+            ports.extend(range.clone());
+        } else {
+            ports.extend([*range.start(), *range.end()]);
+            ports.extend(WELL_KNOWN_PORTS.iter().filter(|port| range.contains(port)));
+        }
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    ports
+}
+
+/// The countries of the relay list with only the relays that should be shown, leaving out
+/// cities and countries that end up without any relays
+fn filter_relay_list(
+    relay_list: &RelayList,
+    keep: impl Fn(&Relay) -> bool,
+) -> Vec<Country> {
+    let mut countries = vec![];
+    for country in &relay_list.countries {
+        let mut cities = vec![];
+        for city in &country.cities {
+            let relays: Vec<Relay> =
+                city.relays.iter().filter(|relay| keep(relay)).cloned().collect();
+            if !relays.is_empty() {
+                cities.push(City { relays, ..city.clone() });
+            }
+        }
+        if !cities.is_empty() {
+            countries.push(Country { cities, ..country.clone() });
+        }
+    }
+    countries
+}
+
+/// Orders the countries, cities and relays by `sort`. Entries without latency or usage data go
+/// last, in the alphabetical order they came in.
+fn sort_relay_list(
+    countries: &mut [Country],
+    sort: SortLocations,
+    latencies: &Latencies,
+    last_used: &HashMap<String, Instant>,
+) {
+    if sort == SortLocations::Alphabetical {
+        return;
+    }
+    // smaller is better for both, this way they can share the code below
+    let relay_key = |relay: &Relay| match sort {
+        SortLocations::Alphabetical => None,
+        SortLocations::Latency => latencies.get(&relay.hostname),
+        SortLocations::Recent => last_used.get(&relay.hostname).map(|used| used.elapsed()),
+    };
+    let sort_key = |key: Option<Duration>| (key.is_none(), key);
+    for country in countries.iter_mut() {
+        for city in &mut country.cities {
+            city.relays.sort_by_key(|relay| sort_key(relay_key(relay)));
+        }
+        let city_key = |city: &City| city.relays.iter().filter_map(relay_key).min();
+        country.cities.sort_by_key(|city| sort_key(city_key(city)));
+    }
+    countries.sort_by_key(|country| {
+        sort_key(country.cities.iter().flat_map(|city| &city.relays).filter_map(relay_key).min())
+    });
+}
+
+/// What the relay list of a location menu depends on, apart from the selected location
+#[derive(Debug, PartialEq)]
+struct LocationsKey {
+    kind: LocationKind,
+    locations_version: u64,
+    tunnel_type: Option<TunnelType>,
+    ownership: Ownership,
+    providers: Vec<String>,
+    show_inactive_relays: bool,
+    sort: SortLocations,
+    show_relays: bool,
+    show_relay_counts: bool,
+    /// The labels show the latencies, so this matters even when sorting alphabetically
+    metrics_version: u64,
+}
+
+/// A country of a location menu with the labels of its items worked out
+#[derive(Debug)]
+pub struct ListedCountry {
+    pub code: String,
+    pub name: String,
+    label: String,
+    pub cities: Vec<ListedCity>,
+}
+
+#[derive(Debug)]
+pub struct ListedCity {
+    pub code: String,
+    pub name: String,
+    /// Of the city item, or of its submenu if it has one
+    label: String,
+    /// Whether the city is picked directly rather than through a submenu of its relays
+    is_item: bool,
+    pub relays: Vec<ListedRelay>,
+}
+
+#[derive(Debug)]
+pub struct ListedRelay {
+    pub hostname: String,
+    label: String,
+    active: bool,
+}
+
+/// The filtered and sorted relay lists of the location menus along with their labels. The
+/// whole menu is built again on every tray update, but these only change when the relay list,
+/// the filters or the latencies do.
+#[derive(Debug, Default)]
+pub struct LocationsCache(Mutex<Vec<(LocationsKey, Arc<Vec<ListedCountry>>)>>);
+
+pub fn cached_locations(app: &MulltrayApp, kind: LocationKind) -> Arc<Vec<ListedCountry>> {
+    let norm = app.relay_settings().cloned().unwrap_or_default();
+    let sort = app.config.sort_locations;
+    let key = LocationsKey {
+        kind,
+        locations_version: app.locations_version,
+        tunnel_type: norm.tunnel_type,
+        ownership: norm.ownership,
+        providers: norm.providers,
+        show_inactive_relays: app.show_inactive_relays,
+        sort,
+        show_relays: app.config.show_relays,
+        show_relay_counts: app.config.show_relay_counts,
+        metrics_version: app.metrics_version,
+    };
+    let mut cache = app.locations_cache.0.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some((_, countries)) = cache.iter().find(|(cached, _)| *cached == key) {
+        return countries.clone();
+    }
+    debug!(?kind, "Filtering the relay list");
+    let mut countries = filter_relay_list(&app.locations, |relay| app.relay_is_listed(kind, relay));
+    sort_relay_list(&mut countries, sort, &app.latencies, &app.last_used);
+    let countries = Arc::new(list_locations(app, countries));
+    cache.retain(|(cached, _)| cached.kind != kind);
+    cache.push((key, countries.clone()));
+    countries
+}
+
+/// Works out the labels of the location menu items for `countries`
+fn list_locations(app: &MulltrayApp, countries: Vec<Country>) -> Vec<ListedCountry> {
+    // counted from the filtered relay list so that the numbers match what's listed
+    let with_count = |name: &str, count: usize| {
+        if app.config.show_relay_counts {
+            format!("{name} ({count})")
+        } else {
+            name.to_string()
+        }
+    };
+    let list_relay = |relay: Relay| ListedRelay {
+        label: match app.latencies.get(&relay.hostname) {
+            Some(latency) => format!("{} ({} ms)", relay.hostname, latency.as_millis()),
+            None => relay.hostname.clone(),
+        },
+        hostname: relay.hostname,
+        active: relay.active,
+    };
+    let list_city = |city: City| {
+        let relay_count = city.relays.len();
+        let relays: Vec<ListedRelay> = city.relays.into_iter().map(list_relay).collect();
+        // a submenu with a single relay in it would only be one more level to click
+        // through, so the city is picked directly and the relay is shown next to it
+        let (label, is_item) = match relays.as_slice() {
+            [relay] if app.config.show_relays => {
+                (format!("{} — {}", city.name, relay.label), true)
+            }
+            _ => (with_count(&city.name, relay_count), !app.config.show_relays),
+        };
+        ListedCity { code: city.code, name: city.name, label, is_item, relays }
+    };
+    countries
+        .into_iter()
+        .map(|country| {
+            let relay_count = country.cities.iter().map(|city| city.relays.len()).sum();
+            ListedCountry {
+                label: with_count(&country.name, relay_count),
+                code: country.code,
+                name: country.name,
+                cities: country.cities.into_iter().map(list_city).collect(),
+            }
+        })
+        .collect()
+}
+
+pub fn tray_menu(app: &MulltrayApp) -> Vec<MenuItem<MulltrayApp>> {
+    if app.config.minimal_menu {
+        return minimal_menu(app);
+    }
+    let hidden = &app.config.hide_menu_sections;
+    let sections = [
+        (Some(MenuSection::Account), account_section(app)),
+        (Some(MenuSection::Status), status_section(app)),
+        (Some(MenuSection::Actions), actions_section(app)),
+        (Some(MenuSection::Location), location_section(app)),
+        (Some(MenuSection::Settings), settings_section(app)),
+        // can't be hidden since it has Quit
+        (None, app_section(app)),
+    ];
+    let mut menu = vec![];
+    for (section, items) in sections {
+        // a section with nothing to show would leave two separators in a row
+        if section.is_some_and(|section| hidden.contains(&section)) || !items.iter().any(shows) {
+            continue;
+        }
+        if !menu.is_empty() {
+            menu.push(MenuItem::Separator);
+        }
+        menu.extend(items);
+    }
+    menu
+}
+
+/// Just connecting or disconnecting and quitting, for cramped trays
+fn minimal_menu(app: &MulltrayApp) -> Vec<MenuItem<MulltrayApp>> {
+    use ksni::menu::*;
+    let disconnects = app.toggle_disconnects();
+    // toggling does nothing while the tunnel is on its way up or down
+    let in_transition = matches!(app.app_state, AppState::Connecting(_) | AppState::Disconnecting);
+    let can_connect = app.is_logged_in() && !app.account_expiry.is_expired();
+    let toggle_item = StandardItem {
+        label: if disconnects { "Disconnect" } else { "Connect" }.into(),
+        enabled: !in_transition && (disconnects || can_connect),
+        activate: Box::new(|this: &mut MulltrayApp| this.toggle_connection()),
+        ..Default::default()
+    }
+    .into();
+    let quit_item = StandardItem {
+        label: "Quit".into(),
+        activate: Box::new(|this: &mut MulltrayApp| this.quit()),
+        ..Default::default()
+    }
+    .into();
+    vec![toggle_item, quit_item]
+}
+
+fn shows(item: &MenuItem<MulltrayApp>) -> bool {
+    match item {
+        MenuItem::Standard(item) => item.visible,
+        MenuItem::Checkmark(item) => item.visible,
+        MenuItem::SubMenu(item) => item.visible,
+        MenuItem::RadioGroup(_) => true,
+        MenuItem::Separator => false,
+    }
+}
+
+/// The device, the account and the WireGuard key, and available updates
+fn account_section(app: &MulltrayApp) -> Vec<MenuItem<MulltrayApp>> {
+    use ksni::menu::*;
+    let device_name = match &app.device_state {
+        Some(DeviceState::LoggedIn(Some(account_and_device))) => {
+            Some(account_and_device.device_name.clone())
+        }
+        _ => None,
+    };
+    let device_item = StandardItem {
+        label: match device_name {
+            Some(name) => format!("Device: {name}"),
+            None => "Not logged in".into(),
+        },
+        enabled: false,
+        visible: app.device_state.is_some(),
+        ..Default::default()
+    }
+    .into();
+    let logged_in = app.device_state.is_some() && app.is_logged_in();
+    let log_in_item = StandardItem {
+        label: "Log in...".into(),
+        visible: app.device_state.is_some() && !logged_in,
+        activate: Box::new(|this: &mut MulltrayApp| this.log_in()),
+        ..Default::default()
+    }
+    .into();
+    let log_out_item = StandardItem {
+        label: "Log out".into(),
+        visible: logged_in,
+        activate: Box::new(|this: &mut MulltrayApp| this.log_out()),
+        ..Default::default()
+    }
+    .into();
+    let redeem_voucher_item = StandardItem {
+        label: "Redeem voucher...".into(),
+        visible: logged_in,
+        activate: Box::new(|this: &mut MulltrayApp| this.redeem_voucher()),
+        ..Default::default()
+    }
+    .into();
+    let key_age_item = StandardItem {
+        label: app.wireguard_key_created.map(key_age_label).unwrap_or_default(),
+        enabled: false,
+        visible: logged_in && app.wireguard_key_created.is_some(),
+        ..Default::default()
+    }
+    .into();
+    let rotate_key_item = StandardItem {
+        label: "Rotate WireGuard key".into(),
+        visible: logged_in,
+        activate: Box::new(|this: &mut MulltrayApp| this.rotate_wireguard_key()),
+        ..Default::default()
+    }
+    .into();
+    let update_label = app.update_label();
+    let update_item = StandardItem {
+        visible: update_label.is_some(),
+        label: update_label.unwrap_or_default(),
+        enabled: false,
+        ..Default::default()
+    }
+    .into();
+    let account_item = StandardItem {
+        label: app.account_expiry.label(),
+        enabled: false,
+        visible: !matches!(app.account_expiry, AccountExpiry::Unknown),
+        ..Default::default()
+    }
+    .into();
+    vec![
+        update_item,
+        device_item,
+        account_item,
+        log_in_item,
+        log_out_item,
+        redeem_voucher_item,
+        key_age_item,
+        rotate_key_item,
+    ]
+}
+
+/// What the tunnel is doing, read-only apart from getting out of an error
+fn status_section(app: &MulltrayApp) -> Vec<MenuItem<MulltrayApp>> {
+    let mut menu = error_menu(app);
+    menu.extend([status_menu(app), copy_menu(app)]);
+    menu
+}
+
+/// Bringing the tunnel up and down
+fn actions_section(app: &MulltrayApp) -> Vec<MenuItem<MulltrayApp>> {
+    use ksni::menu::*;
+    let mut can_connect = false;
+    let mut can_disconnect = false;
+    let mut can_reconnect = false;
+    match app.app_state {
+        AppState::Connected(_) | AppState::Connecting(_) => {
+            can_disconnect = true;
+            can_reconnect = true;
+        }
+        AppState::Disconnected | AppState::Blocked => {
+            can_connect = true;
+        }
+        AppState::Error(_) => {
+            can_reconnect = true;
+        }
+        AppState::Disconnecting
+        | AppState::Inactive
+        | AppState::DaemonUnavailable => {}
+    }
+    let disconnect_item = StandardItem {
+        label: "Disconnect".into(),
+        visible: can_disconnect,
+        activate: Box::new(|this: &mut MulltrayApp| this.disconnect()),
+        ..Default::default()
+    }
+    .into();
+    // checked whenever the menu is built, so an account that runs out between two refreshes
+    // of the expiry is caught too
+    let account_expired = app.account_expiry.is_expired();
+    let connect_item = StandardItem {
+        label: if account_expired { "Account expired — add time" } else { "Connect" }.into(),
+        visible: can_connect,
+        enabled: app.is_logged_in() && !account_expired,
+        activate: Box::new(|this: &mut MulltrayApp| this.connect()),
+        ..Default::default()
+    }
+    .into();
+    let connect_fastest_item = StandardItem {
+        label: "Connect to fastest".into(),
+        enabled: app.is_logged_in() && !account_expired && !app.measuring_fastest,
+        visible: app.locations_status == LocationsStatus::Loaded,
+        activate: Box::new(|this: &mut MulltrayApp| this.connect_to_fastest()),
+        ..Default::default()
+    }
+    .into();
+    let reconnect_item = StandardItem {
+        label: "Reconnect".into(),
+        visible: can_reconnect,
+        activate: Box::new(|this: &mut MulltrayApp| this.reconnect()),
+        ..Default::default()
+    }
+    .into();
+
+    vec![connect_item, connect_fastest_item, reconnect_item, disconnect_item]
+}
+
+/// Favorites and the exit and entry locations
+fn location_section(app: &MulltrayApp) -> Vec<MenuItem<MulltrayApp>> {
+    use ksni::menu::*;
+    let search_location_item = StandardItem {
+        label: "Search location…".into(),
+        visible: app.locations_status == LocationsStatus::Loaded,
+        activate: Box::new(|this: &mut MulltrayApp| this.search_location()),
+        ..Default::default()
+    }
+    .into();
+    let mut exit_locations_menu = filters_menu(app);
+    exit_locations_menu.push(MenuItem::Separator);
+    exit_locations_menu.extend(locations_menu(app, LocationKind::Exit));
+    let locations_item = SubMenu {
+        label: "Choose location".into(),
+        submenu: exit_locations_menu,
+        ..Default::default()
+    }
+    .into();
+    let restore_location_item = StandardItem {
+        label: match app.last_location.as_ref().and_then(|location| app.favorite_label(location)) {
+            Some(label) => format!("Restore last location ({label})"),
+            None => "Restore last location".into(),
+        },
+        enabled: app.restorable_location().is_some(),
+        visible: app.last_location.is_some(),
+        activate: Box::new(|this: &mut MulltrayApp| this.restore_last_location()),
+        ..Default::default()
+    }
+    .into();
+    let use_multihop = app.relay_settings().is_some_and(|relay| relay.use_multihop);
+    let mut entry_locations_menu = vec![
+        CheckmarkItem {
+            label: "Use multihop".into(),
+            checked: use_multihop,
+            activate: Box::new(move |this: &mut MulltrayApp| this.set_multihop(!use_multihop)),
+            ..Default::default()
+        }
+        .into(),
+        MenuItem::Separator,
+    ];
+    entry_locations_menu.extend(locations_menu(app, LocationKind::Entry));
+    let entry_locations_item = SubMenu {
+        label: "Entry location (multihop)".into(),
+        submenu: entry_locations_menu,
+        ..Default::default()
+    }
+    .into();
+    let mut menu = favorites_menu(app);
+    menu.extend([
+        MenuItem::Separator,
+        locations_item,
+        search_location_item,
+        restore_location_item,
+        entry_locations_item,
+    ]);
+    menu
+}
+
+/// The daemon settings, and the ones of mulltray that are worth toggling often
+fn settings_section(app: &MulltrayApp) -> Vec<MenuItem<MulltrayApp>> {
+    use ksni::menu::*;
+    let lockdown_item = CheckmarkItem {
+        label: "Lockdown mode".into(),
+        checked: app.settings.block_when_disconnected,
+        activate: Box::new(|this: &mut MulltrayApp| {
+            this.set_block_when_disconnected(!this.settings.block_when_disconnected)
+        }),
+        ..Default::default()
+    }
+    .into();
+    let auto_connect_item = CheckmarkItem {
+        label: "Connect on startup".into(),
+        checked: app.settings.auto_connect,
+        activate: Box::new(|this: &mut MulltrayApp| {
+            this.set_auto_connect(!this.settings.auto_connect)
+        }),
+        ..Default::default()
+    }
+    .into();
+    let apply_location_item = CheckmarkItem {
+        label: "Apply location immediately".into(),
+        checked: app.config.apply_location_immediately,
+        activate: Box::new(|this: &mut MulltrayApp| this.toggle_apply_location_immediately()),
+        ..Default::default()
+    }
+    .into();
+    let allow_lan_item = CheckmarkItem {
+        label: "Allow LAN access".into(),
+        checked: app.settings.allow_lan,
+        activate: Box::new(|this: &mut MulltrayApp| this.set_allow_lan(!this.settings.allow_lan)),
+        ..Default::default()
+    }
+    .into();
+    let ipv6_enabled = app.settings.enable_ipv6;
+    let ipv6_item = CheckmarkItem {
+        label: "Enable IPv6".into(),
+        checked: ipv6_enabled,
+        activate: Box::new(move |this: &mut MulltrayApp| this.set_enable_ipv6(!ipv6_enabled)),
+        ..Default::default()
+    }
+    .into();
+    let dns = &app.settings.dns;
+    let dns_blocking_item = SubMenu {
+        label: "DNS blocking".into(),
+        // content blockers only apply to the default DNS servers
+        enabled: !dns.custom,
+        submenu: DNS_BLOCKERS
+            .into_iter()
+            .map(|(label, blocker)| {
+                let enabled = dns.blockers.contains(&blocker);
+                CheckmarkItem {
+                    label: label.into(),
+                    checked: enabled,
+                    activate: Box::new(move |this: &mut MulltrayApp| {
+                        this.set_dns_blocker(blocker, !enabled)
+                    }),
+                    ..Default::default()
+                }
+                .into()
+            })
+            .collect(),
+        ..Default::default()
+    }
+    .into();
+    let custom_addresses = dns.custom_servers.clone();
+    let mut dns_servers: Vec<(String, Vec<String>)> = app.config.dns_servers
+        .iter()
+        .map(|server| {
+            let addresses = server.addresses.iter().map(|ip| ip.to_string()).collect();
+            (server.name.clone(), addresses)
+        })
+        .collect();
+    let mut selected_server = dns_servers
+        .iter()
+        .position(|(_, addresses)| *addresses == custom_addresses);
+    if selected_server.is_none() && !custom_addresses.is_empty() {
+        // the servers were set from somewhere else (e.g. the official GUI),
+        // show them so that the selection isn't misleading
+        let label = format!("Other ({})", custom_addresses.join(", "));
+        dns_servers.push((label, custom_addresses.clone()));
+        selected_server = Some(dns_servers.len() - 1);
+    }
+    let use_custom_dns = dns.custom;
+    let mut custom_dns_menu = vec![
+        CheckmarkItem {
+            label: "Use custom DNS".into(),
+            checked: use_custom_dns,
+            enabled: use_custom_dns || dns_servers.iter().any(|(_, a)| !a.is_empty()),
+            activate: Box::new(move |this: &mut MulltrayApp| {
+                this.set_custom_dns_enabled(!use_custom_dns)
+            }),
+            ..Default::default()
+        }
+        .into(),
+        MenuItem::Separator,
+    ];
+    if dns_servers.is_empty() {
+        custom_dns_menu.push(
+            StandardItem {
+                label: "No DNS servers in config.toml".into(),
+                enabled: false,
+                ..Default::default()
+            }
+            .into(),
+        );
+    } else {
+        custom_dns_menu.push(
+            RadioGroup {
+                // nothing is marked if the index is out of bounds
+                selected: selected_server.unwrap_or(usize::MAX),
+                options: dns_servers
+                    .iter()
+                    .map(|(label, _)| RadioItem {
+                        label: label.clone(),
+                        ..Default::default()
+                    })
+                    .collect(),
+                select: Box::new(move |this: &mut MulltrayApp, i| {
+                    if Some(i) != selected_server || !use_custom_dns {
+                        this.set_custom_dns_servers(dns_servers[i].1.clone());
+                    }
+                }),
+            }
+            .into(),
+        );
+    }
+    let custom_dns_item = SubMenu {
+        label: "Custom DNS".into(),
+        submenu: custom_dns_menu,
+        ..Default::default()
+    }
+    .into();
+    let daita_enabled = app.settings.daita;
+    let daita_item = CheckmarkItem {
+        label: "DAITA".into(),
+        visible: app.capabilities.daita,
+        checked: daita_enabled,
+        activate: Box::new(move |this: &mut MulltrayApp| this.set_daita(!daita_enabled)),
+        ..Default::default()
+    }
+    .into();
+    let settings_item = SubMenu {
+        label: "Settings".into(),
+        submenu: vec![
+            lockdown_item,
+            auto_connect_item,
+            apply_location_item,
+            allow_lan_item,
+            ipv6_item,
+            split_tunnel_menu(app),
+            dns_blocking_item,
+            custom_dns_item,
+            tunnel_protocol_menu(app),
+            quantum_resistant_menu(app),
+            daita_item,
+            obfuscation_menu(app),
+            bridge_menu(app),
+            wireguard_port_menu(app),
+            wireguard_mtu_menu(app),
+            api_access_menu(app),
+        ],
+        ..Default::default()
+    }
+    .into();
+    let notifications_item = CheckmarkItem {
+        label: "Notifications".into(),
+        checked: app.notifications_enabled,
+        activate: Box::new(|this: &mut MulltrayApp| {
+            this.notifications_enabled = !this.notifications_enabled
+        }),
+        ..Default::default()
+    }
+    .into();
+    let measure_latency_item = CheckmarkItem {
+        label: "Measure latency".into(),
+        checked: app.measure_latency,
+        activate: Box::new(|this: &mut MulltrayApp| this.toggle_measure_latency()),
+        ..Default::default()
+    }
+    .into();
+    vec![settings_item, notifications_item, measure_latency_item]
+}
+
+/// Other apps, about mulltray, and quitting
+fn app_section(app: &MulltrayApp) -> Vec<MenuItem<MulltrayApp>> {
+    use ksni::menu::*;
+    let open_app_item = StandardItem {
+        label: "Open Mullvad app".into(),
+        visible: app.launchers.mullvad_app.is_some(),
+        activate: Box::new(|this: &mut MulltrayApp| this.open_mullvad_app()),
+        ..Default::default()
+    }
+    .into();
+    let open_account_page_item = StandardItem {
+        label: "Open account page".into(),
+        visible: app.launchers.xdg_open,
+        activate: Box::new(|this: &mut MulltrayApp| this.open_account_page()),
+        ..Default::default()
+    }
+    .into();
+    let quit_item = StandardItem {
+        label: "Quit".into(),
+        activate: Box::new(|this: &mut MulltrayApp| this.quit()),
+        ..Default::default()
+    }
+    .into();
+    let reload_config_item = StandardItem {
+        label: "Reload configuration".into(),
+        activate: Box::new(|this: &mut MulltrayApp| this.reload_config()),
+        ..Default::default()
+    }
+    .into();
+    vec![
+        open_app_item,
+        open_account_page_item,
+        about_menu(app),
+        reload_config_item,
+        quit_item,
+    ]
+}
+
+/// Details of the tunnel, hidden when there's no tunnel
+fn status_menu(app: &MulltrayApp) -> MenuItem<MulltrayApp> {
+    use ksni::menu::*;
+    let mut lines = app.app_state.relay_info().map(status_lines).unwrap_or_default();
+    let usage = app.session_usage
+        .as_ref()
+        .filter(|_| matches!(app.app_state, AppState::Connected(_)))
+        .map(|usage| usage.total());
+    if let Some(usage) = usage {
+        lines.push(format!(
+            "Session usage: {} ↓ / {} ↑",
+            format_bytes(usage.rx_bytes),
+            format_bytes(usage.tx_bytes)
+        ));
+    }
+    SubMenu {
+        label: "Status".into(),
+        visible: !lines.is_empty(),
+        submenu: lines
+            .into_iter()
+            .map(|line| {
+                StandardItem {
+                    label: line,
+                    enabled: false,
+                    ..Default::default()
+                }
+                .into()
+            })
+            .collect(),
+        ..Default::default()
+    }
+    .into()
+}
+
+/// What went wrong and a way out of the blocked state
+fn error_menu(app: &MulltrayApp) -> Vec<MenuItem<MulltrayApp>> {
+    use ksni::menu::*;
+    let AppState::Error(Some(error_state)) = &app.app_state else {
+        return vec![];
+    };
+    let mut menu: Vec<MenuItem<MulltrayApp>> = error_details(error_state)
+        .into_iter()
+        .map(|line| {
+            StandardItem {
+                label: line,
+                enabled: false,
+                ..Default::default()
+            }
+            .into()
+        })
+        .collect();
+    menu.push(
+        StandardItem {
+            label: "Dismiss error / Disconnect".into(),
+            activate: Box::new(|this: &mut MulltrayApp| this.disconnect()),
+            ..Default::default()
+        }
+        .into(),
+    );
+    menu
+}
+
+fn copy_menu(app: &MulltrayApp) -> MenuItem<MulltrayApp> {
+    use ksni::menu::*;
+    let location = match &app.app_state {
+        AppState::Connected(Some(relay_info)) => relay_info.location.as_ref(),
+        _ => None,
+    };
+    let copy_item = |label: &str, value: Option<&String>| {
+        let value = value.cloned();
+        StandardItem {
+            label: label.into(),
+            enabled: value.is_some(),
+            activate: Box::new(move |this: &mut MulltrayApp| {
+                if let Some(value) = value.clone() {
+                    this.copy_to_clipboard(value);
+                }
+            }),
+            ..Default::default()
+        }
+        .into()
+    };
+    SubMenu {
+        label: "Copy".into(),
+        enabled: location.is_some(),
+        submenu: vec![
+            copy_item("Copy exit IPv4", location.and_then(|l| l.ipv4.as_ref())),
+            copy_item("Copy exit IPv6", location.and_then(|l| l.ipv6.as_ref())),
+            copy_item("Copy relay hostname", location.and_then(|l| l.hostname.as_ref())),
+        ],
+        ..Default::default()
+    }
+    .into()
+}
+
+/// Versions and where the daemon is reached, for bug reports
+fn about_menu(app: &MulltrayApp) -> MenuItem<MulltrayApp> {
+    use ksni::menu::*;
+    let info_item = |label: String| {
+        StandardItem {
+            label,
+            enabled: false,
+            ..Default::default()
+        }
+        .into()
+    };
+    let daemon_version = app.daemon_version.as_deref().unwrap_or("unknown");
+    SubMenu {
+        label: "About".into(),
+        submenu: vec![
+            info_item(format!("mulltray {}", env!("CARGO_PKG_VERSION"))),
+            info_item(format!("Daemon version: {}", daemon_version)),
+            info_item(format!("Socket: {}", app.socket_path.display())),
+            MenuItem::Separator,
+            StandardItem {
+                label: "Copy diagnostics".into(),
+                activate: Box::new(|this: &mut MulltrayApp| {
+                    this.copy_to_clipboard(Diagnostics::from_app(this).text());
+                }),
+                ..Default::default()
+            }
+            .into(),
+        ],
+        ..Default::default()
+    }
+    .into()
+}
+
+/// How the daemon reaches the Mullvad API, for networks that block it
+fn api_access_menu(app: &MulltrayApp) -> MenuItem<MulltrayApp> {
+    use ksni::menu::*;
+    let current = app.api_access_method.as_ref().map(|method| &method.id);
+    SubMenu {
+        label: "API access".into(),
+        visible: app.capabilities.api_access_methods,
+        submenu: app.settings.api_access_methods
+            .iter()
+            .map(|method| {
+                let chosen = method.clone();
+                CheckmarkItem {
+                    label: method.name.clone(),
+                    // the daemon won't use methods that are turned off
+                    enabled: method.enabled,
+                    checked: Some(&method.id) == current,
+                    activate: Box::new(move |this: &mut MulltrayApp| {
+                        this.set_api_access_method(chosen.clone());
+                    }),
+                    ..Default::default()
+                }
+                .into()
+            })
+            .collect(),
+        ..Default::default()
+    }
+    .into()
+}
+
+fn obfuscation_menu(app: &MulltrayApp) -> MenuItem<MulltrayApp> {
+    use ksni::menu::*;
+    const MODES: [(&str, ObfuscationMode); 4] = [
+        ("Auto", ObfuscationMode::Auto),
+        ("Off", ObfuscationMode::Off),
+        ("udp2tcp", ObfuscationMode::Udp2Tcp),
+        ("Shadowsocks", ObfuscationMode::Shadowsocks),
+    ];
+    let current = app.settings.obfuscation;
+    // older daemons don't know Shadowsocks, unless it's what they were set to somehow
+    let modes: Vec<_> = MODES
+        .into_iter()
+        .filter(|(_, mode)| {
+            *mode != ObfuscationMode::Shadowsocks
+                || app.capabilities.shadowsocks_obfuscation
+                || current == ObfuscationMode::Shadowsocks
+        })
+        .collect();
+    let selected = modes
+        .iter()
+        .position(|(_, mode)| *mode == current)
+        .unwrap_or_default();
+    let options = modes
+        .iter()
+        .map(|(label, _)| RadioItem {
+            label: label.to_string(),
+            ..Default::default()
+        })
+        .collect();
+    let modes = RadioGroup {
+        selected,
+        options,
+        select: Box::new(move |this: &mut MulltrayApp, i| {
+            if i != selected {
+                this.set_obfuscation(modes[i].1);
+            }
+        }),
+    }
+    .into();
+    // Shadowsocks doesn't go through the udp2tcp servers
+    let uses_udp2tcp = matches!(current, ObfuscationMode::Auto | ObfuscationMode::Udp2Tcp);
+    SubMenu {
+        label: "Obfuscation".into(),
+        submenu: vec![modes, MenuItem::Separator, udp2tcp_port_menu(app, uses_udp2tcp)],
+        ..Default::default()
+    }
+    .into()
+}
+
+fn udp2tcp_port_menu(app: &MulltrayApp, enabled: bool) -> MenuItem<MulltrayApp> {
+    use ksni::menu::*;
+    // the ports the udp2tcp servers of the relays listen on
+    const PORTS: [Option<u32>; 3] = [None, Some(80), Some(5001)];
+    let current = app.settings.udp2tcp_port;
+    let mut choices = PORTS.to_vec();
+    // a port set with the Mullvad CLI
+    if !choices.contains(&current) {
+        choices.push(current);
+    }
+    let selected = choices
+        .iter()
+        .position(|port| *port == current)
+        .unwrap_or_default();
+    SubMenu {
+        label: "udp2tcp port".into(),
+        enabled,
+        submenu: vec![RadioGroup {
+            selected,
+            options: choices
+                .iter()
+                .map(|port| RadioItem {
+                    label: match port {
+                        Some(port) => port.to_string(),
+                        None => "Automatic".into(),
+                    },
+                    ..Default::default()
+                })
+                .collect(),
+            select: Box::new(move |this: &mut MulltrayApp, i| {
+                if i != selected {
+                    this.set_udp2tcp_port(choices[i]);
+                }
+            }),
+        }
+        .into()],
+        ..Default::default()
+    }
+    .into()
+}
+
+/// The country/city/relay submenus for choosing the exit or the entry location
+fn locations_menu(app: &MulltrayApp, kind: LocationKind) -> Vec<MenuItem<MulltrayApp>> {
+    use ksni::menu::*;
+    let location = app.location_constraint(kind);
+    let is_automatic = matches!(location, Some(None));
+    let is_selected = |country: &str, city: Option<&str>, hostname: Option<&str>| {
+        let Some(Some(LocationConstraint::Location(geo))) = location else {
+            return false;
+        };
+        geo.country == country
+            && geo.city.as_deref() == city
+            && geo.hostname.as_deref() == hostname
+    };
+
+    let mut locations_menu = vec![
+        CheckmarkItem {
+            label: "Automatic".into(),
+            checked: is_automatic,
+            activate: Box::new(move |this: &mut MulltrayApp| this.clear_location(kind)),
+            ..Default::default()
+        }
+        .into(),
+        custom_lists_menu(app, kind),
+        MenuItem::Separator,
+    ];
+    match app.locations_status {
+        LocationsStatus::Loading => locations_menu.push(
+            StandardItem {
+                label: "Loading locations…".into(),
+                enabled: false,
+                ..Default::default()
+            }
+            .into(),
+        ),
+        LocationsStatus::Failed => locations_menu.push(
+            StandardItem {
+                label: "Retry loading locations".into(),
+                activate: Box::new(|this: &mut MulltrayApp| this.load_locations()),
+                ..Default::default()
+            }
+            .into(),
+        ),
+        LocationsStatus::Loaded => {}
+    }
+    let countries = cached_locations(app, kind);
+    for country in countries.iter() {
+        let country_code = country.code.clone();
+        let mut cities_menu: Vec<MenuItem<MulltrayApp>> = vec![
+            CheckmarkItem {
+                label: "Any city".into(),
+                checked: is_selected(&country.code, None, None),
+                activate: Box::new(move |this: &mut MulltrayApp| {
+                    this.set_location(kind, country_code.clone(), None, None);
+                }),
+                ..Default::default()
+            }
+            .into(),
+            MenuItem::Separator,
+        ];
+        for city in &country.cities {
+            let country_code = country.code.clone();
+            let city_code = city.code.clone();
+            if city.is_item {
+                let only_relay = city.relays.first().filter(|_| app.config.show_relays);
+                cities_menu.push(
+                    CheckmarkItem {
+                        label: city.label.clone(),
+                        enabled: only_relay.is_none_or(|relay| relay.active),
+                        checked: is_selected(&country.code, Some(&city.code), None)
+                            || only_relay.is_some_and(|relay| {
+                                is_selected(&country.code, Some(&city.code), Some(&relay.hostname))
+                            }),
+                        activate: Box::new(move |this: &mut MulltrayApp| {
+                            this.set_location(
+                                kind,
+                                country_code.clone(),
+                                city_code.clone().into(),
+                                None,
+                            );
+                        }),
+                        ..Default::default()
+                    }
+                    .into(),
+                );
+                continue;
+            }
+            let mut submenu: Vec<MenuItem<MulltrayApp>> = vec![
+                CheckmarkItem {
+                    label: format!("Any server in {}", city.name),
+                    checked: is_selected(&country.code, Some(&city.code), None),
+                    activate: Box::new(move |this: &mut MulltrayApp| {
+                        this.set_location(
+                            kind,
+                            country_code.clone(),
+                            city_code.clone().into(),
+                            None,
+                        );
+                    }),
+                    ..Default::default()
+                }
+                .into(),
+                MenuItem::Separator,
+            ];
+            for relay in &city.relays {
+                let country_code = country.code.clone();
+                let city_code = city.code.clone();
+                let hostname = relay.hostname.clone();
+                submenu.push(
+                    CheckmarkItem {
+                        label: relay.label.clone(),
+                        enabled: relay.active,
+                        checked: is_selected(
+                            &country.code,
+                            Some(&city.code),
+                            Some(&relay.hostname),
+                        ),
+                        activate: Box::new(move |this: &mut MulltrayApp| {
+                            this.set_location(
+                                kind,
+                                country_code.clone(),
+                                city_code.clone().into(),
+                                hostname.clone().into(),
+                            );
+                        }),
+                        ..Default::default()
+                    }
+                    .into(),
+                )
+            }
+            cities_menu.push(
+                SubMenu {
+                    label: city.label.clone(),
+                    submenu,
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+        locations_menu.push(
+            SubMenu {
+                label: country.label.clone(),
+                submenu: cities_menu,
+                ..Default::default()
+            }
+            .into(),
+        );
+    }
+    locations_menu
+}
+
+/// The custom lists to pick from, and for the exit location ways to edit them
+fn custom_lists_menu(app: &MulltrayApp, kind: LocationKind) -> MenuItem<MulltrayApp> {
+    use ksni::menu::*;
+    let selected = match app.location_constraint(kind) {
+        Some(Some(LocationConstraint::CustomList(id))) => Some(id),
+        _ => None,
+    };
+    let lists = &app.settings.custom_lists;
+    let mut submenu: Vec<MenuItem<MulltrayApp>> = lists
+        .iter()
+        .map(|list| {
+            let id = list.id.clone();
+            CheckmarkItem {
+                label: list.name.clone(),
+                checked: selected == Some(&list.id),
+                activate: Box::new(move |this: &mut MulltrayApp| {
+                    this.set_custom_list(kind, id.clone());
+                }),
+                ..Default::default()
+            }
+            .into()
+        })
+        .collect();
+    if kind == LocationKind::Exit {
+        let can_add = app.current_location().is_some();
+        let add_menu = lists
+            .iter()
+            .map(|list| {
+                let id = list.id.clone();
+                StandardItem {
+                    label: list.name.clone(),
+                    activate: Box::new(move |this: &mut MulltrayApp| {
+                        this.add_current_location_to_custom_list(id.clone());
+                    }),
+                    ..Default::default()
+                }
+                .into()
+            })
+            .collect();
+        if !lists.is_empty() {
+            submenu.push(MenuItem::Separator);
+        }
+        submenu.extend([
+            SubMenu {
+                label: "Add current location to list".into(),
+                enabled: can_add,
+                visible: !lists.is_empty(),
+                submenu: add_menu,
+                ..Default::default()
+            }
+            .into(),
+            StandardItem {
+                label: "Create new list…".into(),
+                activate: Box::new(|this: &mut MulltrayApp| this.create_custom_list()),
+                ..Default::default()
+            }
+            .into(),
+        ]);
+    }
+    SubMenu {
+        label: "Custom lists".into(),
+        visible: app.capabilities.custom_lists && !submenu.is_empty(),
+        submenu,
+        ..Default::default()
+    }
+    .into()
+}
+
+fn favorites_menu(app: &MulltrayApp) -> Vec<MenuItem<MulltrayApp>> {
+    use ksni::menu::*;
+    let mut menu = vec![];
+    let mut remove_menu = vec![];
+    for favorite in &app.favorites.favorites {
+        let label = app.favorite_label(favorite);
+        let is_valid = label.is_some();
+        // favorites that are gone from the relay list are shown with their codes
+        let label = label.unwrap_or_else(|| {
+            [Some(&favorite.country), favorite.city.as_ref(), favorite.hostname.as_ref()]
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join("-")
+        });
+        let selected = favorite.clone();
+        menu.push(
+            StandardItem {
+                label: label.clone(),
+                enabled: is_valid,
+                activate: Box::new(move |this: &mut MulltrayApp| {
+                    let Favorite { country, city, hostname } = selected.clone();
+                    this.set_location(LocationKind::Exit, country, city, hostname);
+                }),
+                ..Default::default()
+            }
+            .into(),
+        );
+        let removed = favorite.clone();
+        remove_menu.push(
+            StandardItem {
+                label,
+                activate: Box::new(move |this: &mut MulltrayApp| this.favorites.remove(&removed)),
+                ..Default::default()
+            }
+            .into(),
+        );
+    }
+    let current_relay = app.current_relay();
+    let can_add = current_relay
+        .as_ref()
+        .is_some_and(|relay| !app.favorites.favorites.contains(relay));
+    menu.push(
+        StandardItem {
+            label: "Add current location to favorites".into(),
+            enabled: can_add,
+            activate: Box::new(move |this: &mut MulltrayApp| {
+                if let Some(relay) = current_relay.clone() {
+                    this.favorites.add(relay);
+                }
+            }),
+            ..Default::default()
+        }
+        .into(),
+    );
+    menu.push(
+        SubMenu {
+            label: "Remove".into(),
+            visible: !remove_menu.is_empty(),
+            submenu: remove_menu,
+            ..Default::default()
+        }
+        .into(),
+    );
+    menu
+}
+
+fn quantum_resistant_menu(app: &MulltrayApp) -> MenuItem<MulltrayApp> {
+    use ksni::menu::*;
+    const STATES: [(&str, QuantumResistant); 3] = [
+        ("Auto", QuantumResistant::Auto),
+        ("On", QuantumResistant::On),
+        ("Off", QuantumResistant::Off),
+    ];
+    let current = app.settings.quantum_resistant;
+    let selected = STATES
+        .iter()
+        .position(|(_, state)| *state == current)
+        .unwrap_or_default();
+    SubMenu {
+        label: "Quantum-resistant tunnel".into(),
+        submenu: vec![RadioGroup {
+            selected,
+            options: STATES
+                .iter()
+                .map(|(label, _)| RadioItem {
+                    label: label.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            select: Box::new(move |this: &mut MulltrayApp, i| {
+                if i != selected {
+                    this.set_quantum_resistant_tunnel(STATES[i].1);
+                }
+            }),
+        }
+        .into()],
+        ..Default::default()
+    }
+    .into()
+}
+
+fn filters_menu(app: &MulltrayApp) -> Vec<MenuItem<MulltrayApp>> {
+    use ksni::menu::*;
+    let norm = app.relay_settings().cloned().unwrap_or_default();
+    let mullvad_owned_only = norm.ownership == Ownership::MullvadOwned;
+    let all_providers = app.all_providers();
+    let providers_menu = all_providers
+        .iter()
+        .cloned()
+        .map(|provider| {
+            let enabled = norm.providers.is_empty() || norm.providers.contains(&provider);
+            // unchecking the last one would mean any provider again
+            let only_one = match norm.providers.as_slice() {
+                [] => all_providers.len() == 1,
+                providers => providers.len() == 1 && enabled,
+            };
+            CheckmarkItem {
+                label: provider.clone(),
+                checked: enabled,
+                enabled: !only_one,
+                activate: Box::new(move |this: &mut MulltrayApp| {
+                    this.set_provider_enabled(provider.clone(), !enabled)
+                }),
+                ..Default::default()
+            }
+            .into()
+        })
+        .collect();
+    vec![
+        CheckmarkItem {
+            label: "Mullvad-owned only".into(),
+            checked: mullvad_owned_only,
+            activate: Box::new(move |this: &mut MulltrayApp| {
+                this.set_mullvad_owned_only(!mullvad_owned_only)
+            }),
+            ..Default::default()
+        }
+        .into(),
+        SubMenu {
+            label: "Providers".into(),
+            submenu: providers_menu,
+            ..Default::default()
+        }
+        .into(),
+        CheckmarkItem {
+            label: "Show inactive relays".into(),
+            checked: app.show_inactive_relays,
+            activate: Box::new(|this: &mut MulltrayApp| {
+                this.show_inactive_relays = !this.show_inactive_relays
+            }),
+            ..Default::default()
+        }
+        .into(),
+        sort_locations_menu(app),
+    ]
+}
+
+fn sort_locations_menu(app: &MulltrayApp) -> MenuItem<MulltrayApp> {
+    use ksni::menu::*;
+    const SORTS: [(&str, SortLocations); 3] = [
+        ("Alphabetical", SortLocations::Alphabetical),
+        ("Latency", SortLocations::Latency),
+        ("Recently used", SortLocations::Recent),
+    ];
+    let selected = SORTS
+        .iter()
+        .position(|(_, sort)| *sort == app.config.sort_locations)
+        .unwrap_or_default();
+    SubMenu {
+        label: "Sort locations by".into(),
+        submenu: vec![RadioGroup {
+            selected,
+            options: SORTS
+                .iter()
+                .map(|(label, _)| RadioItem {
+                    label: label.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            select: Box::new(move |this: &mut MulltrayApp, i| {
+                if i != selected {
+                    this.set_sort_locations(SORTS[i].1);
+                }
+            }),
+        }
+        .into()],
+        ..Default::default()
+    }
+    .into()
+}
+
+fn wireguard_port_menu(app: &MulltrayApp) -> MenuItem<MulltrayApp> {
+    use ksni::menu::*;
+    let current = app.relay_settings().and_then(|relay| relay.wireguard_port);
+    let mut ports = wireguard_port_choices(&app.locations.wireguard_port_ranges);
+    if let Some(port) = current {
+        if !ports.contains(&port) {
+            ports.push(port);
+            ports.sort_unstable();
+        }
+    }
+    let mut choices = vec![None];
+    choices.extend(ports.into_iter().map(Some));
+    let selected = choices
+        .iter()
+        .position(|port| *port == current)
+        .unwrap_or_default();
+    SubMenu {
+        label: "WireGuard port".into(),
+        submenu: vec![RadioGroup {
+            selected,
+            options: choices
+                .iter()
+                .map(|port| RadioItem {
+                    label: match port {
+                        Some(port) => port.to_string(),
+                        None => "Automatic".into(),
+                    },
+                    ..Default::default()
+                })
+                .collect(),
+            select: Box::new(move |this: &mut MulltrayApp, i| {
+                if i != selected {
+                    this.set_wireguard_port(choices[i]);
+                }
+            }),
+        }
+        .into()],
+        ..Default::default()
+    }
+    .into()
+}
+
+fn split_tunnel_menu(app: &MulltrayApp) -> MenuItem<MulltrayApp> {
+    use ksni::menu::*;
+    let split_tunnel = app.settings.split_tunnel.clone();
+    let enabled = split_tunnel.enabled;
+    let mut submenu = vec![
+        CheckmarkItem {
+            label: "Enabled".into(),
+            checked: enabled,
+            activate: Box::new(move |this: &mut MulltrayApp| {
+                this.set_split_tunnel_enabled(!enabled)
+            }),
+            ..Default::default()
+        }
+        .into(),
+        MenuItem::Separator,
+    ];
+    for path in split_tunnel.apps {
+        let label = Path::new(&path)
+            .file_name()
+            .map_or(path.clone(), |name| name.to_string_lossy().into_owned());
+        submenu.push(
+            SubMenu {
+                label,
+                submenu: vec![
+                    StandardItem {
+                        label: path.clone(),
+                        enabled: false,
+                        ..Default::default()
+                    }
+                    .into(),
+                    StandardItem {
+                        label: "Remove".into(),
+                        activate: Box::new(move |this: &mut MulltrayApp| {
+                            this.remove_split_tunnel_app(path.clone())
+                        }),
+                        ..Default::default()
+                    }
+                    .into(),
+                ],
+                ..Default::default()
+            }
+            .into(),
+        );
+    }
+    submenu.push(
+        StandardItem {
+            label: "Add application...".into(),
+            activate: Box::new(|this: &mut MulltrayApp| this.add_split_tunnel_app()),
+            ..Default::default()
+        }
+        .into(),
+    );
+    SubMenu {
+        label: "Split tunneling".into(),
+        visible: app.split_tunnel_supported,
+        submenu,
+        ..Default::default()
+    }
+    .into()
+}
+
+fn wireguard_mtu_menu(app: &MulltrayApp) -> MenuItem<MulltrayApp> {
+    use ksni::menu::*;
+    const PRESETS: [u32; 3] = [1280, 1380, 1420];
+    let current = app.settings.wireguard_mtu;
+    let mut choices = vec![None];
+    choices.extend(PRESETS.into_iter().map(Some));
+    // an MTU set with the CLI is shown too so that it doesn't look like Automatic
+    if current.is_some() && !choices.contains(&current) {
+        choices.push(current);
+    }
+    let selected = choices
+        .iter()
+        .position(|mtu| *mtu == current)
+        .unwrap_or_default();
+    SubMenu {
+        label: "MTU".into(),
+        submenu: vec![RadioGroup {
+            selected,
+            options: choices
+                .iter()
+                .map(|mtu| RadioItem {
+                    label: match mtu {
+                        Some(mtu) => mtu.to_string(),
+                        None => "Automatic".into(),
+                    },
+                    ..Default::default()
+                })
+                .collect(),
+            select: Box::new(move |this: &mut MulltrayApp, i| {
+                if i != selected {
+                    this.set_wireguard_mtu(choices[i]);
+                }
+            }),
+        }
+        .into()],
+        ..Default::default()
+    }
+    .into()
+}
+
+/// Bridges only work with OpenVPN so the menu is hidden unless OpenVPN is chosen
+fn bridge_menu(app: &MulltrayApp) -> MenuItem<MulltrayApp> {
+    use ksni::menu::*;
+    const STATES: [(&str, BridgeState); 3] =
+        [("Auto", BridgeState::Auto), ("On", BridgeState::On), ("Off", BridgeState::Off)];
+    let is_openvpn = app
+        .relay_settings()
+        .is_some_and(|relay| relay.tunnel_type == Some(TunnelType::OpenVpn));
+    let current = app.settings.bridge_state;
+    let selected = STATES
+        .iter()
+        .position(|(_, state)| *state == current)
+        .unwrap_or_default();
+    SubMenu {
+        label: "Bridge mode".into(),
+        visible: is_openvpn,
+        submenu: vec![
+            RadioGroup {
+                selected,
+                options: STATES
+                    .iter()
+                    .map(|(label, _)| RadioItem {
+                        label: label.to_string(),
+                        ..Default::default()
+                    })
+                    .collect(),
+                select: Box::new(move |this: &mut MulltrayApp, i| {
+                    if i != selected {
+                        this.set_bridge_state(STATES[i].1);
+                    }
+                }),
+            }
+            .into(),
+            MenuItem::Separator,
+            SubMenu {
+                label: "Bridge location".into(),
+                enabled: current != BridgeState::Off,
+                submenu: locations_menu(app, LocationKind::Bridge),
+                ..Default::default()
+            }
+            .into(),
+        ],
+        ..Default::default()
+    }
+    .into()
+}
+
+fn tunnel_protocol_menu(app: &MulltrayApp) -> MenuItem<MulltrayApp> {
+    use ksni::menu::*;
+    const PROTOCOLS: [(&str, Option<TunnelType>); 3] = [
+        ("Automatic", None),
+        ("WireGuard", Some(TunnelType::WireGuard)),
+        ("OpenVPN", Some(TunnelType::OpenVpn)),
+    ];
+    let current = app.relay_settings().and_then(|relay| relay.tunnel_type);
+    let selected = PROTOCOLS
+        .iter()
+        .position(|(_, protocol)| *protocol == current)
+        .unwrap_or_default();
+    SubMenu {
+        label: "Tunnel protocol".into(),
+        submenu: vec![RadioGroup {
+            selected,
+            options: PROTOCOLS
+                .iter()
+                .map(|(label, _)| RadioItem {
+                    label: label.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            select: Box::new(move |this: &mut MulltrayApp, i| {
+                if i != selected {
+                    this.set_tunnel_protocol(PROTOCOLS[i].1);
+                }
+            }),
+        }
+        .into()],
+        ..Default::default()
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capabilities::DaemonCapabilities;
+    use crate::config::Config;
+    use crate::model;
+    use crate::testing::{
+        connected, find_item, is_visible, label, relay_list, submenu_labels, tunnel_state,
+        TestTray,
+    };
+
+    /// se-got-wg-001 is the only active relay
+    fn partly_inactive_relay_list() -> RelayList {
+        let mut relay_list = relay_list(&["se-got-wg-001", "se-got-wg-002", "de-ber-wg-001"]);
+        for country in &mut relay_list.countries {
+            for relay in country.cities.iter_mut().flat_map(|city| &mut city.relays) {
+                relay.active = relay.hostname == "se-got-wg-001";
+            }
+        }
+        relay_list.into()
+    }
+
+    fn hostnames(countries: &[Country]) -> Vec<&str> {
+        countries
+            .iter()
+            .flat_map(|country| &country.cities)
+            .flat_map(|city| &city.relays)
+            .map(|relay| relay.hostname.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn empty_cities_and_countries_are_left_out() {
+        let relay_list = partly_inactive_relay_list();
+        let countries = filter_relay_list(&relay_list, |relay| relay.active);
+        assert_eq!(hostnames(&countries), ["se-got-wg-001"]);
+        assert_eq!(countries.len(), 1);
+        assert_eq!(countries[0].cities.len(), 1);
+
+        let countries = filter_relay_list(&relay_list, |_| true);
+        assert_eq!(hostnames(&countries), hostnames(&relay_list.countries));
+        assert!(filter_relay_list(&relay_list, |_| false).is_empty());
+    }
+
+    /// Countries with more than one city or relay to order, listed alphabetically
+    fn sortable_countries() -> Vec<Country> {
+        let relay_list: RelayList = relay_list(&[
+            "at-vie-wg-001",
+            "ch-zrh-wg-001",
+            "de-ber-wg-001",
+            "de-ber-wg-002",
+            "se-got-wg-001",
+            "se-got-wg-002",
+            "se-got-wg-003",
+            "se-got-wg-004",
+            "se-sto-wg-001",
+        ])
+        .into();
+        filter_relay_list(&relay_list, |_| true)
+    }
+
+    fn codes(countries: &[Country]) -> Vec<String> {
+        let cities = countries.iter().flat_map(|country| {
+            let cities = country.cities.iter();
+            cities.map(move |city| format!("{}-{}", country.code, city.code))
+        });
+        cities.collect()
+    }
+
+    #[test]
+    fn locations_are_sorted_by_their_fastest_relay() {
+        let mut latencies = Latencies::default();
+        for (hostname, millis) in [
+            ("se-got-wg-001", 30),
+            ("se-got-wg-003", 5),
+            ("se-sto-wg-001", 40),
+            ("de-ber-wg-002", 10),
+        ] {
+            latencies.record(hostname.into(), Some(Duration::from_millis(millis)));
+        }
+        let mut countries = sortable_countries();
+        sort_relay_list(&mut countries, SortLocations::Latency, &latencies, &HashMap::new());
+        // the ones that weren't measured come last, still in alphabetical order
+        assert_eq!(
+            hostnames(&countries),
+            [
+                "se-got-wg-003",
+                "se-got-wg-001",
+                "se-got-wg-002",
+                "se-got-wg-004",
+                "se-sto-wg-001",
+                "de-ber-wg-002",
+                "de-ber-wg-001",
+                "at-vie-wg-001",
+                "ch-zrh-wg-001",
+            ]
+        );
+        assert_eq!(codes(&countries), ["se-got", "se-sto", "de-ber", "at-vie", "ch-zrh"]);
+    }
+
+    #[test]
+    fn locations_are_sorted_by_when_they_were_last_used() {
+        let ago = |secs| Instant::now().checked_sub(Duration::from_secs(secs)).unwrap();
+        let last_used = HashMap::from([
+            ("de-ber-wg-002".to_string(), ago(60)),
+            ("se-sto-wg-001".to_string(), ago(10)),
+            ("se-got-wg-002".to_string(), ago(3600)),
+        ]);
+        let mut countries = sortable_countries();
+        let latencies = Latencies::default();
+        sort_relay_list(&mut countries, SortLocations::Recent, &latencies, &last_used);
+        assert_eq!(
+            hostnames(&countries),
+            [
+                "se-sto-wg-001",
+                "se-got-wg-002",
+                "se-got-wg-001",
+                "se-got-wg-003",
+                "se-got-wg-004",
+                "de-ber-wg-002",
+                "de-ber-wg-001",
+                "at-vie-wg-001",
+                "ch-zrh-wg-001",
+            ]
+        );
+        assert_eq!(codes(&countries), ["se-sto", "se-got", "de-ber", "at-vie", "ch-zrh"]);
+    }
+
+    #[test]
+    fn locations_without_data_stay_alphabetical() {
+        let expected = hostnames(&sortable_countries()).join(" ");
+        let mut latencies = Latencies::default();
+        latencies.record("se-got-wg-001".into(), Some(Duration::from_millis(30)));
+        let last_used = HashMap::from([("se-got-wg-001".to_string(), Instant::now())]);
+        for sort in [SortLocations::Latency, SortLocations::Recent] {
+            let mut countries = sortable_countries();
+            sort_relay_list(&mut countries, sort, &Latencies::default(), &HashMap::new());
+            assert_eq!(hostnames(&countries).join(" "), expected, "{sort:?}");
+        }
+        // whatever there is to sort by
+        let mut countries = sortable_countries();
+        sort_relay_list(&mut countries, SortLocations::Alphabetical, &latencies, &last_used);
+        assert_eq!(hostnames(&countries).join(" "), expected);
+    }
+
+    fn is_enabled<T>(item: &MenuItem<T>) -> bool {
+        match item {
+            MenuItem::Standard(item) => item.enabled,
+            MenuItem::Checkmark(item) => item.enabled,
+            MenuItem::SubMenu(item) => item.enabled,
+            MenuItem::Separator | MenuItem::RadioGroup(_) => true,
+        }
+    }
+
+    #[tokio::test]
+    async fn inactive_relays_are_hidden_unless_asked_for() {
+        let tray = TestTray::new(Config::default());
+        let menu = tray.handle.update(|tray: &mut MulltrayApp| {
+            tray.set_locations(partly_inactive_relay_list());
+            ksni::Tray::menu(tray)
+        });
+        let countries = submenu_labels(&menu, &["Choose location"]);
+        assert!(countries.contains(&"SE (1)".to_string()), "{countries:?}");
+        assert!(!countries.iter().any(|label| label.starts_with("DE")), "{countries:?}");
+        let cities = submenu_labels(&menu, &["Choose location", "SE (1)"]);
+        assert_eq!(cities, ["Any city", "", "GOT — se-got-wg-001"]);
+
+        let menu = tray.handle.update(|tray: &mut MulltrayApp| {
+            tray.show_inactive_relays = true;
+            ksni::Tray::menu(tray)
+        });
+        let countries = submenu_labels(&menu, &["Choose location"]);
+        assert!(countries.contains(&"DE (1)".to_string()), "{countries:?}");
+        // shown greyed out
+        let path = ["Choose location", "SE (2)", "GOT (2)", "se-got-wg-002"];
+        assert!(find_item(&menu, &path).is_some_and(|item| !is_enabled(item)));
+        let path = ["Choose location", "SE (2)", "GOT (2)", "se-got-wg-001"];
+        assert!(find_item(&menu, &path).is_some_and(is_enabled));
+    }
+
+    #[tokio::test]
+    async fn the_locations_are_only_listed_again_when_they_change() {
+        let tray = TestTray::new(Config::default());
+        tray.handle.update(|tray: &mut MulltrayApp| {
+            tray.set_locations(relay_list(&["se-got-wg-001", "se-got-wg-002"]).into());
+            let listed = |tray: &MulltrayApp| {
+                let exit = cached_locations(tray, LocationKind::Exit);
+                (exit, cached_locations(tray, LocationKind::Entry))
+            };
+            let same = |a: &(Arc<_>, Arc<_>), b: &(Arc<_>, Arc<_>)| {
+                Arc::ptr_eq(&a.0, &b.0) && Arc::ptr_eq(&a.1, &b.1)
+            };
+            let before = listed(tray);
+            ksni::Tray::menu(tray);
+            tray.settings.allow_lan = true;
+            ksni::Tray::menu(tray);
+            assert!(same(&before, &listed(tray)));
+
+            tray.locations_version += 1;
+            let after = listed(tray);
+            assert!(!Arc::ptr_eq(&before.0, &after.0) && !Arc::ptr_eq(&before.1, &after.1));
+
+            let before = after;
+            tray.latencies.record("se-got-wg-001".into(), Some(Duration::from_millis(42)));
+            tray.metrics_version += 1;
+            let after = listed(tray);
+            assert!(!Arc::ptr_eq(&before.0, &after.0) && !Arc::ptr_eq(&before.1, &after.1));
+            let relays = &after.0[0].cities[0].relays;
+            assert_eq!(relays[0].label, "se-got-wg-001 (42 ms)");
+            assert!(same(&after, &listed(tray)));
+        });
+    }
+
+    fn is_submenu<T>(item: Option<&MenuItem<T>>) -> bool {
+        matches!(item, Some(MenuItem::SubMenu(_)))
+    }
+
+    #[tokio::test]
+    async fn cities_with_a_single_relay_are_picked_directly() {
+        let tray = TestTray::new(Config::default());
+        let relay_list = relay_list(&["se-got-wg-001", "se-got-wg-002", "se-sto-wg-001"]);
+        let menu = tray.handle.update(|tray: &mut MulltrayApp| {
+            tray.set_locations(relay_list.into());
+            ksni::Tray::menu(tray)
+        });
+        let sweden = ["Choose location", "SE (3)"];
+        assert_eq!(
+            submenu_labels(&menu, &sweden),
+            ["Any city", "", "GOT (2)", "STO — se-sto-wg-001"]
+        );
+        let city = |menu, city| find_item(menu, &["Choose location", "SE (3)", city]);
+        assert!(is_submenu(city(&menu, "GOT (2)")));
+        assert!(!is_submenu(city(&menu, "STO — se-sto-wg-001")));
+        assert_eq!(
+            submenu_labels(&menu, &["Choose location", "SE (3)", "GOT (2)"]),
+            ["Any server in GOT", "", "se-got-wg-001", "se-got-wg-002"]
+        );
+
+        // without the relays there is nothing to collapse
+        let menu = tray.handle.update(|tray: &mut MulltrayApp| {
+            tray.config.show_relays = false;
+            ksni::Tray::menu(tray)
+        });
+        assert_eq!(submenu_labels(&menu, &sweden), ["Any city", "", "GOT (2)", "STO (1)"]);
+        assert!(!is_submenu(city(&menu, "GOT (2)")));
+    }
+
+    /// `hostnames` with the ones containing "ovpn" being OpenVPN relays and the ones containing
+    /// "br" bridges
+    fn mixed_relay_list(hostnames: &[&str]) -> RelayList {
+        use crate::proto::relay::RelayType;
+        let mut relay_list = relay_list(hostnames);
+        for country in &mut relay_list.countries {
+            for relay in country.cities.iter_mut().flat_map(|city| &mut city.relays) {
+                if relay.hostname.contains("ovpn") {
+                    relay.set_endpoint_type(RelayType::Openvpn);
+                } else if relay.hostname.contains("br") {
+                    relay.set_endpoint_type(RelayType::Bridge);
+                }
+            }
+        }
+        relay_list.into()
+    }
+
+    fn menu_with_tunnel_type(
+        tray: &TestTray,
+        relay_list: RelayList,
+        tunnel_type: Option<TunnelType>,
+    ) -> Vec<MenuItem<MulltrayApp>> {
+        tray.handle.update(|tray: &mut MulltrayApp| {
+            tray.set_locations(relay_list);
+            tray.settings.relay_settings =
+                Some(crate::model::RelaySettings { tunnel_type, ..Default::default() });
+            ksni::Tray::menu(tray)
+        })
+    }
+
+    #[tokio::test]
+    async fn countries_without_relays_of_the_protocol_are_left_out() {
+        let tray = TestTray::new(Config::default());
+        let relay_list = || mixed_relay_list(&["se-got-wg-001", "us-nyc-ovpn-001"]);
+        // the countries come after the last separator
+        let countries = |menu: &[MenuItem<MulltrayApp>], path: &[&str]| {
+            let mut labels = submenu_labels(menu, path);
+            let separator = labels.iter().rposition(String::is_empty).unwrap();
+            labels.split_off(separator + 1)
+        };
+
+        let menu = menu_with_tunnel_type(&tray, relay_list(), Some(TunnelType::WireGuard));
+        assert_eq!(countries(&menu, &["Choose location"]), ["SE (1)"]);
+        let menu = menu_with_tunnel_type(&tray, relay_list(), Some(TunnelType::OpenVpn));
+        assert_eq!(countries(&menu, &["Choose location"]), ["US (1)"]);
+        // the entry relay of multihop is always a WireGuard one
+        assert_eq!(countries(&menu, &["Entry location (multihop)"]), ["SE (1)"]);
+        let menu = menu_with_tunnel_type(&tray, relay_list(), None);
+        assert_eq!(countries(&menu, &["Choose location"]), ["SE (1)", "US (1)"]);
+    }
+
+    #[tokio::test]
+    async fn udp2tcp_ports_are_offered_while_obfuscating() {
+        let tray = TestTray::new(Config::default());
+        let port_menu = |obfuscation, udp2tcp_port| {
+            let menu = tray.handle.update(|tray: &mut MulltrayApp| {
+                tray.settings.obfuscation = obfuscation;
+                tray.settings.udp2tcp_port = udp2tcp_port;
+                vec![obfuscation_menu(tray)]
+            });
+            let Some(MenuItem::SubMenu(menu)) = find_item(&menu, &["Obfuscation", "udp2tcp port"])
+            else {
+                panic!("the udp2tcp port menu is not a submenu");
+            };
+            let Some(MenuItem::RadioGroup(ports)) = menu.submenu.first() else {
+                panic!("the udp2tcp port menu has no ports");
+            };
+            let labels: Vec<String> = ports.options.iter().map(|port| port.label.clone()).collect();
+            (menu.enabled, labels, ports.selected)
+        };
+        let (enabled, ports, selected) = port_menu(ObfuscationMode::Udp2Tcp, Some(80));
+        assert!(enabled);
+        assert_eq!(ports, ["Automatic", "80", "5001"]);
+        assert_eq!(selected, 1);
+        // one set with the Mullvad CLI is kept
+        let (_, ports, selected) = port_menu(ObfuscationMode::Auto, Some(443));
+        assert_eq!(ports, ["Automatic", "80", "5001", "443"]);
+        assert_eq!(selected, 3);
+        let (enabled, _, selected) = port_menu(ObfuscationMode::Off, None);
+        assert!(!enabled);
+        assert_eq!(selected, 0);
+        let (enabled, _, _) = port_menu(ObfuscationMode::Shadowsocks, None);
+        assert!(!enabled);
+    }
+
+    #[tokio::test]
+    async fn shadowsocks_is_offered_by_daemons_that_have_it() {
+        let tray = TestTray::new(Config::default());
+        let modes = |version: &str, obfuscation| {
+            let menu = tray.handle.update(|tray: &mut MulltrayApp| {
+                tray.capabilities = DaemonCapabilities::for_version(version);
+                tray.settings.obfuscation = obfuscation;
+                vec![obfuscation_menu(tray)]
+            });
+            let Some(MenuItem::RadioGroup(modes)) = find_item(&menu, &["Obfuscation", ""]) else {
+                panic!("the obfuscation menu has no modes");
+            };
+            let labels: Vec<String> = modes.options.iter().map(|mode| mode.label.clone()).collect();
+            (labels, modes.selected)
+        };
+        let (labels, selected) = modes("2024.8", ObfuscationMode::Shadowsocks);
+        assert_eq!(labels, ["Auto", "Off", "udp2tcp", "Shadowsocks"]);
+        assert_eq!(selected, 3);
+        let (labels, selected) = modes("2024.1", ObfuscationMode::Udp2Tcp);
+        assert_eq!(labels, ["Auto", "Off", "udp2tcp"]);
+        assert_eq!(selected, 2);
+    }
+
+    #[tokio::test]
+    async fn relay_counts_only_count_what_is_listed() {
+        let tray = TestTray::new(Config::default());
+        let relay_list = || {
+            mixed_relay_list(&[
+                "se-got-wg-001",
+                "se-got-wg-002",
+                "se-got-ovpn-001",
+                "se-got-br-001",
+                "se-sto-ovpn-001",
+            ])
+        };
+        let sweden = |menu: &[MenuItem<MulltrayApp>], country: &str| {
+            let cities = submenu_labels(menu, &["Choose location", country]);
+            cities[2..].to_vec()
+        };
+
+        let menu = menu_with_tunnel_type(&tray, relay_list(), Some(TunnelType::WireGuard));
+        assert_eq!(sweden(&menu, "SE (2)"), ["GOT (2)"]);
+        let menu = menu_with_tunnel_type(&tray, relay_list(), Some(TunnelType::OpenVpn));
+        assert_eq!(sweden(&menu, "SE (2)"), ["GOT — se-got-ovpn-001", "STO — se-sto-ovpn-001"]);
+        // bridges aren't exit relays
+        let menu = menu_with_tunnel_type(&tray, relay_list(), None);
+        assert_eq!(sweden(&menu, "SE (4)"), ["GOT (3)", "STO — se-sto-ovpn-001"]);
+
+        let menu = tray.handle.update(|tray: &mut MulltrayApp| {
+            tray.config.show_relay_counts = false;
+            ksni::Tray::menu(tray)
+        });
+        assert_eq!(sweden(&menu, "SE"), ["GOT", "STO — se-sto-ovpn-001"]);
+    }
+
+    /// The labels of the items at the top of the menu, with the separators as ""
+    fn top_level(menu: &[MenuItem<MulltrayApp>]) -> Vec<&str> {
+        menu.iter().filter(|item| is_visible(item)).map(label).collect()
+    }
+
+    fn connected_menu(tray: &TestTray, hidden: Vec<MenuSection>) -> Vec<MenuItem<MulltrayApp>> {
+        tray.handle.update(|tray: &mut MulltrayApp| {
+            let state = model::TunnelState::try_from(tunnel_state(connected("se-got-wg-001")));
+            tray.set_app_state(state.unwrap().into());
+            tray.device_state = Some(DeviceState::LoggedOut);
+            tray.config.hide_menu_sections = hidden;
+            ksni::Tray::menu(tray)
+        })
+    }
+
+    #[tokio::test]
+    async fn sections_can_be_hidden_apart_from_the_last_one() {
+        let tray = TestTray::new(Config::default());
+        let sections = [
+            (MenuSection::Account, "Log in..."),
+            (MenuSection::Status, "Status"),
+            (MenuSection::Actions, "Disconnect"),
+            (MenuSection::Location, "Choose location"),
+            (MenuSection::Settings, "Settings"),
+        ];
+        let menu = connected_menu(&tray, vec![]);
+        for (_, label) in sections {
+            assert!(top_level(&menu).contains(&label), "{label}");
+        }
+        for (section, label) in sections {
+            let menu = connected_menu(&tray, vec![section]);
+            let labels = top_level(&menu);
+            assert!(!labels.contains(&label), "{section:?}: {labels:?}");
+            for (_, other) in sections.iter().filter(|(other, _)| *other != section) {
+                assert!(labels.contains(other), "{section:?}: {labels:?}");
+            }
+        }
+
+        let menu = connected_menu(&tray, sections.iter().map(|(section, _)| *section).collect());
+        assert_eq!(top_level(&menu), ["About", "Reload configuration", "Quit"]);
+    }
+
+    #[tokio::test]
+    async fn sections_with_nothing_to_show_leave_no_separators_behind() {
+        let tray = TestTray::new(Config::default());
+        // the daemon is unavailable, so there is no status and nothing to connect or disconnect
+        let menu = tray.handle.update(|tray: &mut MulltrayApp| ksni::Tray::menu(tray));
+        let labels = top_level(&menu);
+        assert!(!labels.contains(&"Status") && !labels.contains(&"Connect"), "{labels:?}");
+        assert_ne!(labels.first(), Some(&""), "{labels:?}");
+        assert_ne!(labels.last(), Some(&""), "{labels:?}");
+        assert!(!labels.windows(2).any(|pair| pair == ["", ""]), "{labels:?}");
+
+        let menu = connected_menu(&tray, vec![MenuSection::Location, MenuSection::Settings]);
+        let labels = top_level(&menu);
+        assert!(!labels.windows(2).any(|pair| pair == ["", ""]), "{labels:?}");
+        let disconnect = labels.iter().position(|label| *label == "Disconnect").unwrap();
+        // straight from the actions to the app section
+        assert_eq!(labels[disconnect + 1..disconnect + 3], ["", "About"]);
+    }
+}
@@ -0,0 +1,1217 @@
+//! Our own types for what the daemon tells about the tunnel, its relays and its settings.
+//! The nested options, oneofs and enum numbers of the generated proto types are dealt with
+//! here, so that a new version of the proto file only needs changes in this module.
+
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::ops::RangeInclusive;
+
+use tracing::{debug, warn};
+
+use crate::proto;
+
+#[derive(Debug)]
+pub enum Error {
+    /// An enum value that this version of the proto doesn't know, from a newer daemon
+    UnknownValue(&'static str, i32),
+    /// A message field that the daemon always sends was left out
+    MissingField(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownValue(field, value) => write!(f, "Unknown {}: {}", field, value),
+            Error::MissingField(field) => write!(f, "The daemon left out the {}", field),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The relay the tunnel goes through and how it gets there
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelayInfo {
+    pub location: Option<Location>,
+    /// `None` if the daemon didn't say or we can't make sense of it
+    pub endpoint: Option<Endpoint>,
+}
+
+impl From<proto::TunnelStateRelayInfo> for RelayInfo {
+    fn from(relay_info: proto::TunnelStateRelayInfo) -> Self {
+        // the location is still worth showing if the endpoint is from a newer daemon
+        let endpoint = relay_info.tunnel_endpoint.and_then(|endpoint| {
+            Endpoint::try_from(endpoint)
+                .inspect_err(|e| warn!("Leaving out the tunnel endpoint: {}", e))
+                .ok()
+        });
+        Self {
+            location: relay_info.location.map(Location::from),
+            endpoint,
+        }
+    }
+}
+
+impl RelayInfo {
+    pub fn hostname(&self) -> Option<&String> {
+        self.location.as_ref()?.hostname.as_ref()
+    }
+
+    /// The hostname of the entry relay of a multihop connection, or its IP address if the
+    /// daemon didn't say which relay it is. `None` without multihop.
+    pub fn entry_relay(&self) -> Option<String> {
+        let location = self.location.as_ref();
+        if let Some(hostname) = location.and_then(|location| location.entry_hostname.as_ref()) {
+            return Some(hostname.clone());
+        }
+        let entry_address = self.endpoint.as_ref()?.entry_address.as_ref()?;
+        // the address comes with the port
+        match entry_address.parse::<SocketAddr>() {
+            Ok(addr) => Some(addr.ip().to_string()),
+            Err(_) => Some(entry_address.clone()),
+        }
+    }
+}
+
+/// Where the traffic seems to come from, as looked up by the daemon
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub hostname: Option<String>,
+    pub entry_hostname: Option<String>,
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub ipv4: Option<String>,
+    pub ipv6: Option<String>,
+}
+
+/// Empty strings are what proto3 sends for a missing string
+fn non_empty(s: String) -> Option<String> {
+    Some(s).filter(|s| !s.is_empty())
+}
+
+impl From<proto::GeoIpLocation> for Location {
+    fn from(location: proto::GeoIpLocation) -> Self {
+        Self {
+            hostname: location.hostname.and_then(non_empty),
+            entry_hostname: location.entry_hostname.and_then(non_empty),
+            country: non_empty(location.country),
+            city: location.city.and_then(non_empty),
+            ipv4: location.ipv4.and_then(non_empty),
+            ipv6: location.ipv6.and_then(non_empty),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunnelType {
+    WireGuard,
+    OpenVpn,
+}
+
+impl TryFrom<i32> for TunnelType {
+    type Error = Error;
+
+    fn try_from(value: i32) -> Result<Self, Error> {
+        match proto::TunnelType::try_from(value) {
+            Ok(proto::TunnelType::Wireguard) => Ok(TunnelType::WireGuard),
+            Ok(proto::TunnelType::Openvpn) => Ok(TunnelType::OpenVpn),
+            Err(_) => Err(Error::UnknownValue("tunnel type", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportProtocol {
+    Udp,
+    Tcp,
+}
+
+impl TryFrom<i32> for TransportProtocol {
+    type Error = Error;
+
+    fn try_from(value: i32) -> Result<Self, Error> {
+        match proto::TransportProtocol::try_from(value) {
+            Ok(proto::TransportProtocol::Udp) => Ok(TransportProtocol::Udp),
+            Ok(proto::TransportProtocol::Tcp) => Ok(TransportProtocol::Tcp),
+            Err(_) => Err(Error::UnknownValue("transport protocol", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoint {
+    pub address: String,
+    pub tunnel_type: TunnelType,
+    pub quantum_resistant: bool,
+    pub obfuscation: Option<Obfuscation>,
+    /// The proxy an OpenVPN tunnel goes through
+    pub proxy: Option<Proxy>,
+    /// The address of the entry relay with multihop, with the port
+    pub entry_address: Option<String>,
+    /// The name of the network interface of the tunnel
+    pub tunnel_interface: Option<String>,
+}
+
+impl TryFrom<proto::TunnelEndpoint> for Endpoint {
+    type Error = Error;
+
+    fn try_from(endpoint: proto::TunnelEndpoint) -> Result<Self, Error> {
+        Ok(Self {
+            address: endpoint.address,
+            tunnel_type: endpoint.tunnel_type.try_into()?,
+            quantum_resistant: endpoint.quantum_resistant,
+            obfuscation: endpoint.obfuscation.map(Obfuscation::try_from).transpose()?,
+            proxy: endpoint.proxy.map(Proxy::try_from).transpose()?,
+            entry_address: endpoint.entry_endpoint.and_then(|entry| non_empty(entry.address)),
+            tunnel_interface: endpoint.tunnel_metadata
+                .and_then(|metadata| non_empty(metadata.tunnel_interface)),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObfuscationType {
+    Udp2Tcp,
+    Shadowsocks,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Obfuscation {
+    pub obfuscation_type: ObfuscationType,
+    pub address: String,
+    pub port: u32,
+}
+
+impl TryFrom<proto::ObfuscationEndpoint> for Obfuscation {
+    type Error = Error;
+
+    fn try_from(obfuscation: proto::ObfuscationEndpoint) -> Result<Self, Error> {
+        let value = obfuscation.obfuscation_type;
+        use proto::obfuscation_endpoint::ObfuscationType as ProtoObfuscationType;
+        let obfuscation_type = match ProtoObfuscationType::try_from(value) {
+            Ok(ProtoObfuscationType::Udp2tcp) => ObfuscationType::Udp2Tcp,
+            Ok(ProtoObfuscationType::Shadowsocks) => ObfuscationType::Shadowsocks,
+            Err(_) => return Err(Error::UnknownValue("obfuscation type", value)),
+        };
+        Ok(Self {
+            obfuscation_type,
+            address: obfuscation.address,
+            port: obfuscation.port,
+        })
+    }
+}
+
+impl Obfuscation {
+    /// `address:port`, with the brackets an IPv6 address needs
+    pub fn socket_address(&self) -> String {
+        if self.address.contains(':') {
+            format!("[{}]:{}", self.address, self.port)
+        } else {
+            format!("{}:{}", self.address, self.port)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyType {
+    Shadowsocks,
+    Custom,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Proxy {
+    pub proxy_type: ProxyType,
+    /// With the port
+    pub address: String,
+    pub protocol: TransportProtocol,
+}
+
+impl TryFrom<proto::ProxyEndpoint> for Proxy {
+    type Error = Error;
+
+    fn try_from(proxy: proto::ProxyEndpoint) -> Result<Self, Error> {
+        let proxy_type = match proto::ProxyType::try_from(proxy.proxy_type) {
+            Ok(proto::ProxyType::Shadowsocks) => ProxyType::Shadowsocks,
+            Ok(proto::ProxyType::Custom) => ProxyType::Custom,
+            Err(_) => return Err(Error::UnknownValue("proxy type", proxy.proxy_type)),
+        };
+        Ok(Self {
+            proxy_type,
+            address: proxy.address,
+            protocol: proxy.protocol.try_into()?,
+        })
+    }
+}
+
+impl From<TunnelType> for proto::TunnelType {
+    fn from(tunnel_type: TunnelType) -> Self {
+        match tunnel_type {
+            TunnelType::WireGuard => proto::TunnelType::Wireguard,
+            TunnelType::OpenVpn => proto::TunnelType::Openvpn,
+        }
+    }
+}
+
+/// What the daemon is doing with the tunnel
+#[derive(Debug, Clone, PartialEq)]
+pub enum TunnelState {
+    Disconnected {
+        /// Lockdown mode is blocking all traffic
+        locked_down: bool,
+    },
+    /// The relay info is missing from some events, mostly early connecting ones
+    Connecting(Option<RelayInfo>),
+    Connected(Option<RelayInfo>),
+    Disconnecting,
+    /// `None` if the daemon didn't say what went wrong or we can't make sense of it
+    Error(Option<ErrorState>),
+}
+
+impl TryFrom<proto::TunnelState> for TunnelState {
+    type Error = Error;
+
+    fn try_from(tunnel_state: proto::TunnelState) -> Result<Self, Error> {
+        use proto::tunnel_state::State;
+        let state = tunnel_state.state.ok_or(Error::MissingField("tunnel state"))?;
+        Ok(match state {
+            State::Disconnected(disconnected) => {
+                TunnelState::Disconnected { locked_down: disconnected.locked_down }
+            }
+            State::Connecting(connecting) => {
+                TunnelState::Connecting(connecting.relay_info.map(RelayInfo::from))
+            }
+            State::Connected(connected) => {
+                TunnelState::Connected(connected.relay_info.map(RelayInfo::from))
+            }
+            State::Disconnecting(_) => TunnelState::Disconnecting,
+            // that there is an error is still worth showing if its cause is from a newer daemon
+            State::Error(error) => TunnelState::Error(error.error_state.and_then(|error_state| {
+                ErrorState::try_from(error_state)
+                    .inspect_err(|e| warn!("Leaving out the cause of the error: {}", e))
+                    .ok()
+            })),
+        })
+    }
+}
+
+/// Why the tunnel is down, traffic is blocked unless `blocking_error` says otherwise
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorState {
+    pub cause: ErrorCause,
+    /// Why the traffic could not be blocked, `None` if it is
+    pub blocking_error: Option<FirewallPolicyError>,
+}
+
+impl TryFrom<proto::ErrorState> for ErrorState {
+    type Error = Error;
+
+    fn try_from(error_state: proto::ErrorState) -> Result<Self, Error> {
+        use proto::error_state::Cause;
+        let cause = match Cause::try_from(error_state.cause) {
+            Ok(Cause::AuthFailed) => {
+                ErrorCause::AuthFailed(error_state.auth_failed_error.try_into()?)
+            }
+            Ok(Cause::Ipv6Unavailable) => ErrorCause::Ipv6Unavailable,
+            Ok(Cause::SetFirewallPolicyError) => ErrorCause::SetFirewallPolicy(
+                error_state.policy_error.map(FirewallPolicyError::try_from).transpose()?,
+            ),
+            Ok(Cause::SetDnsError) => ErrorCause::SetDns,
+            Ok(Cause::StartTunnelError) => ErrorCause::StartTunnel,
+            Ok(Cause::CreateTunnelDevice) => {
+                ErrorCause::CreateTunnelDevice(error_state.create_tunnel_error)
+            }
+            Ok(Cause::TunnelParameterError) => {
+                ErrorCause::TunnelParameter(error_state.parameter_error.try_into()?)
+            }
+            Ok(Cause::IsOffline) => ErrorCause::IsOffline,
+            Ok(Cause::VpnPermissionDenied) => ErrorCause::VpnPermissionDenied,
+            Ok(Cause::SplitTunnelError) => ErrorCause::SplitTunnel,
+            Err(_) => return Err(Error::UnknownValue("error cause", error_state.cause)),
+        };
+        Ok(Self {
+            cause,
+            blocking_error: error_state.blocking_error.map(TryInto::try_into).transpose()?,
+        })
+    }
+}
+
+/// What went wrong, with the details the daemon gives for some causes
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorCause {
+    AuthFailed(AuthFailedError),
+    Ipv6Unavailable,
+    /// `None` if the daemon didn't say what went wrong with the firewall
+    SetFirewallPolicy(Option<FirewallPolicyError>),
+    SetDns,
+    StartTunnel,
+    /// The error code, if the daemon gave one
+    CreateTunnelDevice(Option<i32>),
+    TunnelParameter(TunnelParameterError),
+    IsOffline,
+    VpnPermissionDenied,
+    SplitTunnel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthFailedError {
+    Unknown,
+    InvalidAccount,
+    ExpiredAccount,
+    TooManyConnections,
+}
+
+impl TryFrom<i32> for AuthFailedError {
+    type Error = Error;
+
+    fn try_from(value: i32) -> Result<Self, Error> {
+        use proto::error_state::AuthFailedError as Proto;
+        match Proto::try_from(value) {
+            Ok(Proto::Unknown) => Ok(AuthFailedError::Unknown),
+            Ok(Proto::InvalidAccount) => Ok(AuthFailedError::InvalidAccount),
+            Ok(Proto::ExpiredAccount) => Ok(AuthFailedError::ExpiredAccount),
+            Ok(Proto::TooManyConnections) => Ok(AuthFailedError::TooManyConnections),
+            Err(_) => Err(Error::UnknownValue("authentication error", value)),
+        }
+    }
+}
+
+/// Why the daemon couldn't pick a relay to connect to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunnelParameterError {
+    NoMatchingRelay,
+    NoMatchingBridgeRelay,
+    NoWireguardKey,
+    CustomTunnelHostResolution,
+}
+
+impl TryFrom<i32> for TunnelParameterError {
+    type Error = Error;
+
+    fn try_from(value: i32) -> Result<Self, Error> {
+        use proto::error_state::GenerationError;
+        match GenerationError::try_from(value) {
+            Ok(GenerationError::NoMatchingRelay) => Ok(TunnelParameterError::NoMatchingRelay),
+            Ok(GenerationError::NoMatchingBridgeRelay) => {
+                Ok(TunnelParameterError::NoMatchingBridgeRelay)
+            }
+            Ok(GenerationError::NoWireguardKey) => Ok(TunnelParameterError::NoWireguardKey),
+            Ok(GenerationError::CustomTunnelHostResolutionError) => {
+                Ok(TunnelParameterError::CustomTunnelHostResolution)
+            }
+            Err(_) => Err(Error::UnknownValue("tunnel parameter error", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FirewallPolicyError {
+    Generic,
+    /// Another program holds the lock on the firewall
+    Locked { pid: u32, name: Option<String> },
+}
+
+impl TryFrom<proto::error_state::FirewallPolicyError> for FirewallPolicyError {
+    type Error = Error;
+
+    fn try_from(error: proto::error_state::FirewallPolicyError) -> Result<Self, Error> {
+        use proto::error_state::firewall_policy_error::ErrorType;
+        match ErrorType::try_from(error.r#type) {
+            Ok(ErrorType::Generic) => Ok(FirewallPolicyError::Generic),
+            Ok(ErrorType::Locked) => Ok(FirewallPolicyError::Locked {
+                pid: error.lock_pid,
+                name: error.lock_name.and_then(non_empty),
+            }),
+            Err(_) => Err(Error::UnknownValue("firewall error", error.r#type)),
+        }
+    }
+}
+
+/// The relays the daemon can connect to, by country and city
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RelayList {
+    pub countries: Vec<Country>,
+    /// The ports the WireGuard relays listen on
+    pub wireguard_port_ranges: Vec<RangeInclusive<u32>>,
+}
+
+impl From<proto::RelayList> for RelayList {
+    fn from(relay_list: proto::RelayList) -> Self {
+        let countries = relay_list.countries
+            .into_iter()
+            .map(|country| Country {
+                name: country.name,
+                code: country.code,
+                cities: country.cities.into_iter().map(City::from).collect(),
+            })
+            .collect();
+        let port_ranges = relay_list.wireguard.map(|wireguard| wireguard.port_ranges);
+        Self {
+            countries,
+            wireguard_port_ranges: port_ranges
+                .unwrap_or_default()
+                .into_iter()
+                .map(|range| range.first..=range.last)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Country {
+    pub name: String,
+    pub code: String,
+    pub cities: Vec<City>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct City {
+    pub name: String,
+    pub code: String,
+    pub relays: Vec<Relay>,
+}
+
+impl From<proto::RelayListCity> for City {
+    fn from(city: proto::RelayListCity) -> Self {
+        // a newer daemon may have kinds of relays that this version can't use anyway
+        let relays = city.relays
+            .into_iter()
+            .filter_map(|relay| {
+                Relay::try_from(relay).inspect_err(|e| debug!("Leaving out a relay: {}", e)).ok()
+            })
+            .collect();
+        Self { name: city.name, code: city.code, relays }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relay {
+    pub hostname: String,
+    /// `None` if the daemon sent something that isn't an IPv4 address
+    pub ipv4_addr_in: Option<Ipv4Addr>,
+    pub active: bool,
+    /// Owned by Mullvad rather than rented
+    pub owned: bool,
+    pub provider: String,
+    pub relay_type: RelayType,
+}
+
+impl TryFrom<proto::Relay> for Relay {
+    type Error = Error;
+
+    fn try_from(relay: proto::Relay) -> Result<Self, Error> {
+        use proto::relay::RelayType as Proto;
+        let relay_type = match Proto::try_from(relay.endpoint_type) {
+            Ok(Proto::Openvpn) => RelayType::OpenVpn,
+            Ok(Proto::Bridge) => RelayType::Bridge,
+            Ok(Proto::Wireguard) => RelayType::WireGuard,
+            Err(_) => return Err(Error::UnknownValue("relay type", relay.endpoint_type)),
+        };
+        Ok(Self {
+            ipv4_addr_in: relay.ipv4_addr_in.parse().ok(),
+            hostname: relay.hostname,
+            active: relay.active,
+            owned: relay.owned,
+            provider: relay.provider,
+            relay_type,
+        })
+    }
+}
+
+impl Relay {
+    /// The tunnel protocol the relay is for, `None` for bridges
+    pub fn tunnel_type(&self) -> Option<TunnelType> {
+        match self.relay_type {
+            RelayType::WireGuard => Some(TunnelType::WireGuard),
+            RelayType::OpenVpn => Some(TunnelType::OpenVpn),
+            RelayType::Bridge => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayType {
+    OpenVpn,
+    /// Used to reach OpenVPN relays on networks that block them
+    Bridge,
+    WireGuard,
+}
+
+/// A value of a setting that a newer daemon added, shown as the default in the menu
+fn known_or_default<T: Default>(value: Result<T, Error>) -> T {
+    value.inspect_err(|e| warn!("Showing the default instead: {}", e)).unwrap_or_default()
+}
+
+/// The daemon settings that the menu shows
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Settings {
+    pub block_when_disconnected: bool,
+    pub auto_connect: bool,
+    pub allow_lan: bool,
+    pub enable_ipv6: bool,
+    /// `None` unless they're the Normal kind that mulltray supports
+    pub relay_settings: Option<RelaySettings>,
+    pub bridge_state: BridgeState,
+    /// `None` lets the daemon pick any bridge
+    pub bridge_location: Option<LocationConstraint>,
+    pub dns: DnsSettings,
+    pub obfuscation: ObfuscationMode,
+    /// `None` lets the daemon pick the port
+    pub udp2tcp_port: Option<u32>,
+    pub quantum_resistant: QuantumResistant,
+    pub daita: bool,
+    /// `None` lets the daemon pick the MTU
+    pub wireguard_mtu: Option<u32>,
+    pub split_tunnel: SplitTunnel,
+    pub custom_lists: Vec<CustomList>,
+    /// The direct and bridge methods followed by the custom ones
+    pub api_access_methods: Vec<AccessMethod>,
+}
+
+impl From<proto::Settings> for Settings {
+    fn from(settings: proto::Settings) -> Self {
+        let tunnel_options = settings.tunnel_options.unwrap_or_default();
+        let wireguard = tunnel_options.wireguard.unwrap_or_default();
+        let obfuscation = settings.obfuscation_settings.unwrap_or_default();
+        let relay_settings = match settings.relay_settings {
+            Some(proto::RelaySettings {
+                endpoint: Some(proto::relay_settings::Endpoint::Normal(norm)),
+            }) => Some(norm.into()),
+            _ => None,
+        };
+        let bridge_location = settings.bridge_settings
+            .and_then(|bridge_settings| bridge_settings.normal)
+            .and_then(|bridge_constraints| location_constraint(bridge_constraints.location));
+        let api_access_methods = settings.api_access_methods.map(|methods| {
+            let builtin = [methods.direct, methods.mullvad_bridges];
+            builtin
+                .into_iter()
+                .flatten()
+                .chain(methods.custom)
+                .filter_map(|method| {
+                    AccessMethod::try_from(method)
+                        .inspect_err(|e| warn!("Leaving out an API access method: {}", e))
+                        .ok()
+                })
+                .collect()
+        });
+        Self {
+            block_when_disconnected: settings.block_when_disconnected,
+            auto_connect: settings.auto_connect,
+            allow_lan: settings.allow_lan,
+            enable_ipv6: tunnel_options.generic.is_some_and(|generic| generic.enable_ipv6),
+            relay_settings,
+            bridge_state: known_or_default(
+                settings.bridge_state.map_or(Ok(BridgeState::Auto), |state| state.state.try_into()),
+            ),
+            bridge_location,
+            dns: tunnel_options.dns_options.unwrap_or_default().into(),
+            obfuscation: known_or_default(obfuscation.selected_obfuscation.try_into()),
+            udp2tcp_port: obfuscation.udp2tcp.and_then(|udp2tcp| udp2tcp.port),
+            quantum_resistant: known_or_default(
+                wireguard.quantum_resistant
+                    .map_or(Ok(QuantumResistant::Auto), |state| state.state.try_into()),
+            ),
+            daita: wireguard.daita.is_some_and(|daita| daita.enabled),
+            wireguard_mtu: wireguard.mtu,
+            split_tunnel: settings.split_tunnel.map(SplitTunnel::from).unwrap_or_default(),
+            custom_lists: settings.custom_lists
+                .map(|lists| lists.custom_lists.into_iter().map(CustomList::from).collect())
+                .unwrap_or_default(),
+            api_access_methods: api_access_methods.unwrap_or_default(),
+        }
+    }
+}
+
+/// Which relays the daemon picks from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RelaySettings {
+    /// `None` lets the daemon pick any relay
+    pub location: Option<LocationConstraint>,
+    /// `None` lets the daemon pick the tunnel protocol
+    pub tunnel_type: Option<TunnelType>,
+    pub ownership: Ownership,
+    /// Empty for any provider
+    pub providers: Vec<String>,
+    pub use_multihop: bool,
+    /// `None` lets the daemon pick any entry relay
+    pub entry_location: Option<LocationConstraint>,
+    /// `None` lets the daemon pick the port
+    pub wireguard_port: Option<u32>,
+}
+
+impl From<proto::NormalRelaySettings> for RelaySettings {
+    fn from(norm: proto::NormalRelaySettings) -> Self {
+        let wireguard_constraints = norm.wireguard_constraints.unwrap_or_default();
+        // an unknown protocol is shown as letting the daemon pick
+        let tunnel_type = norm.tunnel_type.and_then(|tunnel_type| {
+            TunnelType::try_from(tunnel_type)
+                .inspect_err(|e| warn!("Showing the tunnel protocol as automatic: {}", e))
+                .ok()
+        });
+        Self {
+            location: location_constraint(norm.location),
+            tunnel_type,
+            ownership: known_or_default(norm.ownership.try_into()),
+            providers: norm.providers,
+            use_multihop: wireguard_constraints.use_multihop,
+            entry_location: location_constraint(wireguard_constraints.entry_location),
+            wireguard_port: wireguard_constraints.port,
+        }
+    }
+}
+
+/// Where the daemon picks relays from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LocationConstraint {
+    Location(GeographicLocation),
+    /// Any relay in the custom list with this id
+    CustomList(String),
+}
+
+impl TryFrom<proto::LocationConstraint> for LocationConstraint {
+    type Error = Error;
+
+    fn try_from(location: proto::LocationConstraint) -> Result<Self, Error> {
+        use proto::location_constraint::Type;
+        match location.r#type.ok_or(Error::MissingField("location constraint type"))? {
+            Type::Location(location) => Ok(LocationConstraint::Location(location.into())),
+            Type::CustomList(id) => Ok(LocationConstraint::CustomList(id)),
+        }
+    }
+}
+
+impl From<LocationConstraint> for proto::LocationConstraint {
+    fn from(location: LocationConstraint) -> Self {
+        use proto::location_constraint::Type;
+        let location = match location {
+            LocationConstraint::Location(location) => Type::Location(location.into()),
+            LocationConstraint::CustomList(id) => Type::CustomList(id),
+        };
+        Self { r#type: Some(location) }
+    }
+}
+
+/// A location constraint that can't be made sense of is shown as letting the daemon pick
+fn location_constraint(location: Option<proto::LocationConstraint>) -> Option<LocationConstraint> {
+    let location = LocationConstraint::try_from(location?);
+    location.inspect_err(|e| warn!("Showing the location as automatic: {}", e)).ok()
+}
+
+/// A country, a city in it or a relay in the city
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeographicLocation {
+    pub country: String,
+    pub city: Option<String>,
+    pub hostname: Option<String>,
+}
+
+impl From<proto::GeographicLocationConstraint> for GeographicLocation {
+    fn from(location: proto::GeographicLocationConstraint) -> Self {
+        Self {
+            country: location.country,
+            city: location.city.and_then(non_empty),
+            hostname: location.hostname.and_then(non_empty),
+        }
+    }
+}
+
+impl From<GeographicLocation> for proto::GeographicLocationConstraint {
+    fn from(location: GeographicLocation) -> Self {
+        let GeographicLocation { country, city, hostname } = location;
+        Self { country, city, hostname }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Ownership {
+    #[default]
+    Any,
+    MullvadOwned,
+    Rented,
+}
+
+impl TryFrom<i32> for Ownership {
+    type Error = Error;
+
+    fn try_from(value: i32) -> Result<Self, Error> {
+        match proto::Ownership::try_from(value) {
+            Ok(proto::Ownership::Any) => Ok(Ownership::Any),
+            Ok(proto::Ownership::MullvadOwned) => Ok(Ownership::MullvadOwned),
+            Ok(proto::Ownership::Rented) => Ok(Ownership::Rented),
+            Err(_) => Err(Error::UnknownValue("ownership", value)),
+        }
+    }
+}
+
+/// Whether OpenVPN connections go through a bridge
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BridgeState {
+    #[default]
+    Auto,
+    On,
+    Off,
+}
+
+impl TryFrom<i32> for BridgeState {
+    type Error = Error;
+
+    fn try_from(value: i32) -> Result<Self, Error> {
+        use proto::bridge_state::State;
+        match State::try_from(value) {
+            Ok(State::Auto) => Ok(BridgeState::Auto),
+            Ok(State::On) => Ok(BridgeState::On),
+            Ok(State::Off) => Ok(BridgeState::Off),
+            Err(_) => Err(Error::UnknownValue("bridge state", value)),
+        }
+    }
+}
+
+impl From<BridgeState> for proto::bridge_state::State {
+    fn from(state: BridgeState) -> Self {
+        match state {
+            BridgeState::Auto => Self::Auto,
+            BridgeState::On => Self::On,
+            BridgeState::Off => Self::Off,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuantumResistant {
+    #[default]
+    Auto,
+    On,
+    Off,
+}
+
+impl TryFrom<i32> for QuantumResistant {
+    type Error = Error;
+
+    fn try_from(value: i32) -> Result<Self, Error> {
+        use proto::quantum_resistant_state::State;
+        match State::try_from(value) {
+            Ok(State::Auto) => Ok(QuantumResistant::Auto),
+            Ok(State::On) => Ok(QuantumResistant::On),
+            Ok(State::Off) => Ok(QuantumResistant::Off),
+            Err(_) => Err(Error::UnknownValue("quantum-resistant state", value)),
+        }
+    }
+}
+
+impl From<QuantumResistant> for proto::quantum_resistant_state::State {
+    fn from(state: QuantumResistant) -> Self {
+        match state {
+            QuantumResistant::Auto => Self::Auto,
+            QuantumResistant::On => Self::On,
+            QuantumResistant::Off => Self::Off,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ObfuscationMode {
+    #[default]
+    Auto,
+    Off,
+    Udp2Tcp,
+    Shadowsocks,
+}
+
+impl TryFrom<i32> for ObfuscationMode {
+    type Error = Error;
+
+    fn try_from(value: i32) -> Result<Self, Error> {
+        use proto::obfuscation_settings::SelectedObfuscation;
+        match SelectedObfuscation::try_from(value) {
+            Ok(SelectedObfuscation::Auto) => Ok(ObfuscationMode::Auto),
+            Ok(SelectedObfuscation::Off) => Ok(ObfuscationMode::Off),
+            Ok(SelectedObfuscation::Udp2tcp) => Ok(ObfuscationMode::Udp2Tcp),
+            Ok(SelectedObfuscation::Shadowsocks) => Ok(ObfuscationMode::Shadowsocks),
+            Err(_) => Err(Error::UnknownValue("obfuscation mode", value)),
+        }
+    }
+}
+
+impl From<ObfuscationMode> for proto::obfuscation_settings::SelectedObfuscation {
+    fn from(mode: ObfuscationMode) -> Self {
+        match mode {
+            ObfuscationMode::Auto => Self::Auto,
+            ObfuscationMode::Off => Self::Off,
+            ObfuscationMode::Udp2Tcp => Self::Udp2tcp,
+            ObfuscationMode::Shadowsocks => Self::Shadowsocks,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DnsSettings {
+    /// Whether the custom servers are used instead of the default ones
+    pub custom: bool,
+    /// The content blockers that are on, they only apply to the default servers
+    pub blockers: Vec<DnsBlocker>,
+    pub custom_servers: Vec<String>,
+}
+
+impl From<proto::DnsOptions> for DnsSettings {
+    fn from(dns_options: proto::DnsOptions) -> Self {
+        let mut default_options = dns_options.default_options.unwrap_or_default();
+        let blockers = DnsBlocker::ALL
+            .into_iter()
+            .filter(|blocker| *blocker.field()(&mut default_options))
+            .collect();
+        Self {
+            custom: dns_options.state == proto::dns_options::DnsState::Custom as i32,
+            blockers,
+            custom_servers: dns_options.custom_options
+                .map(|custom_options| custom_options.addresses)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// The content the default DNS servers can block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsBlocker {
+    Ads,
+    Trackers,
+    Malware,
+    AdultContent,
+    Gambling,
+    SocialMedia,
+}
+
+impl DnsBlocker {
+    pub const ALL: [DnsBlocker; 6] = [
+        DnsBlocker::Ads,
+        DnsBlocker::Trackers,
+        DnsBlocker::Malware,
+        DnsBlocker::AdultContent,
+        DnsBlocker::Gambling,
+        DnsBlocker::SocialMedia,
+    ];
+
+    /// Where the blocker is turned on in the proto settings
+    pub fn field(self) -> fn(&mut proto::DefaultDnsOptions) -> &mut bool {
+        match self {
+            DnsBlocker::Ads => |options| &mut options.block_ads,
+            DnsBlocker::Trackers => |options| &mut options.block_trackers,
+            DnsBlocker::Malware => |options| &mut options.block_malware,
+            DnsBlocker::AdultContent => |options| &mut options.block_adult_content,
+            DnsBlocker::Gambling => |options| &mut options.block_gambling,
+            DnsBlocker::SocialMedia => |options| &mut options.block_social_media,
+        }
+    }
+}
+
+/// The applications that are kept out of the tunnel
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SplitTunnel {
+    pub enabled: bool,
+    /// Paths of the executables
+    pub apps: Vec<String>,
+}
+
+impl From<proto::SplitTunnelSettings> for SplitTunnel {
+    fn from(split_tunnel: proto::SplitTunnelSettings) -> Self {
+        Self { enabled: split_tunnel.enable_exclusions, apps: split_tunnel.apps }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomList {
+    pub id: String,
+    pub name: String,
+    pub locations: Vec<GeographicLocation>,
+}
+
+impl From<proto::CustomList> for CustomList {
+    fn from(list: proto::CustomList) -> Self {
+        Self {
+            id: list.id,
+            name: list.name,
+            locations: list.locations.into_iter().map(GeographicLocation::from).collect(),
+        }
+    }
+}
+
+/// A way for the daemon to reach the Mullvad API
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessMethod {
+    pub id: String,
+    pub name: String,
+    /// The daemon won't use methods that are turned off
+    pub enabled: bool,
+}
+
+impl TryFrom<proto::AccessMethodSetting> for AccessMethod {
+    type Error = Error;
+
+    fn try_from(method: proto::AccessMethodSetting) -> Result<Self, Error> {
+        let id = method.id.ok_or(Error::MissingField("id of the API access method"))?;
+        Ok(Self { id: id.value, name: method.name, enabled: method.enabled })
+    }
+}
+
+/// Whether this device is logged in to a Mullvad account
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceState {
+    /// `None` if the daemon left out the account or the device
+    LoggedIn(Option<AccountAndDevice>),
+    LoggedOut,
+    /// Removed from the account, e.g. from another device
+    Revoked,
+}
+
+impl TryFrom<proto::DeviceState> for DeviceState {
+    type Error = Error;
+
+    fn try_from(device_state: proto::DeviceState) -> Result<Self, Error> {
+        use proto::device_state::State;
+        match State::try_from(device_state.state) {
+            Ok(State::LoggedIn) => {
+                Ok(DeviceState::LoggedIn(device_state.device.and_then(|account_and_device| {
+                    let device = account_and_device.device?;
+                    Some(AccountAndDevice {
+                        account_number: account_and_device.account_token,
+                        device_id: device.id,
+                        device_name: device.name,
+                    })
+                })))
+            }
+            Ok(State::LoggedOut) => Ok(DeviceState::LoggedOut),
+            Ok(State::Revoked) => Ok(DeviceState::Revoked),
+            Err(_) => Err(Error::UnknownValue("device state", device_state.state)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountAndDevice {
+    pub account_number: String,
+    pub device_id: String,
+    pub device_name: String,
+}
+
+/// Whether the installed Mullvad app is worth upgrading
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionInfo {
+    pub supported: bool,
+    /// `None` if the installed version is the one to have
+    pub suggested_upgrade: Option<String>,
+}
+
+impl From<proto::AppVersionInfo> for VersionInfo {
+    fn from(version_info: proto::AppVersionInfo) -> Self {
+        Self {
+            supported: version_info.supported,
+            suggested_upgrade: version_info.suggested_upgrade.and_then(non_empty),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{connected, relay_list, settings, tunnel_state};
+    use proto::tunnel_state::State;
+
+    fn error_state(cause: proto::error_state::Cause) -> proto::ErrorState {
+        proto::ErrorState { cause: cause.into(), ..Default::default() }
+    }
+
+    fn in_error(error_state: proto::ErrorState) -> proto::TunnelState {
+        tunnel_state(State::Error(proto::tunnel_state::Error { error_state: Some(error_state) }))
+    }
+
+    #[test]
+    fn tunnel_states_convert() {
+        let locked_down = State::Disconnected(proto::tunnel_state::Disconnected {
+            locked_down: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            TunnelState::try_from(tunnel_state(locked_down)).unwrap(),
+            TunnelState::Disconnected { locked_down: true }
+        );
+        let connecting = State::Connecting(Default::default());
+        assert_eq!(
+            TunnelState::try_from(tunnel_state(connecting)).unwrap(),
+            TunnelState::Connecting(None)
+        );
+        let TunnelState::Connected(Some(relay_info)) =
+            TunnelState::try_from(tunnel_state(connected("se-got-wg-001"))).unwrap()
+        else {
+            panic!("not connected with relay info");
+        };
+        assert_eq!(relay_info.hostname().map(String::as_str), Some("se-got-wg-001"));
+        let disconnecting = State::Disconnecting(Default::default());
+        assert_eq!(
+            TunnelState::try_from(tunnel_state(disconnecting)).unwrap(),
+            TunnelState::Disconnecting
+        );
+        assert!(TunnelState::try_from(proto::TunnelState { state: None }).is_err());
+    }
+
+    #[test]
+    fn error_states_keep_their_details() {
+        use proto::error_state::{AuthFailedError as Proto, Cause, FirewallPolicyError as Policy};
+        let mut auth_failed = error_state(Cause::AuthFailed);
+        auth_failed.set_auth_failed_error(Proto::ExpiredAccount);
+        auth_failed.blocking_error = Some(Policy {
+            r#type: proto::error_state::firewall_policy_error::ErrorType::Locked.into(),
+            lock_pid: 42,
+            lock_name: Some("iptables".into()),
+        });
+        assert_eq!(
+            TunnelState::try_from(in_error(auth_failed)).unwrap(),
+            TunnelState::Error(Some(ErrorState {
+                cause: ErrorCause::AuthFailed(AuthFailedError::ExpiredAccount),
+                blocking_error: Some(FirewallPolicyError::Locked {
+                    pid: 42,
+                    name: Some("iptables".into())
+                }),
+            }))
+        );
+        let mut create_device = error_state(Cause::CreateTunnelDevice);
+        create_device.create_tunnel_error = Some(5);
+        assert_eq!(
+            ErrorState::try_from(create_device).unwrap().cause,
+            ErrorCause::CreateTunnelDevice(Some(5))
+        );
+    }
+
+    #[test]
+    fn errors_from_a_newer_daemon_are_still_errors() {
+        let unknown_cause = proto::ErrorState { cause: 1000, ..Default::default() };
+        assert!(ErrorState::try_from(unknown_cause.clone()).is_err());
+        let shown = TunnelState::try_from(in_error(unknown_cause)).unwrap();
+        assert_eq!(shown, TunnelState::Error(None));
+        let error = State::Error(proto::tunnel_state::Error { error_state: None });
+        assert_eq!(TunnelState::try_from(tunnel_state(error)).unwrap(), TunnelState::Error(None));
+    }
+
+    #[test]
+    fn relays_of_unknown_types_are_left_out() {
+        let mut relay_list = relay_list(&["se-got-wg-001", "se-got-wg-002"]);
+        relay_list.countries[0].cities[0].relays[1].endpoint_type = 1000;
+        relay_list.wireguard = Some(proto::WireguardEndpointData {
+            port_ranges: vec![proto::PortRange { first: 51820, last: 51830 }],
+            ..Default::default()
+        });
+        let relay_list = RelayList::from(relay_list);
+        let relays = &relay_list.countries[0].cities[0].relays;
+        assert_eq!(relays.len(), 1);
+        assert_eq!(relays[0].hostname, "se-got-wg-001");
+        assert_eq!(relays[0].ipv4_addr_in, Some(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(relays[0].tunnel_type(), Some(TunnelType::WireGuard));
+        assert_eq!(relay_list.wireguard_port_ranges, [51820..=51830]);
+    }
+
+    #[test]
+    fn settings_convert() {
+        let mut proto_settings = settings();
+        proto_settings.allow_lan = true;
+        let Some(proto::RelaySettings {
+            endpoint: Some(proto::relay_settings::Endpoint::Normal(norm)),
+        }) = &mut proto_settings.relay_settings
+        else {
+            unreachable!();
+        };
+        norm.set_tunnel_type(proto::TunnelType::Openvpn);
+        norm.set_ownership(proto::Ownership::MullvadOwned);
+        norm.location = Some(proto::LocationConstraint {
+            r#type: Some(proto::location_constraint::Type::CustomList("list".into())),
+        });
+        proto_settings.tunnel_options = Some(proto::TunnelOptions {
+            dns_options: Some(proto::DnsOptions {
+                default_options: Some(proto::DefaultDnsOptions {
+                    block_ads: true,
+                    block_gambling: true,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        // from a newer daemon
+        proto_settings.obfuscation_settings = Some(proto::ObfuscationSettings {
+            selected_obfuscation: 1000,
+            ..Default::default()
+        });
+        proto_settings.api_access_methods = Some(proto::ApiAccessMethodSettings {
+            direct: Some(proto::AccessMethodSetting {
+                id: Some(proto::Uuid { value: "direct".into() }),
+                name: "Direct".into(),
+                enabled: true,
+                access_method: None,
+            }),
+            mullvad_bridges: Some(proto::AccessMethodSetting::default()),
+            custom: vec![],
+        });
+
+        let settings = Settings::from(proto_settings);
+        assert!(settings.allow_lan);
+        let relay_settings = settings.relay_settings.unwrap();
+        assert_eq!(relay_settings.tunnel_type, Some(TunnelType::OpenVpn));
+        assert_eq!(relay_settings.ownership, Ownership::MullvadOwned);
+        assert_eq!(relay_settings.location, Some(LocationConstraint::CustomList("list".into())));
+        assert_eq!(settings.dns.blockers, [DnsBlocker::Ads, DnsBlocker::Gambling]);
+        assert!(!settings.dns.custom);
+        assert_eq!(settings.obfuscation, ObfuscationMode::Auto);
+        // the bridges method came without an id
+        let ids: Vec<&str> = settings.api_access_methods.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["direct"]);
+    }
+
+    #[test]
+    fn custom_relay_settings_are_not_normal() {
+        let mut proto_settings = settings();
+        proto_settings.relay_settings = Some(proto::RelaySettings {
+            endpoint: Some(proto::relay_settings::Endpoint::Custom(Default::default())),
+        });
+        assert_eq!(Settings::from(proto_settings).relay_settings, None);
+    }
+
+    #[test]
+    fn locations_convert_back_and_forth() {
+        let location = LocationConstraint::Location(GeographicLocation {
+            country: "se".into(),
+            city: Some("got".into()),
+            hostname: None,
+        });
+        let sent = proto::LocationConstraint::from(location.clone());
+        assert_eq!(LocationConstraint::try_from(sent).unwrap(), location);
+        let custom_list = LocationConstraint::CustomList("list".into());
+        let sent = proto::LocationConstraint::from(custom_list.clone());
+        assert_eq!(LocationConstraint::try_from(sent).unwrap(), custom_list);
+        assert!(LocationConstraint::try_from(proto::LocationConstraint { r#type: None }).is_err());
+    }
+
+    #[test]
+    fn device_states_convert() {
+        use proto::device_state::State;
+        let logged_in = proto::DeviceState {
+            state: State::LoggedIn.into(),
+            device: Some(proto::AccountAndDevice {
+                account_token: "1234".into(),
+                device: Some(proto::Device {
+                    id: "id".into(),
+                    name: "happy seal".into(),
+                    ..Default::default()
+                }),
+            }),
+        };
+        assert_eq!(
+            DeviceState::try_from(logged_in).unwrap(),
+            DeviceState::LoggedIn(Some(AccountAndDevice {
+                account_number: "1234".into(),
+                device_id: "id".into(),
+                device_name: "happy seal".into(),
+            }))
+        );
+        let revoked = proto::DeviceState { state: State::Revoked.into(), device: None };
+        assert_eq!(DeviceState::try_from(revoked).unwrap(), DeviceState::Revoked);
+        let unknown = proto::DeviceState { state: 1000, device: None };
+        assert!(DeviceState::try_from(unknown).is_err());
+    }
+}
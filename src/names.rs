@@ -0,0 +1,274 @@
+//! Location names for people: countries in the language of the user and sorting that
+//! doesn't put "Åland" after "Zambia"
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// From the iso-codes package, which most distributions install
+const ISO_3166_JSON: &str = "/usr/share/iso-codes/json/iso_3166-1.json";
+const LOCALE_DIR: &str = "/usr/share/locale";
+
+/// The letter a letter with diacritics is sorted as, or the letters for ligatures
+fn base_letters(c: char) -> Option<&'static str> {
+    let base = match c {
+        'à'..='å' | 'ā'..='ą' => "a",
+        'æ' => "ae",
+        'ç' | 'ć'..='č' => "c",
+        'ď'..='đ' | 'ð' => "d",
+        'è'..='ë' | 'ē'..='ě' => "e",
+        'ĝ'..='ģ' => "g",
+        'ĥ'..='ħ' => "h",
+        'ì'..='ï' | 'ĩ'..='ı' => "i",
+        'ĵ' => "j",
+        'ķ'..='ĸ' => "k",
+        'ĺ'..='ł' => "l",
+        'ñ' | 'ń'..='ŋ' => "n",
+        'ò'..='ö' | 'ø' | 'ō'..='ő' => "o",
+        'œ' => "oe",
+        'ŕ'..='ř' => "r",
+        'ś'..='š' | 'ș' => "s",
+        'ß' => "ss",
+        'ţ'..='ŧ' | 'ț' => "t",
+        'þ' => "th",
+        'ù'..='ü' | 'ũ'..='ų' => "u",
+        'ŵ' => "w",
+        'ý' | 'ÿ' | 'ŷ' => "y",
+        'ź'..='ž' => "z",
+        _ => return None,
+    };
+    Some(base)
+}
+
+/// Ignores case and diacritics, which is close enough to how most languages sort names.
+/// Languages that sort some letters after "z", like Swedish does with "å", aren't handled.
+pub fn collation_key(name: &str) -> String {
+    let mut key = String::with_capacity(name.len());
+    for c in name.chars().flat_map(char::to_lowercase) {
+        match base_letters(c) {
+            Some(base) => key.push_str(base),
+            None => key.push(c),
+        }
+    }
+    key
+}
+
+/// Orders names by [`collation_key`] and then byte-wise, so names that only differ by
+/// accents still have a fixed order
+pub fn compare_names(a: &str, b: &str) -> std::cmp::Ordering {
+    collation_key(a).cmp(&collation_key(b)).then_with(|| a.cmp(b))
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Read(path, e) => write!(f, "Could not read {}: {}", path.display(), e),
+            Error::Parse(path, e) => write!(f, "Invalid file {}: {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Deserialize)]
+struct IsoCodes {
+    #[serde(rename = "3166-1")]
+    countries: Vec<IsoCountry>,
+}
+
+#[derive(Deserialize)]
+struct IsoCountry {
+    alpha_2: String,
+    name: String,
+    /// e.g. "Taiwan" while `name` is "Taiwan, Province of China"
+    common_name: Option<String>,
+}
+
+/// The translations in a gettext .mo file, `None` if it's broken
+fn parse_mo(bytes: &[u8]) -> Option<HashMap<&str, &str>> {
+    let word = |little_endian: bool, at: usize| -> Option<usize> {
+        let word = bytes.get(at..at + 4)?.try_into().ok()?;
+        let word = if little_endian { u32::from_le_bytes(word) } else { u32::from_be_bytes(word) };
+        Some(word as usize)
+    };
+    let little_endian = match word(true, 0)? {
+        0x950412de => true,
+        0xde120495 => false,
+        _ => return None,
+    };
+    let count = word(little_endian, 8)?;
+    let originals = word(little_endian, 12)?;
+    let translations = word(little_endian, 16)?;
+    let string = |table: usize, i: usize| -> Option<&str> {
+        let len = word(little_endian, table + 8 * i)?;
+        let offset = word(little_endian, table + 8 * i + 4)?;
+        std::str::from_utf8(bytes.get(offset..offset + len)?).ok()
+    };
+    (0..count).map(|i| Some((string(originals, i)?, string(translations, i)?))).collect()
+}
+
+/// Languages to look for translations in, most preferred first, e.g. `sv_SE` and `sv`
+fn preferred_languages() -> Vec<String> {
+    let mut languages = vec![];
+    let language_list = std::env::var("LANGUAGE").unwrap_or_default();
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    for locale in language_list.split(':').chain([locale.as_str()]) {
+        // e.g. sv_SE.UTF-8@euro
+        let locale = locale.split(['.', '@']).next().unwrap_or_default();
+        if locale.is_empty() || locale == "C" || locale == "POSIX" {
+            continue;
+        }
+        languages.push(locale.to_string());
+        if let Some((language, _)) = locale.split_once('_') {
+            languages.push(language.to_string());
+        }
+    }
+    languages
+}
+
+/// Translated country names by lowercase ISO 3166-1 code, the same codes the daemon uses
+#[derive(Debug, Default)]
+pub struct CountryNames(HashMap<String, String>);
+
+impl CountryNames {
+    /// Loads the names for the language of the locale. There are none for English or when
+    /// there's no translation for the language.
+    pub fn load() -> Result<Self, Error> {
+        let Some(catalog) = preferred_languages()
+            .into_iter()
+            .map(|language| Path::new(LOCALE_DIR).join(language).join("LC_MESSAGES/iso_3166-1.mo"))
+            .find(|path| path.exists())
+        else {
+            return Ok(Self::default());
+        };
+        let read = |path: &Path| std::fs::read(path).map_err(|e| Error::Read(path.into(), e));
+        let iso_codes = read(Path::new(ISO_3166_JSON))?;
+        let iso_codes: IsoCodes = serde_json::from_slice(&iso_codes)
+            .map_err(|e| Error::Parse(ISO_3166_JSON.into(), e.to_string()))?;
+        let catalog_bytes = read(&catalog)?;
+        let translations = parse_mo(&catalog_bytes)
+            .ok_or_else(|| Error::Parse(catalog.clone(), "not a gettext catalog".into()))?;
+        let names = iso_codes.countries
+            .into_iter()
+            .filter_map(|country| {
+                let name = country.common_name.as_ref().unwrap_or(&country.name);
+                let translated = translations.get(name.as_str()).filter(|name| !name.is_empty())?;
+                Some((country.alpha_2.to_lowercase(), translated.to_string()))
+            })
+            .collect();
+        Ok(Self(names))
+    }
+
+    pub fn get(&self, code: &str) -> Option<&str> {
+        self.0.get(code).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accents_and_case_do_not_change_the_order() {
+        let mut names = [
+            "Zambia",
+            "Österreich",
+            "Czechia",
+            "Åland Islands",
+            "Ísland",
+            "albania",
+            "Česko",
+            "Éire",
+        ];
+        names.sort_by(|a, b| compare_names(a, b));
+        assert_eq!(
+            names,
+            [
+                "Åland Islands",
+                "albania",
+                "Česko",
+                "Czechia",
+                "Éire",
+                "Ísland",
+                "Österreich",
+                "Zambia",
+            ]
+        );
+    }
+
+    #[test]
+    fn names_that_only_differ_by_accents_have_a_fixed_order() {
+        use std::cmp::Ordering;
+        assert_eq!(compare_names("Reze", "Rezé"), Ordering::Less);
+        assert_eq!(compare_names("Rezé", "Reze"), Ordering::Greater);
+        assert_eq!(compare_names("Rezé", "Rezé"), Ordering::Equal);
+    }
+
+    #[test]
+    fn ligatures_sort_as_their_letters() {
+        assert_eq!(collation_key("Æbeltoft"), "aebeltoft");
+        assert_eq!(collation_key("Straße"), "strasse");
+        assert_eq!(collation_key("Þórshöfn"), "thorshofn");
+        assert_eq!(collation_key("Łódź"), "lodz");
+    }
+
+    /// A gettext catalog with `entries`, laid out like msgfmt does
+    fn mo(entries: &[(&str, &str)], little_endian: bool) -> Vec<u8> {
+        let word = |n: usize| match little_endian {
+            true => (n as u32).to_le_bytes(),
+            false => (n as u32).to_be_bytes(),
+        };
+        let originals = 28;
+        let translations = originals + 8 * entries.len();
+        let mut strings = translations + 8 * entries.len();
+        let mut bytes = vec![];
+        for n in [0x950412de, 0, entries.len(), originals, translations, 0, 0] {
+            bytes.extend(word(n));
+        }
+        let mut tables = [vec![], vec![]];
+        let mut data = vec![];
+        for (table, text) in entries.iter().flat_map(|(from, to)| [(0, from), (1, to)]) {
+            tables[table].extend(word(text.len()));
+            tables[table].extend(word(strings));
+            data.extend(text.as_bytes());
+            data.push(0);
+            strings += text.len() + 1;
+        }
+        bytes.extend(tables.concat());
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn catalogs_are_read_in_either_byte_order() {
+        let entries = [("Sweden", "Sverige"), ("Austria", "Österrike")];
+        for little_endian in [true, false] {
+            let bytes = mo(&entries, little_endian);
+            let translations = parse_mo(&bytes).unwrap();
+            assert_eq!(translations.get("Sweden"), Some(&"Sverige"));
+            assert_eq!(translations.get("Austria"), Some(&"Österrike"));
+            assert_eq!(translations.len(), 2);
+        }
+    }
+
+    #[test]
+    fn broken_catalogs_are_rejected() {
+        let bytes = mo(&[("Sweden", "Sverige")], true);
+        assert!(parse_mo(&bytes[..bytes.len() - 4]).is_none());
+        assert!(parse_mo(&bytes[..20]).is_none());
+        assert!(parse_mo(b"not a catalog at all").is_none());
+        assert!(parse_mo(&[]).is_none());
+    }
+}
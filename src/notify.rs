@@ -0,0 +1,151 @@
+//! Desktop notifications through the freedesktop `org.freedesktop.Notifications` D-Bus service
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use dbus::arg::PropMap;
+use dbus::blocking::Connection;
+use dbus::message::MatchRule;
+use tracing::warn;
+
+const APP_NAME: &str = "mulltray";
+const ICON: &str = "network-vpn-symbolic";
+const INTERFACE: &str = "org.freedesktop.Notifications";
+const OBJECT_PATH: &str = "/org/freedesktop/Notifications";
+// how often the stop flag is checked while waiting for a notification to be clicked
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A button on a notification: the key it's reported back with, and its label
+pub type Action = (&'static str, &'static str);
+
+/// The signals for every notification by id, with the key of the clicked action or `None`
+/// for closing
+type Signals = Arc<Mutex<Vec<(u32, Option<String>)>>>;
+
+/// Shows a notification, replacing the notification with id `replaces_id` (0 for none).
+/// Returns the id of the new notification.
+///
+/// This blocks until the notification server replies so don't call it from async code directly.
+pub fn send(
+    conn: &Connection,
+    summary: &str,
+    body: &str,
+    actions: &[Action],
+    replaces_id: u32,
+) -> Result<u32, dbus::Error> {
+    let proxy = conn.with_proxy(INTERFACE, OBJECT_PATH, Duration::from_secs(5));
+    let actions: Vec<&str> = actions.iter().flat_map(|&(key, label)| [key, label]).collect();
+    let hints = PropMap::new();
+    // -1 lets the notification server decide when the notification expires
+    let expire_timeout = -1i32;
+    let (id,): (u32,) = proxy.method_call(
+        INTERFACE,
+        "Notify",
+        (APP_NAME, replaces_id, ICON, summary, body, actions, hints, expire_timeout),
+    )?;
+    Ok(id)
+}
+
+/// Shows a notification and waits for one of its actions to be clicked, if it has any.
+/// Returns the key of the clicked action, `None` if the notification expired, was closed or
+/// was replaced by a newer one first, or `stop` was set.
+fn show(
+    summary: &str,
+    body: &str,
+    actions: &[Action],
+    last_id: &AtomicU32,
+    stop: &AtomicBool,
+) -> Result<Option<&'static str>, dbus::Error> {
+    let conn = Connection::new_session()?;
+    let signals = Signals::default();
+    // listen before the notification is shown so that not even a quick click is missed
+    if !actions.is_empty() {
+        let invoked = signals.clone();
+        conn.add_match(
+            MatchRule::new_signal(INTERFACE, "ActionInvoked"),
+            move |(id, key): (u32, String), _, _| {
+                invoked.lock().unwrap_or_else(PoisonError::into_inner).push((id, Some(key)));
+                true
+            },
+        )?;
+        let closed = signals.clone();
+        conn.add_match(
+            MatchRule::new_signal(INTERFACE, "NotificationClosed"),
+            move |(id, _reason): (u32, u32), _, _| {
+                closed.lock().unwrap_or_else(PoisonError::into_inner).push((id, None));
+                true
+            },
+        )?;
+    }
+    let id = send(&conn, summary, body, actions, last_id.load(Ordering::Relaxed))?;
+    last_id.store(id, Ordering::Relaxed);
+    if actions.is_empty() {
+        return Ok(None);
+    }
+    // replacing a notification doesn't close it, so keep an eye on the replacements too
+    while last_id.load(Ordering::Relaxed) == id {
+        if stop.load(Ordering::Relaxed) {
+            // nothing would handle the buttons once we're gone
+            let proxy = conn.with_proxy(INTERFACE, OBJECT_PATH, Duration::from_secs(1));
+            let _: Result<(), _> = proxy.method_call(INTERFACE, "CloseNotification", (id,));
+            return Ok(None);
+        }
+        conn.process(POLL_INTERVAL)?;
+        let received = std::mem::take(&mut *signals.lock().unwrap_or_else(PoisonError::into_inner));
+        if let Some((_, key)) = received.into_iter().find(|(signal_id, _)| *signal_id == id) {
+            let action = key.and_then(|key| actions.iter().find(|(k, _)| *k == key));
+            return Ok(action.map(|&(key, _)| key));
+        }
+    }
+    Ok(None)
+}
+
+/// Shows notifications in the background, each one replacing the previous so that only
+/// the latest one stays on screen
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    tokio_handle: tokio::runtime::Handle,
+    last_id: Arc<AtomicU32>,
+    /// Set at shutdown so that nothing is left waiting for a click
+    stop: Arc<AtomicBool>,
+}
+
+impl Notifier {
+    pub fn new(tokio_handle: tokio::runtime::Handle) -> Self {
+        Self {
+            tokio_handle,
+            last_id: Arc::new(AtomicU32::new(0)),
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn notify(&self, summary: String, body: String) {
+        self.notify_with_actions(summary, body, &[], |_| {});
+    }
+
+    /// Shows a notification with buttons, `on_action` is called with the key of the one
+    /// that gets clicked. Servers that don't support actions show the notification without.
+    pub fn notify_with_actions(
+        &self,
+        summary: String,
+        body: String,
+        actions: &'static [Action],
+        on_action: impl FnOnce(&'static str) + Send + 'static,
+    ) {
+        let last_id = self.last_id.clone();
+        let stop = self.stop.clone();
+        self.tokio_handle.spawn_blocking(move || {
+            match show(&summary, &body, actions, &last_id, &stop) {
+                Ok(Some(key)) => on_action(key),
+                Ok(None) => {}
+                Err(e) => warn!("Could not show notification: {e}"),
+            }
+        });
+    }
+
+    /// Stops waiting for clicks and closes the notifications that have buttons
+    pub fn shutdown(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,172 @@
+//! Asking the user for text with a zenity or kdialog dialog, or to pick from a long list with
+//! a dmenu-style chooser, since the tray menu can't take text input
+
+use std::fmt;
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Dialogs that are forgotten on another workspace are closed after this long
+const TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug)]
+pub enum Error {
+    /// Neither zenity nor kdialog is installed
+    NoDialogTool,
+    /// None of the dmenu-style choosers is installed
+    NoChooser,
+    TimedOut,
+    Io(String, std::io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoDialogTool => write!(f, "install zenity or kdialog"),
+            Error::NoChooser => {
+                write!(f, "install rofi, fuzzel, wofi or dmenu, or set `chooser` in the config")
+            }
+            Error::TimedOut => write!(f, "the dialog was not answered in time"),
+            Error::Io(tool, e) => write!(f, "could not run {tool}: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Shows a dialog with a text field. Returns `None` if the user cancelled.
+///
+/// The dialog is closed if the returned future is dropped, e.g. when quitting.
+pub async fn ask(title: &str, text: &str) -> Result<Option<String>, Error> {
+    run_dialog([
+        ("zenity", vec!["--entry", "--title", title, "--text", text]),
+        ("kdialog", vec!["--title", title, "--inputbox", text]),
+    ])
+    .await
+}
+
+/// Shows a file chooser and returns the path of the chosen file, `None` if the user cancelled.
+///
+/// The dialog is closed like the one of [`ask`].
+pub async fn choose_file(title: &str) -> Result<Option<String>, Error> {
+    run_dialog([
+        ("zenity", vec!["--file-selection", "--title", title]),
+        ("kdialog", vec!["--title", title, "--getopenfilename"]),
+    ])
+    .await
+}
+
+/// Runs the first of the dialog tools that is installed and returns what it printed
+async fn run_dialog(dialogs: [(&'static str, Vec<&str>); 2]) -> Result<Option<String>, Error> {
+    for (tool, args) in dialogs {
+        match run(tool, &args, None).await {
+            Err(Error::Io(_, e)) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            result => return result,
+        }
+    }
+    Err(Error::NoDialogTool)
+}
+
+/// dmenu-style choosers that read the choices from stdin, in the order they are tried
+const CHOOSERS: [(&str, &[&str]); 4] = [
+    ("rofi", &["-dmenu", "-i", "-p", "Location"]),
+    ("fuzzel", &["--dmenu"]),
+    ("wofi", &["--dmenu", "--insensitive"]),
+    ("dmenu", &["-i"]),
+];
+
+/// Lets the user pick one of the lines with a dmenu-style chooser, `command` or otherwise the
+/// first one that is installed. Returns `None` if the user cancelled.
+///
+/// The chooser is closed like the dialog of [`ask`].
+pub async fn choose(
+    command: Option<&[String]>,
+    lines: &[String],
+) -> Result<Option<String>, Error> {
+    // the choices can't contain line breaks, they'd show up as several
+    let input: String = lines.iter().map(|line| line.replace('\n', " ") + "\n").collect();
+    if let Some([program, args @ ..]) = command {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        return run(program, &args, Some(&input)).await;
+    }
+    for (tool, args) in CHOOSERS {
+        match run(tool, args, Some(&input)).await {
+            Err(Error::Io(_, e)) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            result => return result,
+        }
+    }
+    Err(Error::NoChooser)
+}
+
+/// Runs the tool with `input` on its stdin and returns what it printed, `None` if it was
+/// cancelled. Fails with an `Io` error of kind `NotFound` if the tool isn't installed.
+async fn run(tool: &str, args: &[&str], input: Option<&str>) -> Result<Option<String>, Error> {
+    let io_error = |e| Error::Io(tool.to_string(), e);
+    let mut child = Command::new(tool)
+        .args(args)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        // so that a dialog that's open when quitting doesn't keep the runtime from shutting down
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(io_error)?;
+    let answered = async {
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            // a chooser that quits before reading everything closes the pipe, that's fine
+            let _ = stdin.write_all(input.as_bytes()).await;
+        }
+        child.wait_with_output().await
+    };
+    let output = match tokio::time::timeout(TIMEOUT, answered).await {
+        Ok(output) => output.map_err(io_error)?,
+        // the child was dropped along with the future, which killed it
+        Err(_) => return Err(Error::TimedOut),
+    };
+    // they all exit with 1 when cancelled
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let answer = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok(Some(answer).filter(|answer| !answer.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn answers_are_what_the_tool_printed() {
+        assert_eq!(run("echo", &[" 1234 "], None).await.unwrap(), Some("1234".into()));
+        assert_eq!(run("cat", &[], Some("se got\n")).await.unwrap(), Some("se got".into()));
+        // cancelled
+        assert_eq!(run("false", &[], None).await.unwrap(), None);
+        assert_eq!(run("true", &[], None).await.unwrap(), None);
+        let missing = run("mulltray-no-such-tool", &[], None).await;
+        let not_found = |e: &std::io::Error| e.kind() == std::io::ErrorKind::NotFound;
+        assert!(matches!(missing, Err(Error::Io(_, e)) if not_found(&e)));
+    }
+
+    #[tokio::test]
+    async fn dropped_dialogs_are_closed() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let script = format!("echo $$ > {}; exec sleep 60", pid_file.display());
+        let args = ["-c", &script];
+        let dialog = run("sh", &args, None);
+        assert!(tokio::time::timeout(Duration::from_millis(500), dialog).await.is_err());
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        let stat = format!("/proc/{}/stat", pid.trim());
+        // killed, it's either gone or waiting to be reaped
+        let closed = || std::fs::read_to_string(&stat).map_or(true, |stat| stat.contains(") Z"));
+        for _ in 0..100 {
+            if closed() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("The dialog is still open");
+    }
+}
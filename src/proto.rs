@@ -0,0 +1,12 @@
+//! Client and messages generated from the daemon's management interface definition
+#![allow(clippy::all)]
+
+tonic::include_proto!("mullvad_daemon.management_interface");
+
+impl From<GeographicLocationConstraint> for LocationConstraint {
+    fn from(geo_loc_constraint: GeographicLocationConstraint) -> Self {
+        Self {
+            r#type: Some(location_constraint::Type::Location(geo_loc_constraint)),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Reconnecting after the computer wakes up from suspend when the tunnel doesn't come back
+//! by itself. logind announces suspending and resuming on the system bus.
+
+use std::time::Duration;
+
+use dbus::blocking::Connection;
+use dbus::message::MatchRule;
+use tokio::sync::{mpsc, watch};
+use tracing::debug;
+
+use crate::app::{AppState, MulltrayApp};
+
+const INTERFACE: &str = "org.freedesktop.login1.Manager";
+// how often the listener checks whether anyone is still interested
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long after resuming the tunnel is watched, it may take a while for the network to
+/// come up again
+const WATCH_FOR: Duration = Duration::from_secs(2 * 60);
+
+/// Sends `true` before suspending and `false` after resuming until `events` is closed
+fn listen(events: mpsc::UnboundedSender<bool>) -> Result<(), dbus::Error> {
+    let conn = Connection::new_system()?;
+    let sender = events.clone();
+    // without logind nobody sends the signal, which is no different from never suspending
+    conn.add_match(
+        MatchRule::new_signal(INTERFACE, "PrepareForSleep"),
+        move |(start,): (bool,), _, _| {
+            let _ = sender.send(start);
+            true
+        },
+    )?;
+    while !events.is_closed() {
+        conn.process(POLL_INTERVAL)?;
+    }
+    Ok(())
+}
+
+/// Whether the tunnel stays down for `grace` in a row. The daemon reconnects by itself in
+/// many cases, so it gets a chance to do that first.
+async fn stays_down(state: &mut watch::Receiver<&'static str>, grace: Duration) -> bool {
+    let down = [
+        AppState::Disconnected.name(),
+        AppState::Blocked.name(),
+        AppState::Error(Default::default()).name(),
+    ];
+    loop {
+        let name = *state.borrow_and_update();
+        if name == AppState::Connected(None).name() {
+            return false;
+        }
+        if down.contains(&name) {
+            tokio::select! {
+                _ = tokio::time::sleep(grace) => return true,
+                changed = state.changed() => if changed.is_err() {
+                    return false;
+                },
+            }
+        } else if state.changed().await.is_err() {
+            return false;
+        }
+    }
+}
+
+/// Brings the tunnel back up after resuming if it was up before suspending and is still
+/// down after the grace period. Only done if the config asks for it.
+pub async fn reconnect_after_resume(
+    mut state: watch::Receiver<&'static str>,
+    tray_handle: &ksni::Handle<MulltrayApp>,
+) {
+    let (events, mut events_rx) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        if let Err(e) = listen(events) {
+            debug!("Not watching for suspend and resume: {}", e);
+        }
+    });
+    let mut was_connected = false;
+    while let Some(suspending) = events_rx.recv().await {
+        if suspending {
+            was_connected = *state.borrow() == AppState::Connected(None).name();
+            continue;
+        }
+        // read every time so that reloading the config takes effect
+        let grace = tray_handle.update(|tray: &mut MulltrayApp| {
+            let config = &tray.config;
+            config.reconnect_after_resume.then(|| Duration::from_secs(config.resume_grace_period))
+        });
+        let Some(grace) = grace.filter(|_| was_connected) else {
+            continue;
+        };
+        debug!("Resumed from suspend, watching the tunnel");
+        if tokio::time::timeout(WATCH_FOR, stays_down(&mut state, grace)).await != Ok(true) {
+            continue;
+        }
+        tray_handle.update(|tray: &mut MulltrayApp| match tray.app_state {
+            // reconnecting does nothing while disconnected
+            AppState::Disconnected | AppState::Blocked => {
+                debug!("The tunnel didn't come back after resuming, connecting");
+                tray.connect();
+            }
+            AppState::Error(_) => {
+                debug!("The tunnel didn't come back after resuming, reconnecting");
+                tray.reconnect();
+            }
+            _ => {}
+        });
+    }
+    // without the system bus there's nothing to wait for, but that's no reason to quit
+    std::future::pending().await
+}
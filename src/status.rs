@@ -0,0 +1,211 @@
+//! Tunnel state output for scripts and status bars
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::app::AppState;
+use crate::cli::OutputFormat;
+use crate::daemon::{app_state, MAX_RECONNECT_DELAY, MIN_RECONNECT_DELAY};
+use crate::error::Error;
+use crate::format::{geoip_place, state_summary};
+use crate::logging::LoggingChannel;
+use crate::model::RelayInfo;
+use crate::proto;
+use crate::proto::management_service_client::ManagementServiceClient;
+
+#[derive(Debug, Serialize)]
+pub struct Status {
+    /// The same names as the D-Bus interface uses, such as `connected`
+    pub state: &'static str,
+    pub summary: String,
+    pub hostname: Option<String>,
+    pub city: Option<String>,
+    pub country: Option<String>,
+    pub ipv4: Option<String>,
+    pub ipv6: Option<String>,
+    #[serde(skip)]
+    place: Option<String>,
+}
+
+impl Status {
+    pub fn new(app_state: &AppState) -> Self {
+        let relay_info = app_state.relay_info();
+        let location = relay_info.and_then(|relay_info| relay_info.location.as_ref());
+        Status {
+            state: app_state.name(),
+            summary: state_summary(app_state),
+            hostname: relay_info.and_then(RelayInfo::hostname).cloned(),
+            city: location.and_then(|location| location.city.clone()),
+            country: location.and_then(|location| location.country.clone()),
+            ipv4: location.and_then(|location| location.ipv4.clone()),
+            ipv6: location.and_then(|location| location.ipv6.clone()),
+            place: location.and_then(geoip_place),
+        }
+    }
+
+    /// e.g. "connected to se-got-wg-001 (Gothenburg, Sweden)"
+    pub fn text(&self) -> String {
+        match &self.place {
+            Some(place) => format!("{} ({})", self.summary, place),
+            None => self.summary.clone(),
+        }
+    }
+
+    pub fn json(&self) -> String {
+        // there is nothing in here that can't be serialized
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Prints the current tunnel state on one line
+pub async fn print_status(
+    mut client: ManagementServiceClient<LoggingChannel>,
+    json: bool,
+) -> Result<(), Error> {
+    let app_state = app_state(client.get_tunnel_state(()).await?.into_inner());
+    let status = Status::new(&app_state);
+    println!("{}", if json { status.json() } else { status.text() });
+    Ok(())
+}
+
+/// Writes a line for every state that differs from the previous one
+struct Printer {
+    format: OutputFormat,
+    last_line: Option<String>,
+}
+
+impl Printer {
+    fn print(&mut self, app_state: &AppState) -> io::Result<()> {
+        let status = Status::new(app_state);
+        let line = match self.format {
+            OutputFormat::Text => status.text(),
+            OutputFormat::Json => status.json(),
+        };
+        if self.last_line.as_ref() == Some(&line) {
+            return Ok(());
+        }
+        // bars read line by line so every line has to be flushed right away
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "{line}")?;
+        stdout.flush()?;
+        self.last_line = Some(line);
+        Ok(())
+    }
+}
+
+/// Prints tunnel states until the connection to the daemon is lost. Returns whether it got
+/// as far as listening for events, errors are from writing to stdout.
+async fn follow(
+    client: &mut ManagementServiceClient<LoggingChannel>,
+    printer: &mut Printer,
+) -> io::Result<bool> {
+    let subscribed = async {
+        let stream = client.events_listen(()).await?.into_inner();
+        let tunnel_state = client.get_tunnel_state(()).await?.into_inner();
+        Ok::<_, tonic::Status>((stream, tunnel_state))
+    };
+    let (mut stream, tunnel_state) = match subscribed.await {
+        Ok(subscribed) => subscribed,
+        Err(e) => {
+            warn!("Could not reach the daemon: {}", e.message());
+            return Ok(false);
+        }
+    };
+    printer.print(&app_state(tunnel_state))?;
+    loop {
+        match stream.message().await {
+            Ok(Some(proto::DaemonEvent {
+                event: Some(proto::daemon_event::Event::TunnelState(tunnel_state)),
+            })) => printer.print(&app_state(tunnel_state))?,
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                warn!("Daemon closed the event stream");
+                return Ok(true);
+            }
+            Err(e) => {
+                warn!("Lost connection to the daemon: {}", e.message());
+                return Ok(true);
+            }
+        }
+    }
+}
+
+/// Prints a line whenever the tunnel state changes, reconnecting to the daemon like the tray
+/// does. Returns when whoever reads the output goes away.
+pub async fn watch(
+    mut client: ManagementServiceClient<LoggingChannel>,
+    format: OutputFormat,
+) -> io::Result<()> {
+    let mut printer = Printer { format, last_line: None };
+    let mut reconnect_delay = MIN_RECONNECT_DELAY;
+    loop {
+        let printed = follow(&mut client, &mut printer).await.and_then(|listened| {
+            if listened {
+                reconnect_delay = MIN_RECONNECT_DELAY;
+            }
+            printer.print(&AppState::DaemonUnavailable)
+        });
+        match printed {
+            Ok(()) => {}
+            // Rust ignores SIGPIPE, so a bar that quits shows up as a broken pipe instead
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        tokio::time::sleep(reconnect_delay).await;
+        reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{connected, tunnel_state};
+
+    #[test]
+    fn connected_status_has_the_relay_and_location() {
+        let mut state = connected("se-got-wg-001");
+        if let proto::tunnel_state::State::Connected(connected) = &mut state {
+            let location = connected.relay_info.as_mut().unwrap().location.as_mut().unwrap();
+            location.ipv4 = Some("185.213.154.68".into());
+            location.ipv6 = Some("2a03:1b20:5:f011::a09f".into());
+        }
+        let status = Status::new(&app_state(tunnel_state(state)));
+
+        let json: serde_json::Value = serde_json::from_str(&status.json()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "state": "connected",
+                "summary": "connected to se-got-wg-001",
+                "hostname": "se-got-wg-001",
+                "city": "Gothenburg",
+                "country": "Sweden",
+                "ipv4": "185.213.154.68",
+                "ipv6": "2a03:1b20:5:f011::a09f",
+            })
+        );
+        assert_eq!(status.text(), "connected to se-got-wg-001 (Gothenburg, Sweden)");
+    }
+
+    #[test]
+    fn unavailable_daemon_has_no_location() {
+        let status = Status::new(&AppState::DaemonUnavailable);
+
+        let json: serde_json::Value = serde_json::from_str(&status.json()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "state": "daemon-unavailable",
+                "summary": "waiting for daemon..",
+                "hostname": null,
+                "city": null,
+                "country": null,
+                "ipv4": null,
+                "ipv6": null,
+            })
+        );
+        assert_eq!(status.text(), "waiting for daemon..");
+    }
+}
@@ -0,0 +1,84 @@
+//! Telling systemd about mulltray when it runs as a `Type=notify` user service, see
+//! <https://www.freedesktop.org/software/systemd/man/latest/sd_notify.html>
+//!
+//! Everything here does nothing unless systemd set `NOTIFY_SOCKET`.
+
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+use crate::app::AppState;
+
+/// Sends a state such as `READY=1` to the service manager
+pub fn notify(state: &str) {
+    let Some(socket_path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        // a leading @ means a socket in the abstract namespace
+        match socket_path.as_encoded_bytes().strip_prefix(b"@") {
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                socket.send_to_addr(state.as_bytes(), &addr)
+            }
+            None => socket.send_to(state.as_bytes(), &socket_path),
+        }
+    });
+    if let Err(e) = result {
+        warn!("Could not notify systemd: {}", e);
+    }
+}
+
+/// How often systemd wants to hear from us, `None` if there's no watchdog
+fn watchdog_interval() -> Option<Duration> {
+    // the watchdog may be meant for another process of the service
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec)).filter(|interval| !interval.is_zero())
+}
+
+/// Pings the watchdog for as long as it's polled, so when the event loop in `main` gets
+/// stuck systemd notices. Never returns.
+pub async fn keep_watchdog_happy() {
+    let Some(interval) = watchdog_interval() else {
+        return std::future::pending().await;
+    };
+    if std::env::var_os("NOTIFY_SOCKET").is_none() {
+        return std::future::pending().await;
+    }
+    debug!(?interval, "Pinging the systemd watchdog");
+    // as sd_watchdog_enabled recommends, with room for a late wakeup
+    let mut ticks = tokio::time::interval(interval / 2);
+    loop {
+        ticks.tick().await;
+        notify("WATCHDOG=1");
+    }
+}
+
+/// Reports readiness once the daemon has been reached, and then every state the tunnel goes
+/// through as the status of the service. Never returns.
+pub async fn report_state(mut state_names: watch::Receiver<&'static str>) {
+    if std::env::var_os("NOTIFY_SOCKET").is_none() {
+        return std::future::pending().await;
+    }
+    let mut ready = false;
+    loop {
+        let name = *state_names.borrow_and_update();
+        if !ready && name != AppState::DaemonUnavailable.name() {
+            notify(&format!("READY=1\nSTATUS={name}"));
+            ready = true;
+        } else {
+            notify(&format!("STATUS={name}"));
+        }
+        if state_names.changed().await.is_err() {
+            return std::future::pending().await;
+        }
+    }
+}
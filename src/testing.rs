@@ -0,0 +1,569 @@
+//! A mock daemon for the tests: a `ManagementService` on a temporary unix socket that answers
+//! from state the test sets up, and a tray connected to it like `main` connects the real one
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::{Duration, SystemTime};
+
+use ksni::MenuItem;
+use tokio::net::UnixListener;
+use tokio::sync::{mpsc, oneshot, watch, Notify};
+use tokio_stream::wrappers::{UnboundedReceiverStream, UnixListenerStream};
+use tokio_util::task::TaskTracker;
+use tonic::{Request, Response, Status};
+
+use crate::app::{
+    apply_tray_updates, AccountExpiry, AppState, Connection, LocationsStatus, MulltrayApp,
+};
+use crate::capabilities::DaemonCapabilities;
+use crate::commands::{Command, CommandRunner};
+use crate::config::Config;
+use crate::daemon::daemon_channel;
+use crate::favorites::Favorites;
+use crate::latency::Latencies;
+use crate::launch::Launchers;
+use crate::logging::LoggingChannel;
+use crate::menu::LocationsCache;
+use crate::model::{RelayList, Settings};
+use crate::names::CountryNames;
+use crate::notify::Notifier;
+use crate::proto;
+use crate::proto::management_service_client::ManagementServiceClient;
+use crate::proto::management_service_server::{ManagementService, ManagementServiceServer};
+
+/// How long a test waits for the tray to get into the state it expects
+const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the mock daemon answers with, and what it was asked to do
+#[derive(Debug)]
+pub struct MockState {
+    pub tunnel_state: proto::TunnelState,
+    pub settings: proto::Settings,
+    pub relay_list: proto::RelayList,
+    pub device_state: proto::DeviceState,
+    pub version: String,
+    /// The names of the calls that change something, in the order they came, including
+    /// the ones that failed
+    pub calls: Vec<&'static str>,
+    /// Calls that fail with `Unimplemented`, as they do on a daemon built without them
+    pub unimplemented: Vec<&'static str>,
+    /// How long calls take to be answered, for the ones that shouldn't be answered right away
+    pub delays: HashMap<&'static str, Duration>,
+    events: Vec<mpsc::UnboundedSender<Result<proto::DaemonEvent, Status>>>,
+}
+
+impl Default for MockState {
+    fn default() -> Self {
+        let mut device_state = proto::DeviceState::default();
+        device_state.set_state(proto::device_state::State::LoggedOut);
+        Self {
+            tunnel_state: tunnel_state(disconnected()),
+            settings: settings(),
+            relay_list: proto::RelayList::default(),
+            device_state,
+            version: "2024.8".into(),
+            calls: vec![],
+            unimplemented: vec![],
+            delays: HashMap::new(),
+            events: vec![],
+        }
+    }
+}
+
+pub fn tunnel_state(state: proto::tunnel_state::State) -> proto::TunnelState {
+    proto::TunnelState { state: Some(state) }
+}
+
+pub fn disconnected() -> proto::tunnel_state::State {
+    proto::tunnel_state::State::Disconnected(Default::default())
+}
+
+/// Connected to `hostname`
+pub fn connected(hostname: &str) -> proto::tunnel_state::State {
+    let relay_info = proto::TunnelStateRelayInfo {
+        location: Some(proto::GeoIpLocation {
+            hostname: Some(hostname.into()),
+            country: "Sweden".into(),
+            city: Some("Gothenburg".into()),
+            ..Default::default()
+        }),
+        tunnel_endpoint: None,
+    };
+    proto::tunnel_state::State::Connected(proto::tunnel_state::Connected {
+        relay_info: Some(relay_info),
+    })
+}
+
+/// Settings with normal relay settings, which is what the daemon has unless it's been set up
+/// with a custom tunnel endpoint
+pub fn settings() -> proto::Settings {
+    proto::Settings {
+        relay_settings: Some(proto::RelaySettings {
+            endpoint: Some(proto::relay_settings::Endpoint::Normal(Default::default())),
+        }),
+        ..Default::default()
+    }
+}
+
+/// A relay list with a WireGuard relay in each of `hostnames`, which are `country-city-…`
+pub fn relay_list(hostnames: &[&str]) -> proto::RelayList {
+    let mut relay_list = proto::RelayList::default();
+    for hostname in hostnames {
+        let mut parts = hostname.split('-');
+        let (country_code, city_code) = (parts.next().unwrap(), parts.next().unwrap());
+        let country = match relay_list.countries.iter().position(|c| c.code == country_code) {
+            Some(i) => &mut relay_list.countries[i],
+            None => {
+                relay_list.countries.push(proto::RelayListCountry {
+                    name: country_code.to_uppercase(),
+                    code: country_code.into(),
+                    cities: vec![],
+                });
+                relay_list.countries.last_mut().unwrap()
+            }
+        };
+        let city = match country.cities.iter().position(|c| c.code == city_code) {
+            Some(i) => &mut country.cities[i],
+            None => {
+                country.cities.push(proto::RelayListCity {
+                    name: city_code.to_uppercase(),
+                    code: city_code.into(),
+                    ..Default::default()
+                });
+                country.cities.last_mut().unwrap()
+            }
+        };
+        let mut relay = proto::Relay {
+            hostname: hostname.to_string(),
+            ipv4_addr_in: "10.0.0.1".into(),
+            active: true,
+            owned: true,
+            provider: "Mullvad".into(),
+            ..Default::default()
+        };
+        relay.set_endpoint_type(proto::relay::RelayType::Wireguard);
+        city.relays.push(relay);
+    }
+    relay_list
+}
+
+/// The state of a mock daemon, which stays the same when it's restarted
+#[derive(Debug, Clone, Default)]
+pub struct MockDaemon {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockDaemon {
+    pub fn state(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Sends an event to everyone listening, like the daemon does when something changes
+    pub fn send_event(&self, event: proto::daemon_event::Event) {
+        let event = proto::DaemonEvent { event: Some(event) };
+        self.state().events.retain(|events| events.send(Ok(event.clone())).is_ok());
+    }
+
+    pub fn calls(&self) -> Vec<&'static str> {
+        self.state().calls.clone()
+    }
+
+    async fn answer(&self, call: &'static str) -> Result<(), Status> {
+        let delay = {
+            let mut state = self.state();
+            state.calls.push(call);
+            if state.unimplemented.contains(&call) {
+                return Err(Status::unimplemented(call));
+            }
+            state.delays.get(call).copied()
+        };
+        if let Some(delay) = delay {
+            tokio::time::sleep(delay).await;
+        }
+        Ok(())
+    }
+
+    /// Answers a call that changes the settings and tells the listeners, like the daemon does
+    async fn change_settings(
+        &self,
+        call: &'static str,
+        f: impl FnOnce(&mut proto::Settings),
+    ) -> Result<Response<()>, Status> {
+        self.answer(call).await?;
+        let settings = {
+            let mut state = self.state();
+            f(&mut state.settings);
+            state.settings.clone()
+        };
+        self.send_event(proto::daemon_event::Event::Settings(settings));
+        Ok(Response::new(()))
+    }
+
+    async fn tunnel_command(&self, call: &'static str) -> Result<Response<bool>, Status> {
+        self.answer(call).await?;
+        Ok(Response::new(true))
+    }
+
+    /// Starts answering on `socket_path` until the returned server is stopped
+    pub fn serve(&self, socket_path: &Path) -> MockServer {
+        let listener = UnixListener::bind(socket_path).expect("Could not bind the mock socket");
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server = tonic::transport::Server::builder()
+            .add_service(ManagementServiceServer::new(self.clone()))
+            .serve_with_incoming_shutdown(UnixListenerStream::new(listener), async {
+                let _ = shutdown_rx.await;
+            });
+        MockServer {
+            daemon: self.clone(),
+            socket_path: socket_path.to_path_buf(),
+            shutdown: Some(shutdown),
+            server: tokio::spawn(server),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl ManagementService for MockDaemon {
+    async fn connect_tunnel(&self, _: Request<()>) -> Result<Response<bool>, Status> {
+        self.tunnel_command("connect_tunnel").await
+    }
+
+    async fn disconnect_tunnel(&self, _: Request<()>) -> Result<Response<bool>, Status> {
+        self.tunnel_command("disconnect_tunnel").await
+    }
+
+    async fn reconnect_tunnel(&self, _: Request<()>) -> Result<Response<bool>, Status> {
+        self.tunnel_command("reconnect_tunnel").await
+    }
+
+    async fn get_tunnel_state(
+        &self,
+        _: Request<()>,
+    ) -> Result<Response<proto::TunnelState>, Status> {
+        Ok(Response::new(self.state().tunnel_state.clone()))
+    }
+
+    async fn events_listen(
+        &self,
+        _: Request<()>,
+    ) -> Result<Response<tonic::codegen::BoxStream<proto::DaemonEvent>>, Status> {
+        let (events, events_rx) = mpsc::unbounded_channel();
+        self.state().events.push(events);
+        Ok(Response::new(Box::pin(UnboundedReceiverStream::new(events_rx))))
+    }
+
+    async fn get_current_version(&self, _: Request<()>) -> Result<Response<String>, Status> {
+        Ok(Response::new(self.state().version.clone()))
+    }
+
+    async fn get_relay_locations(
+        &self,
+        _: Request<()>,
+    ) -> Result<Response<proto::RelayList>, Status> {
+        Ok(Response::new(self.state().relay_list.clone()))
+    }
+
+    async fn get_settings(&self, _: Request<()>) -> Result<Response<proto::Settings>, Status> {
+        Ok(Response::new(self.state().settings.clone()))
+    }
+
+    async fn get_device(&self, _: Request<()>) -> Result<Response<proto::DeviceState>, Status> {
+        Ok(Response::new(self.state().device_state.clone()))
+    }
+
+    async fn get_account_data(
+        &self,
+        _: Request<String>,
+    ) -> Result<Response<proto::AccountData>, Status> {
+        // it's only a read, but the daemon has to ask the Mullvad API which can be slow
+        let delay = self.state().delays.get("get_account_data").copied();
+        if let Some(delay) = delay {
+            tokio::time::sleep(delay).await;
+        }
+        let expiry = SystemTime::now() + Duration::from_secs(30 * 24 * 60 * 60);
+        Ok(Response::new(proto::AccountData { id: "account".into(), expiry: Some(expiry.into()) }))
+    }
+
+    async fn get_wireguard_key(
+        &self,
+        _: Request<()>,
+    ) -> Result<Response<proto::PublicKey>, Status> {
+        Err(Status::not_found("no key while logged out"))
+    }
+
+    async fn set_relay_settings(
+        &self,
+        request: Request<proto::RelaySettings>,
+    ) -> Result<Response<()>, Status> {
+        let relay_settings = request.into_inner();
+        self.change_settings("set_relay_settings", |settings| {
+            settings.relay_settings = Some(relay_settings)
+        })
+        .await
+    }
+
+    async fn set_allow_lan(&self, request: Request<bool>) -> Result<Response<()>, Status> {
+        let enabled = request.into_inner();
+        self.change_settings("set_allow_lan", |settings| settings.allow_lan = enabled).await
+    }
+
+    async fn set_daita_settings(
+        &self,
+        request: Request<proto::DaitaSettings>,
+    ) -> Result<Response<()>, Status> {
+        let daita = request.into_inner();
+        self.change_settings("set_daita_settings", |settings| {
+            let tunnel_options = settings.tunnel_options.get_or_insert_with(Default::default);
+            tunnel_options.wireguard.get_or_insert_with(Default::default).daita = Some(daita);
+        })
+        .await
+    }
+
+    async fn set_obfuscation_settings(
+        &self,
+        request: Request<proto::ObfuscationSettings>,
+    ) -> Result<Response<()>, Status> {
+        let obfuscation_settings = request.into_inner();
+        self.change_settings("set_obfuscation_settings", |settings| {
+            settings.obfuscation_settings = Some(obfuscation_settings)
+        })
+        .await
+    }
+
+    async fn set_split_tunnel_state(&self, request: Request<bool>) -> Result<Response<()>, Status> {
+        let enabled = request.into_inner();
+        self.change_settings("set_split_tunnel_state", |settings| {
+            settings.split_tunnel.get_or_insert_with(Default::default).enable_exclusions = enabled
+        })
+        .await
+    }
+
+    async fn add_split_tunnel_app(&self, request: Request<String>) -> Result<Response<()>, Status> {
+        let path = request.into_inner();
+        self.change_settings("add_split_tunnel_app", |settings| {
+            settings.split_tunnel.get_or_insert_with(Default::default).apps.push(path)
+        })
+        .await
+    }
+
+    async fn remove_split_tunnel_app(
+        &self,
+        request: Request<String>,
+    ) -> Result<Response<()>, Status> {
+        let path = request.into_inner();
+        self.change_settings("remove_split_tunnel_app", |settings| {
+            settings.split_tunnel.get_or_insert_with(Default::default).apps.retain(|a| *a != path)
+        })
+        .await
+    }
+}
+
+/// A running mock daemon
+pub struct MockServer {
+    pub daemon: MockDaemon,
+    pub socket_path: PathBuf,
+    shutdown: Option<oneshot::Sender<()>>,
+    server: tokio::task::JoinHandle<Result<(), tonic::transport::Error>>,
+}
+
+impl MockServer {
+    /// Stops answering, which ends the event streams like the daemon going away does
+    pub async fn stop(mut self) {
+        self.daemon.state().events.clear();
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        let _ = (&mut self.server).await;
+        let _ = std::fs::remove_file(&self.socket_path);
+    }
+}
+
+/// Points the config and state directories at a temporary directory, so that the tests don't
+/// read the config or overwrite the remembered location of whoever runs them
+fn isolate_from_home() {
+    static HOME: OnceLock<tempfile::TempDir> = OnceLock::new();
+    HOME.get_or_init(|| {
+        let home = tempfile::tempdir().expect("Could not create a temporary home");
+        std::env::set_var("XDG_CONFIG_HOME", home.path().join("config"));
+        std::env::set_var("XDG_STATE_HOME", home.path().join("state"));
+        home
+    });
+}
+
+/// A mock daemon on a socket of its own and a tray that talks to it
+pub struct TestTray {
+    pub handle: ksni::Handle<MulltrayApp>,
+    pub client: ManagementServiceClient<LoggingChannel>,
+    pub socket_path: PathBuf,
+    _service: ksni::TrayService<MulltrayApp>,
+    _dir: tempfile::TempDir,
+}
+
+impl TestTray {
+    /// The tray isn't shown anywhere, updates to it are only applied to the state
+    pub fn new(config: Config) -> Self {
+        isolate_from_home();
+        let dir = tempfile::tempdir().expect("Could not create a directory for the socket");
+        let socket_path = dir.path().join("mullvad-vpn");
+        let channel = daemon_channel(socket_path.clone()).expect("Could not create the channel");
+        let client = ManagementServiceClient::new(LoggingChannel::new(channel));
+        let tokio_handle = tokio::runtime::Handle::current();
+        let (tray_updates, tray_updates_rx) = mpsc::unbounded_channel();
+        let (commands, commands_rx) = mpsc::unbounded_channel();
+        let tasks = TaskTracker::new();
+        let notifier = Notifier::new(tokio_handle.clone());
+        let (state_names, state_names_rx) = watch::channel(AppState::DaemonUnavailable.name());
+        let app = MulltrayApp {
+            locations: RelayList::default(),
+            locations_status: LocationsStatus::Loading,
+            settings: Settings::default(),
+            app_state: AppState::DaemonUnavailable,
+            notifier: notifier.clone(),
+            notifications_enabled: false,
+            measure_latency: false,
+            tokio_handle,
+            quit: Arc::new(Notify::new()),
+            tray_updates,
+            commands,
+            tasks: tasks.clone(),
+            config,
+            favorites: Favorites::default(),
+            last_location: None,
+            account_expiry: AccountExpiry::Unknown,
+            device_state: None,
+            wireguard_key_created: None,
+            version_info: None,
+            latencies: Latencies::default(),
+            command_failure: None,
+            last_used: HashMap::new(),
+            locations_version: 0,
+            metrics_version: 0,
+            locations_cache: LocationsCache::default(),
+            relays_to_measure: watch::channel(vec![]).0,
+            connected_since: None,
+            disconnect_requested: false,
+            disconnected_unexpectedly: false,
+            connection: watch::channel(Connection::Down).0,
+            state_names,
+            scrolled: None,
+            session_usage: None,
+            measuring_fastest: false,
+            connect_on_start: false,
+            daemon_version: None,
+            socket_path: socket_path.clone(),
+            last_error: None,
+            capabilities: DaemonCapabilities::default(),
+            split_tunnel_supported: false,
+            api_access_method: None,
+            launchers: Launchers { mullvad_app: None, xdg_open: false },
+            country_names: CountryNames::default(),
+            show_inactive_relays: false,
+        };
+        let service = ksni::TrayService::new(app);
+        let handle = service.handle();
+        let runner = CommandRunner::new(
+            client.clone(),
+            handle.clone(),
+            state_names_rx,
+            notifier,
+            tasks.clone(),
+        );
+        tasks.spawn(runner.run(commands_rx, Arc::new(Notify::new())));
+        let apply_handle = handle.clone();
+        tokio::spawn(async move { apply_tray_updates(tray_updates_rx, &apply_handle).await });
+        Self {
+            handle,
+            client,
+            socket_path,
+            _service: service,
+            _dir: dir,
+        }
+    }
+
+    /// Starts a mock daemon on the socket of this tray
+    pub fn serve(&self, daemon: &MockDaemon) -> MockServer {
+        daemon.serve(&self.socket_path)
+    }
+
+    /// Keeps the tray in sync with the daemon in the background, like `main` does
+    pub fn sync_with_daemon(&self) {
+        let (client, handle) = (self.client.clone(), self.handle.clone());
+        tokio::spawn(async move { crate::daemon::stay_in_sync_with_daemon(client, &handle).await });
+    }
+
+    /// Sends a command to the runner like the tray callbacks do
+    pub fn send(&self, command: Command) {
+        self.read(|tray| tray.commands.send(command)).expect("The command runner is gone");
+    }
+
+    /// Activates the menu item at `path` like clicking it does, panicking if there is none
+    pub fn click(&self, path: &[&str]) {
+        self.handle.update(|tray: &mut MulltrayApp| {
+            let menu = ksni::Tray::menu(tray);
+            match find_item(&menu, path) {
+                Some(MenuItem::Standard(item)) => (item.activate)(tray),
+                Some(MenuItem::Checkmark(item)) => (item.activate)(tray),
+                _ => panic!("No item to click at {path:?}"),
+            }
+        });
+    }
+
+    pub fn read<T>(&self, f: impl FnOnce(&MulltrayApp) -> T) -> T {
+        self.handle.update(|tray: &mut MulltrayApp| f(tray))
+    }
+
+    /// Waits until `f` is true of the tray, panicking with `what` if it takes too long
+    pub async fn wait_for(&self, what: &str, f: impl Fn(&MulltrayApp) -> bool) {
+        let wait = async {
+            while !self.read(&f) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        if tokio::time::timeout(WAIT_TIMEOUT, wait).await.is_err() {
+            panic!("Timed out waiting for {what}");
+        }
+    }
+}
+
+/// The label of a menu item, empty for separators and radio groups
+pub fn label<T>(item: &MenuItem<T>) -> &str {
+    match item {
+        MenuItem::Standard(item) => &item.label,
+        MenuItem::Checkmark(item) => &item.label,
+        MenuItem::SubMenu(item) => &item.label,
+        MenuItem::Separator | MenuItem::RadioGroup(_) => "",
+    }
+}
+
+pub fn is_visible<T>(item: &MenuItem<T>) -> bool {
+    match item {
+        MenuItem::Standard(item) => item.visible,
+        MenuItem::Checkmark(item) => item.visible,
+        MenuItem::SubMenu(item) => item.visible,
+        MenuItem::Separator | MenuItem::RadioGroup(_) => true,
+    }
+}
+
+/// The menu item with the last label of `path`, in the submenus with the labels before it
+pub fn find_item<'a, T>(menu: &'a [MenuItem<T>], path: &[&str]) -> Option<&'a MenuItem<T>> {
+    let (first, rest) = path.split_first()?;
+    let item = menu.iter().find(|item| label(item) == *first)?;
+    match (item, rest.is_empty()) {
+        (_, true) => Some(item),
+        (MenuItem::SubMenu(submenu), false) => find_item(&submenu.submenu, rest),
+        _ => None,
+    }
+}
+
+/// The labels of the visible items of the submenu at `path`
+pub fn submenu_labels(menu: &[MenuItem<MulltrayApp>], path: &[&str]) -> Vec<String> {
+    let Some(MenuItem::SubMenu(submenu)) = find_item(menu, path) else {
+        panic!("No submenu at {path:?}");
+    };
+    submenu.submenu
+        .iter()
+        .filter(|item| is_visible(item))
+        .map(|item| label(item).to_string())
+        .collect()
+}
@@ -0,0 +1,97 @@
+//! Data transferred through the tunnel, from the counters of its network interface
+
+use std::ops::Add;
+use std::path::Path;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counters {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+impl Add for Counters {
+    type Output = Counters;
+
+    fn add(self, other: Counters) -> Counters {
+        Counters {
+            rx_bytes: self.rx_bytes.saturating_add(other.rx_bytes),
+            tx_bytes: self.tx_bytes.saturating_add(other.tx_bytes),
+        }
+    }
+}
+
+/// What has been transferred since connecting
+#[derive(Debug, Default)]
+pub struct SessionUsage {
+    /// Counted by earlier incarnations of the interface
+    earlier: Counters,
+    latest: Counters,
+}
+
+impl SessionUsage {
+    pub fn record(&mut self, counters: Counters) {
+        // the interface is created again on reconnects and its counters start over
+        if counters.rx_bytes < self.latest.rx_bytes || counters.tx_bytes < self.latest.tx_bytes {
+            self.earlier = self.earlier + self.latest;
+        }
+        self.latest = counters;
+    }
+
+    pub fn total(&self) -> Counters {
+        self.earlier + self.latest
+    }
+}
+
+/// The counters of a network interface, `None` if there is no such interface (anymore).
+/// These are in sysfs so reading them doesn't block.
+pub fn read_counters(interface: &str) -> Option<Counters> {
+    let statistics = Path::new("/sys/class/net").join(interface).join("statistics");
+    let read = |name: &str| -> Option<u64> {
+        std::fs::read_to_string(statistics.join(name)).ok()?.trim().parse().ok()
+    };
+    Some(Counters {
+        rx_bytes: read("rx_bytes")?,
+        tx_bytes: read("tx_bytes")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counters(rx_bytes: u64, tx_bytes: u64) -> Counters {
+        Counters { rx_bytes, tx_bytes }
+    }
+
+    #[test]
+    fn usage_carries_on_across_a_recreated_interface() {
+        let mut usage = SessionUsage::default();
+        assert_eq!(usage.total(), counters(0, 0));
+        usage.record(counters(100, 10));
+        usage.record(counters(300, 30));
+        assert_eq!(usage.total(), counters(300, 30));
+        // the interface came back after a reconnect with its counters at zero
+        usage.record(counters(5, 1));
+        assert_eq!(usage.total(), counters(305, 31));
+        usage.record(counters(50, 40));
+        assert_eq!(usage.total(), counters(350, 70));
+        // a drop in only one of the counters is a new interface too
+        usage.record(counters(60, 2));
+        assert_eq!(usage.total(), counters(410, 72));
+    }
+
+    #[test]
+    fn totals_saturate() {
+        let mut usage = SessionUsage::default();
+        usage.record(counters(u64::MAX, 1));
+        usage.record(counters(1, 0));
+        assert_eq!(usage.total(), counters(u64::MAX, 1));
+    }
+
+    #[test]
+    fn missing_interfaces_have_no_counters() {
+        assert_eq!(read_counters("mulltray-test-no-such-interface"), None);
+        // every Linux system has a loopback interface
+        assert!(read_counters("lo").is_some());
+    }
+}